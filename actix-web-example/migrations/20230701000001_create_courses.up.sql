@@ -0,0 +1,15 @@
+-- courses as created by the first releases, kept so existing databases are adopted as-is
+CREATE TABLE IF NOT EXISTS courses (
+    id TEXT,
+    teacher_id INTEGER,
+    name TEXT,
+    time TEXT,
+    description TEXT,
+    format TEXT,
+    structure TEXT,
+    duration TEXT,
+    price DOUBLE,
+    language TEXT,
+    level TEXT,
+    version INTEGER NOT NULL DEFAULT 1
+);
@@ -0,0 +1,22 @@
+CREATE TABLE courses_old (
+    id TEXT,
+    teacher_id INTEGER,
+    name TEXT,
+    time TEXT,
+    description TEXT,
+    format TEXT,
+    structure TEXT,
+    duration TEXT,
+    price DOUBLE,
+    language TEXT,
+    level TEXT,
+    version INTEGER NOT NULL DEFAULT 1
+);
+
+INSERT INTO courses_old
+    (id, teacher_id, name, time, description, format, structure, duration, price, language, level, version)
+SELECT id, teacher_id, name, time, description, format, structure, duration, price, language, level, version
+FROM courses;
+
+DROP TABLE courses;
+ALTER TABLE courses_old RENAME TO courses;
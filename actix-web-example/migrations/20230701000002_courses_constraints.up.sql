@@ -0,0 +1,34 @@
+-- SQLite cannot add a primary key to an existing table, rebuild it instead.
+-- Rows without an id are dropped, duplicated ids keep the first row.
+CREATE TABLE courses_new (
+    id TEXT NOT NULL PRIMARY KEY,
+    teacher_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    description TEXT,
+    format TEXT,
+    structure TEXT,
+    duration TEXT,
+    price DOUBLE,
+    language TEXT,
+    level TEXT,
+    version INTEGER NOT NULL DEFAULT 1,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT OR IGNORE INTO courses_new
+    (id, teacher_id, name, time, description, format, structure, duration, price, language, level, version, created_at, updated_at)
+SELECT id,
+       COALESCE(teacher_id, 0),
+       COALESCE(name, ''),
+       COALESCE(time, CURRENT_TIMESTAMP),
+       description, format, structure, duration, price, language, level,
+       version,
+       COALESCE(time, CURRENT_TIMESTAMP),
+       COALESCE(time, CURRENT_TIMESTAMP)
+FROM courses
+WHERE id IS NOT NULL;
+
+DROP TABLE courses;
+ALTER TABLE courses_new RENAME TO courses;
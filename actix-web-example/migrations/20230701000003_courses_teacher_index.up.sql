@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_courses_teacher_id ON courses (teacher_id);
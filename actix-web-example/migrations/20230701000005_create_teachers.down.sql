@@ -0,0 +1,26 @@
+CREATE TABLE courses_old (
+    id TEXT NOT NULL PRIMARY KEY,
+    teacher_id INTEGER NOT NULL,
+    name TEXT NOT NULL,
+    time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    description TEXT,
+    format TEXT,
+    structure TEXT,
+    duration TEXT,
+    price DOUBLE,
+    language TEXT,
+    level TEXT,
+    version INTEGER NOT NULL DEFAULT 1,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO courses_old
+    (id, teacher_id, name, time, description, format, structure, duration, price, language, level, version, created_at, updated_at)
+SELECT id, teacher_id, name, time, description, format, structure, duration, price, language, level, version, created_at, updated_at
+FROM courses;
+
+DROP TABLE courses;
+ALTER TABLE courses_old RENAME TO courses;
+CREATE INDEX IF NOT EXISTS idx_courses_teacher_id ON courses (teacher_id);
+DROP TABLE IF EXISTS teachers;
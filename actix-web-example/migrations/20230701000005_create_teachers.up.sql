@@ -0,0 +1,40 @@
+CREATE TABLE IF NOT EXISTS teachers (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    email TEXT,
+    bio TEXT,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- teachers courses already point to, named after their id until someone renames them
+INSERT OR IGNORE INTO teachers (id, name)
+SELECT DISTINCT teacher_id, 'teacher ' || teacher_id FROM courses;
+
+-- SQLite cannot add a foreign key to an existing table, rebuild it instead.
+-- Deleting a teacher with courses fails, the application deletes the courses first on cascade.
+CREATE TABLE courses_new (
+    id TEXT NOT NULL PRIMARY KEY,
+    teacher_id INTEGER NOT NULL REFERENCES teachers (id),
+    name TEXT NOT NULL,
+    time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    description TEXT,
+    format TEXT,
+    structure TEXT,
+    duration TEXT,
+    price DOUBLE,
+    language TEXT,
+    level TEXT,
+    version INTEGER NOT NULL DEFAULT 1,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+INSERT INTO courses_new
+    (id, teacher_id, name, time, description, format, structure, duration, price, language, level, version, created_at, updated_at)
+SELECT id, teacher_id, name, time, description, format, structure, duration, price, language, level, version, created_at, updated_at
+FROM courses;
+
+DROP TABLE courses;
+ALTER TABLE courses_new RENAME TO courses;
+CREATE INDEX IF NOT EXISTS idx_courses_teacher_id ON courses (teacher_id);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS enrollments;
+ALTER TABLE courses DROP COLUMN capacity;
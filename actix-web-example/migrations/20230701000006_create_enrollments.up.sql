@@ -0,0 +1,16 @@
+-- seats of a course, NULL for no limit
+ALTER TABLE courses ADD COLUMN capacity INTEGER CHECK (capacity IS NULL OR capacity > 0);
+
+CREATE TABLE IF NOT EXISTS enrollments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    course_id TEXT NOT NULL REFERENCES courses (id) ON DELETE CASCADE,
+    user_id TEXT NOT NULL,
+    -- enrolled or waitlisted, the waitlist is promoted in id order
+    status TEXT NOT NULL CHECK (status IN ('enrolled', 'waitlisted')),
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE (course_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_enrollments_course_status ON enrollments (course_id, status);
+CREATE INDEX IF NOT EXISTS idx_enrollments_user_id ON enrollments (user_id);
@@ -1,26 +1,46 @@
-use std::env;
-use std::fs::File;
-use std::io::prelude::*;
-use std::path::Path;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::time::Duration;
 // use lazy_static::lazy::Lazy;
 
+use actix_web::http::{header::HeaderName, Method};
+use arc_swap::ArcSwap;
+use conf_rs::ConfError;
+use log::LevelFilter;
 use serde::Deserialize;
-use toml;
-use validator::{Validate, ValidationError};
-use once_cell::sync::Lazy;
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
 // use sqlite::Connection;
-use sqlx::{Connection, Executor, Pool, Sqlite, sqlite::SqlitePoolOptions, SqliteConnection};
+use sqlx::{Any, AnyConnection, AnyPool, Connection, Executor, any::AnyPoolOptions};
 use sqlx::migrate::MigrateDatabase;
+use tokio::sync::broadcast;
 
-lazy_static::lazy_static! {
-    pub static ref GLOBAL_CONFIG: Arc<Mutex<Conf>> = Arc::new(Mutex::new(Conf::new().unwrap()));
-    // pub static ref CONN: Arc<Mutex<Pool<Sqlite>>> = Arc::new(Mutex::new(setup_db("actix-web-example.db").unwrap()));
-    // pub static ref SQLITE_CONN: Arc<Mutex<Pool<Sqlite>>> = Arc::new(Mutex::new(setup_db("actix-web-example.db").unwrap()));
-}
+use super::migrate;
+use crate::utils::ip::Cidr;
+
+/// Config loaded by [`init`], [`GLOBAL_CONFIG`] falls back to [`Conf::new`] without it.
+static LOADED: OnceCell<Conf> = OnceCell::new();
+
+/// Snapshot of the current config, swapped as a whole by [`reload`].
+///
+/// `GLOBAL_CONFIG.load()` is cheap, read it where a value should follow reloads
+/// instead of keeping a copy.
+pub static GLOBAL_CONFIG: Lazy<ArcSwap<Conf>> = Lazy::new(|| ArcSwap::from_pointee(match LOADED.get() {
+    Some(conf) => conf.clone(),
+    None => Conf::new().unwrap_or_else(|e| panic!("invalid config {}", e)),
+}));
+
+// lazy_static::lazy_static! {
+//     pub static ref CONN: Arc<Mutex<Pool<Sqlite>>> = Arc::new(Mutex::new(setup_db("actix-web-example.db").unwrap()));
+//     pub static ref SQLITE_CONN: Arc<Mutex<Pool<Sqlite>>> = Arc::new(Mutex::new(setup_db("actix-web-example.db").unwrap()));
+// }
 
-pub static SQLITE_CONN: Lazy<Pool<Sqlite>> = Lazy::new(|| setup_db("actix-web-example.db").unwrap());
+static CHANGES: Lazy<broadcast::Sender<Arc<Conf>>> = Lazy::new(|| broadcast::channel(8).0);
+
+/// Pool of the database configured in the `[db]` section.
+pub static DB_POOL: Lazy<AnyPool> = Lazy::new(|| setup_db(&GLOBAL_CONFIG.load().db).unwrap());
 
 // lazy_static::lazy_static! {
 //     pub static ref CONN: Arc<Mutex<Connection>> = Arc::new(Mutex::new(setup_users("actix-web-example.db")));
@@ -31,34 +51,48 @@ pub static SQLITE_CONN: Lazy<Pool<Sqlite>> = Lazy::new(|| setup_db("actix-web-ex
 
 
 pub async fn init_db() {
-    Sqlite::create_database("actix-web-example.db").await.expect("init db error");
-    let mut conn = SqliteConnection::connect("actix-web-example.db").await.unwrap();
-    let query = "CREATE TABLE IF NOT EXISTS courses (\
-            id TEXT, \
-            teacher_id INTEGER, \
-            name TEXT, \
-            time TEXT, \
-            description TEXT, \
-            format TEXT, \
-            structure TEXT, \
-            duration TEXT, \
-            price DOUBLE, \
-            language TEXT, \
-            level TEXT);";
-    conn.execute(query).await.unwrap();
-}
-
-pub fn setup_db<T: AsRef<Path>>(_path: T) -> Result<Pool<Sqlite>, sqlx::Error> {
-    debug!("init db");
-    let pool = SqlitePoolOptions::new()
-        .max_connections(100)
-        .min_connections(10)
-        // .connect_timeout(std::time::Duration::from_secs(10))
-        .max_lifetime(std::time::Duration::from_secs(1800))
-        .idle_timeout(std::time::Duration::from_secs(600))
-        // .connect("actix-web-example.db"). await?.unwrap();
-        .connect_lazy("actix-web-example.db").unwrap();
-    Ok(pool)
+    let db = GLOBAL_CONFIG.load().db.clone();
+    let url = db.url();
+    sqlx::any::install_default_drivers();
+    if !Any::database_exists(&url).await.unwrap_or(false) {
+        Any::create_database(&url).await.expect("init db error");
+    }
+    let mut conn = AnyConnection::connect(&url).await.unwrap();
+    // databases created before courses were versioned, the migrations expect the column
+    let legacy: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'courses'")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap();
+    let versioned: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM pragma_table_info('courses') WHERE name = 'version'")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap();
+    if legacy > 0 && versioned == 0 {
+        conn.execute("ALTER TABLE courses ADD COLUMN version INTEGER NOT NULL DEFAULT 1;").await.unwrap();
+    }
+    conn.close().await.unwrap();
+
+    migrate::up(&DB_POOL).await.expect("migrate db error");
+}
+
+/// Builds the pool lazily, connections are opened on first use.
+pub fn setup_db(db: &Database) -> Result<AnyPool, sqlx::Error> {
+    debug!("init db {:?}", db.name);
+    sqlx::any::install_default_drivers();
+    let mut options = AnyPoolOptions::new()
+        .max_connections(db.max_open())
+        .min_connections(db.max_idle())
+        .max_lifetime(Duration::from_secs(db.max_lifetime.unwrap_or(1800)))
+        .idle_timeout(Duration::from_secs(db.idle_timeout.unwrap_or(600)));
+    if let Some(secs) = db.connect_timeout {
+        options = options.acquire_timeout(Duration::from_secs(secs));
+    }
+    // off by default in SQLite, courses must reference an existing teacher
+    options = options.after_connect(|conn, _| Box::pin(async move {
+        conn.execute("PRAGMA foreign_keys = ON").await?;
+        Ok(())
+    }));
+    options.connect_lazy(&db.url())
 }
 
 #[derive(Deserialize, Debug, Validate, Clone)]
@@ -76,29 +110,364 @@ pub struct Server {
     pub services: Option<Vec<Address>>,
 }
 
-#[derive(Deserialize, Debug, Validate, Clone)]
+#[derive(Deserialize, Debug, Validate, Clone, PartialEq)]
 pub struct Address {
     pub address: Option<String>,
     #[validate(custom(function = "validate_port", message = "invalid port"))]
     pub port: Option<i64>,
 }
 
+impl Address {
+    /// Only the loopback listener serves plain http.
+    pub fn tls(&self) -> bool {
+        self.address.as_deref() != Some("127.0.0.1")
+    }
+}
+
 #[derive(Deserialize, Debug, Validate, Clone)]
 pub struct Log {
     file: Option<String>,
+    /// `off`, `error`, `warn`, `info`, `debug` or `trace`, applied on reload.
+    /// Records above the root level of `conf/log4rs.yaml` stay filtered.
+    #[validate(custom(function = "validate_level", message = "invalid log level"))]
+    pub level: Option<String>,
+    /// `text` for the Apache-style access log, `json` for one object per request
+    #[validate(custom(function = "validate_access_format", message = "access_format must be text or json"))]
+    pub access_format: Option<String>,
 }
 
-#[derive(Deserialize, Debug, Validate, Clone)]
+impl Log {
+    pub fn level_filter(&self) -> LevelFilter {
+        self.level.as_deref().and_then(|l| l.parse().ok()).unwrap_or(LevelFilter::Trace)
+    }
+
+    pub fn json_access_log(&self) -> bool {
+        self.access_format.as_deref() == Some("json")
+    }
+}
+
+/// CORS policy of each scope, see [`crate::middleware::cors`].
+#[derive(Deserialize, Debug, Validate, Clone, PartialEq)]
+pub struct Cors {
+    /// `/app`
+    #[validate]
+    pub app: CorsPolicy,
+    /// `/sys`
+    #[validate]
+    pub sys: CorsPolicy,
+    /// every other route
+    #[validate]
+    pub public: CorsPolicy,
+}
+
+/// Origins are checked per request so reloads apply, the other fields are read when a
+/// worker starts.
+#[derive(Deserialize, Debug, Validate, Clone, PartialEq)]
+#[validate(schema(function = "validate_cors_policy", skip_on_field_errors = false))]
+pub struct CorsPolicy {
+    /// exact origins like `https://example.com`, `*` allows any origin
+    pub allowed_origins: Option<Vec<String>>,
+    /// regular expressions matched against the whole origin
+    pub allowed_origin_patterns: Option<Vec<OriginPattern>>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allowed_headers: Option<Vec<String>>,
+    /// response headers scripts may read
+    pub expose_headers: Option<Vec<String>>,
+    /// seconds browsers may cache a preflight response
+    pub max_age: Option<usize>,
+    pub supports_credentials: Option<bool>,
+}
+
+impl CorsPolicy {
+    pub fn allows(&self, origin: &str) -> bool {
+        let exact = self.allowed_origins
+            .iter()
+            .flatten()
+            .any(|o| o == "*" || o == origin);
+        exact || self.allowed_origin_patterns.iter().flatten().any(|p| p.0.is_match(origin))
+    }
+
+    fn wildcard(&self) -> bool {
+        self.allowed_origins.iter().flatten().any(|o| o == "*")
+    }
+
+    /// First pattern that matches any origin, like `.*` or `https://.*`.
+    fn catch_all_pattern(&self) -> Option<&OriginPattern> {
+        self.allowed_origin_patterns
+            .iter()
+            .flatten()
+            .find(|p| PROBE_ORIGINS.iter().any(|o| p.0.is_match(o)))
+    }
+
+    /// The fields only read when a worker starts.
+    fn startup_fields(&self) -> CorsPolicy {
+        CorsPolicy {
+            allowed_origins: None,
+            allowed_origin_patterns: None,
+            ..self.clone()
+        }
+    }
+}
+
+/// Origins on the reserved `.invalid` domain, no pattern meant for real hosts matches them.
+const PROBE_ORIGINS: [&str; 3] = ["https://cors-probe.invalid", "http://cors-probe.invalid", "null"];
+
+/// Origin regex, anchored so it has to match the whole origin.
+#[derive(Debug, Clone)]
+pub struct OriginPattern(Regex);
+
+impl OriginPattern {
+    /// The pattern as written in the config, without the anchors.
+    pub fn as_str(&self) -> &str {
+        let anchored = self.0.as_str();
+        &anchored["^(?:".len()..anchored.len() - ")$".len()]
+    }
+}
+
+impl PartialEq for OriginPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl<'de> Deserialize<'de> for OriginPattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Regex::new(&format!("^(?:{})$", pattern))
+            .map(OriginPattern)
+            .map_err(|e| serde::de::Error::custom(format!("invalid origin pattern {:?}: {}", pattern, e)))
+    }
+}
+
+/// shortest HS256 secret the config accepts
+pub const MIN_JWT_SECRET: usize = 32;
+/// value the sample config used to ship with, rejected whatever its length
+const PLACEHOLDER_JWT_SECRET: &str = "change-me";
+
+const DEFAULT_MAX_OPEN: u32 = 100;
+const DEFAULT_MAX_IDLE: u32 = 10;
+
+#[derive(Deserialize, Debug, Validate, Clone, PartialEq)]
+#[validate(schema(function = "validate_database", skip_on_field_errors = false))]
 pub struct Database {
+    /// only `sqlite`, the migrations and queries are written for it
     pub db_type: Option<String>,
-    pub host: Option<String>,
-    pub port: Option<i64>,
+    /// database file
     pub name: Option<String>,
-    pub user: Option<String>,
-    pub password: Option<String>,
-    pub ssl_enable: Option<bool>,
-    pub max_idle: Option<String>,
-    pub max_open: Option<String>,
+    /// connections kept open while idle
+    pub max_idle: Option<u32>,
+    /// upper bound of open connections
+    pub max_open: Option<u32>,
+    /// seconds to wait for a free connection
+    pub connect_timeout: Option<u64>,
+    /// seconds before an idle connection above `max_idle` is closed
+    pub idle_timeout: Option<u64>,
+    /// seconds before a connection is replaced
+    pub max_lifetime: Option<u64>,
+}
+
+impl Database {
+    pub fn max_open(&self) -> u32 {
+        self.max_open.unwrap_or(DEFAULT_MAX_OPEN)
+    }
+
+    pub fn max_idle(&self) -> u32 {
+        self.max_idle.unwrap_or_else(|| DEFAULT_MAX_IDLE.min(self.max_open()))
+    }
+
+    /// Connection url understood by `sqlx::AnyPool`.
+    pub fn url(&self) -> String {
+        format!("sqlite://{}?mode=rwc", self.name.as_deref().unwrap_or_default())
+    }
+}
+
+/// Request and response body audit, see [`crate::middleware::BodyAudit`].
+/// Read per request so reloads apply.
+#[derive(Deserialize, Debug, Validate, Clone)]
+pub struct BodyLog {
+    pub enable: Option<bool>,
+    /// bytes kept of each body, the rest is only counted
+    pub max_bytes: Option<usize>,
+    /// share of requests audited, 0 to 100
+    #[validate(range(max = 100, message = "sample_percent must be 0 to 100"))]
+    pub sample_percent: Option<u8>,
+    /// JSON fields and form keys whose values are logged as `***`, compared case-insensitively
+    pub redact: Option<Vec<String>>,
+}
+
+/// Token buckets of [`crate::middleware::RateLimit`], read per request so reloads apply.
+#[derive(Deserialize, Debug, Validate, Clone)]
+pub struct RateLimit {
+    pub enable: Option<bool>,
+    /// seconds an unused bucket is kept before it is evicted
+    pub idle_timeout: Option<u64>,
+    /// buckets kept at most, the least recently used one makes room for a new client
+    #[validate(range(min = 1, message = "max_buckets must be at least 1"))]
+    pub max_buckets: Option<usize>,
+    #[validate]
+    pub groups: Option<Vec<RateLimitGroup>>,
+    /// clients keyed by their `x-api-key`, unknown keys are ignored
+    #[validate]
+    pub api_keys: Option<Vec<RateLimitApiKey>>,
+}
+
+impl RateLimit {
+    /// Group with the longest prefix matching the request, `None` when it is not limited.
+    pub fn group(&self, method: &str, path: &str) -> Option<&RateLimitGroup> {
+        if !self.enable.unwrap_or(false) {
+            return None;
+        }
+        self.groups
+            .iter()
+            .flatten()
+            .filter(|g| g.matches(method, path))
+            .max_by_key(|g| g.prefix.len())
+    }
+
+    /// Id of the configured client `key` belongs to.
+    pub fn api_key_id(&self, key: &str) -> Option<&str> {
+        self.api_keys
+            .iter()
+            .flatten()
+            .find(|k| k.key == key)
+            .map(|k| k.id.as_str())
+    }
+}
+
+/// An API client, buckets and logs name it by `id` so the key itself is never printed.
+#[derive(Deserialize, Debug, Validate, Clone)]
+pub struct RateLimitApiKey {
+    pub id: String,
+    #[validate(length(min = 16, message = "api key too short"))]
+    pub key: String,
+}
+
+#[derive(Deserialize, Debug, Validate, Clone)]
+pub struct RateLimitGroup {
+    pub name: String,
+    /// path prefix like `/app/courses`, matched on segment boundaries
+    pub prefix: String,
+    /// methods the group applies to, all when missing
+    pub methods: Option<Vec<String>>,
+    /// requests a client may send in a burst
+    #[validate(range(min = 1, message = "capacity must be at least 1"))]
+    pub capacity: u32,
+    /// tokens added back per second
+    #[validate(range(min = 0.001, message = "per_second must be positive"))]
+    pub per_second: f64,
+}
+
+impl RateLimitGroup {
+    fn matches(&self, method: &str, path: &str) -> bool {
+        let prefix = self.prefix.trim_end_matches('/');
+        let path_matches = match path.strip_prefix(prefix) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/'),
+            None => false,
+        };
+        path_matches
+            && self.methods.as_ref().is_none_or(|m| m.iter().any(|m| m.eq_ignore_ascii_case(method)))
+    }
+}
+
+/// Reverse proxies whose forwarding headers are believed, see [`crate::utils::ip`].
+/// Read per request so reloads apply.
+#[derive(Deserialize, Debug, Validate, Clone)]
+pub struct Proxy {
+    /// addresses or CIDRs like `10.0.0.0/8`, forwarding headers are ignored while empty
+    pub trusted: Option<Vec<Cidr>>,
+}
+
+#[derive(Deserialize, Debug, Validate, Clone, PartialEq)]
+#[validate(schema(function = "validate_jwt", skip_on_field_errors = false))]
+pub struct Jwt {
+    /// `HS256` or `RS256`
+    #[validate(custom(function = "validate_algorithm", message = "unsupported jwt algorithm"))]
+    pub algorithm: Option<String>,
+    /// shared secret used by HS256, at least [`MIN_JWT_SECRET`] bytes
+    pub secret: Option<String>,
+    /// PEM encoded public key file used by RS256
+    pub public_key: Option<String>,
+    /// PEM encoded private key file RS256 tokens are signed with, see [`crate::handler::auth`]
+    pub private_key: Option<String>,
+    pub issuer: Option<String>,
+    pub audience: Option<String>,
+    /// allowed clock skew in seconds when checking `exp`/`nbf`
+    pub leeway: Option<u64>,
+}
+
+/// Accounts and tokens of `/auth`, see [`crate::handler::auth`]. Read per request so
+/// reloads apply.
+#[derive(Deserialize, Debug, Validate, Clone)]
+pub struct Auth {
+    /// seconds an access token is valid
+    pub access_ttl: Option<u64>,
+    /// seconds a refresh token is valid, rotating it does not extend the login
+    pub refresh_ttl: Option<u64>,
+    /// failed logins in a row that lock an account
+    #[validate(range(min = 1, message = "max_failed_logins must be at least 1"))]
+    pub max_failed_logins: Option<u32>,
+    /// seconds a locked account refuses logins
+    pub lockout: Option<u64>,
+}
+
+/// Certificates of the TLS listeners, see [`crate::utils::tls`]. Paths are relative to the
+/// working directory.
+#[derive(Deserialize, Debug, Validate, Clone, PartialEq)]
+pub struct Tls {
+    /// PEM certificate chain, leaf first, reloaded when the file changes, a new path needs a
+    /// restart
+    pub cert: Option<String>,
+    /// PEM private key in PKCS8, RSA or EC format, reloaded with `cert`
+    pub key: Option<String>,
+    /// PEM bundle of the CAs client certificates must chain to, mTLS is off while empty
+    pub client_ca: Option<String>,
+    /// reject clients without a certificate instead of only verifying the ones sent
+    pub client_auth_required: Option<bool>,
+}
+
+/// Graceful shutdown, see [`crate::utils::shutdown`].
+#[derive(Deserialize, Debug, Validate, Clone)]
+pub struct Shutdown {
+    /// seconds in-flight requests get to finish once a stop starts
+    pub drain_timeout: Option<u64>,
+    /// bearer token of `POST /sys/stop`, the endpoint refuses every request while empty
+    pub admin_token: Option<String>,
+}
+
+/// Where domain events like enrollments are published, see [`crate::events`]. Read at
+/// startup.
+#[derive(Deserialize, Debug, Validate, Clone, PartialEq)]
+pub struct Events {
+    /// `log` writes them to the `events` log target, `kafka` needs the `kafka` feature
+    #[validate(custom(function = "validate_events_sink", message = "sink must be log or kafka"))]
+    pub sink: Option<String>,
+    /// comma separated `host:port` of the kafka brokers
+    pub brokers: Option<String>,
+    pub topic: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Validate, Clone, PartialEq)]
+pub struct Sign {
+    pub enable: Option<bool>,
+    /// false lets requests without `x-sign-client` through on their jwt alone; signed
+    /// requests are checked either way
+    pub required: Option<bool>,
+    /// replay window in seconds, requests with an older or newer timestamp are rejected
+    pub window: Option<i64>,
+    #[validate]
+    pub clients: Option<Vec<SignClient>>,
+    /// derives the signing keys `/auth/login` hands to clients without a secret of their
+    /// own, like the browser; random per process while empty, at least [`MIN_JWT_SECRET`]
+    /// bytes otherwise
+    #[validate(custom(function = "validate_session_secret"))]
+    pub session_secret: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Validate, Clone, PartialEq)]
+pub struct SignClient {
+    pub id: String,
+    #[validate(length(min = 16, message = "sign secret too short"))]
+    pub secret: String,
 }
 
 #[derive(Deserialize, Debug, Validate)]
@@ -108,8 +477,29 @@ pub struct Conf {
     #[validate]
     pub log: Log,
     #[validate]
+    pub cors: Cors,
+    #[validate]
+    pub body_log: BodyLog,
+    #[validate]
+    pub rate_limit: RateLimit,
+    #[validate]
+    pub proxy: Proxy,
+    #[validate]
     pub server: Server,
-
+    #[validate]
+    pub jwt: Jwt,
+    #[validate]
+    pub auth: Auth,
+    #[validate]
+    pub sign: Sign,
+    #[validate]
+    pub db: Database,
+    #[validate]
+    pub tls: Tls,
+    #[validate]
+    pub shutdown: Shutdown,
+    #[validate]
+    pub events: Events,
 }
 
 fn validate_port(p: i64) -> Result<(), ValidationError> {
@@ -121,24 +511,165 @@ fn validate_port(p: i64) -> Result<(), ValidationError> {
     Ok(())
 }
 
+fn validate_level(level: &str) -> Result<(), ValidationError> {
+    LevelFilter::from_str(level).map(|_| ()).map_err(|_| ValidationError::new("invalid_level"))
+}
+
+fn validate_access_format(format: &str) -> Result<(), ValidationError> {
+    match format {
+        "text" | "json" => Ok(()),
+        _ => Err(ValidationError::new("invalid_access_format")),
+    }
+}
+
+fn validate_events_sink(sink: &str) -> Result<(), ValidationError> {
+    match sink {
+        "log" | "kafka" => Ok(()),
+        _ => Err(ValidationError::new("invalid_events_sink")),
+    }
+}
+
+fn validate_cors_policy(policy: &CorsPolicy) -> Result<(), ValidationError> {
+    let invalid = |message: String| {
+        let mut e = ValidationError::new("invalid_cors");
+        e.message = Some(message.into());
+        e
+    };
+    if policy.supports_credentials.unwrap_or(false) {
+        if policy.wildcard() {
+            return Err(invalid("wildcard origin \"*\" cannot be combined with supports_credentials".to_string()));
+        }
+        if let Some(pattern) = policy.catch_all_pattern() {
+            return Err(invalid(format!(
+                "origin pattern {:?} matches any origin and cannot be combined with supports_credentials",
+                pattern.as_str()
+            )));
+        }
+    }
+    for method in policy.allowed_methods.iter().flatten() {
+        Method::from_str(method).map_err(|_| invalid(format!("invalid method {:?}", method)))?;
+    }
+    let headers = policy.allowed_headers.iter().flatten().chain(policy.expose_headers.iter().flatten());
+    for header in headers {
+        HeaderName::from_str(header).map_err(|_| invalid(format!("invalid header {:?}", header)))?;
+    }
+    Ok(())
+}
+
+fn invalid_database(message: String) -> ValidationError {
+    let mut e = ValidationError::new("invalid_database");
+    e.message = Some(message.into());
+    e
+}
+
+fn validate_database(db: &Database) -> Result<(), ValidationError> {
+    let db_type = db.db_type.as_deref().unwrap_or("sqlite");
+    if db_type != "sqlite" {
+        return Err(invalid_database(format!("db.db_type {} is not supported, only sqlite is", db_type)));
+    }
+    if db.name.as_deref().unwrap_or_default().is_empty() {
+        return Err(invalid_database("db.name is required".to_string()));
+    }
+    let (max_open, max_idle) = (db.max_open(), db.max_idle());
+    if max_open == 0 {
+        return Err(invalid_database("db.max_open must be at least 1".to_string()));
+    }
+    if max_idle > max_open {
+        return Err(invalid_database(format!("db.max_idle {} is greater than db.max_open {}", max_idle, max_open)));
+    }
+    Ok(())
+}
+
+fn validate_jwt(jwt: &Jwt) -> Result<(), ValidationError> {
+    if jwt.algorithm.as_deref().unwrap_or("HS256") != "HS256" {
+        return Ok(());
+    }
+    let secret = jwt.secret.as_deref().unwrap_or_default();
+    if secret == PLACEHOLDER_JWT_SECRET || secret.len() < MIN_JWT_SECRET {
+        let mut e = ValidationError::new("weak_jwt_secret");
+        e.message = Some(format!("jwt.secret must be a random value of at least {} bytes", MIN_JWT_SECRET).into());
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn validate_session_secret(secret: &str) -> Result<(), ValidationError> {
+    if !secret.is_empty() && secret.len() < MIN_JWT_SECRET {
+        let mut e = ValidationError::new("weak_session_secret");
+        let msg = format!("sign.session_secret must be a random value of at least {} bytes", MIN_JWT_SECRET);
+        e.message = Some(msg.into());
+        return Err(e);
+    }
+    Ok(())
+}
+
+fn validate_algorithm(alg: &str) -> Result<(), ValidationError> {
+    match alg {
+        "HS256" | "RS256" => Ok(()),
+        _ => Err(ValidationError::new("invalid algorithm")),
+    }
+}
+
+/// Loads the config from the process arguments and environment, see [`Conf::load`].
+pub fn init() -> Result<(), ConfError> {
+    let conf = Conf::new()?;
+    log::set_max_level(conf.log.level_filter());
+    let _ = LOADED.set(conf);
+    Ok(())
+}
+
+/// Config file read by [`Conf::new`], the one to watch for changes.
+pub fn path() -> Option<PathBuf> {
+    layers(conf_rs::Loader::new("APP").args(std::env::args())).path()
+}
+
+/// Receives every config swapped in by [`reload`].
+pub fn subscribe() -> broadcast::Receiver<Arc<Conf>> {
+    CHANGES.subscribe()
+}
+
+/// Reads the config again and swaps it into [`GLOBAL_CONFIG`] if it is valid, an invalid
+/// config leaves the current one in place.
+///
+/// Log levels, CORS origins, body logging and rate limits apply at once. Returns the changed
+/// sections that are only read at startup and need a restart.
+///
+/// `log.level` only sets the global maximum: it can quiet the loggers of `conf/log4rs.yaml`
+/// but not raise them above their own levels, log4rs re-reads that file by itself.
+pub fn reload() -> Result<Vec<&'static str>, ConfError> {
+    let conf = Arc::new(Conf::new()?);
+    let old = GLOBAL_CONFIG.swap(conf.clone());
+    log::set_max_level(conf.log.level_filter());
+    let restart = conf.restart_required(&old);
+    let _ = CHANGES.send(conf);
+    Ok(restart)
+}
+
+fn layers(loader: conf_rs::Loader) -> conf_rs::Loader {
+    loader
+        .defaults(include_str!("app.toml"))
+        .default_file("conf/app.toml")
+        .var("addr", "127.0.0.1")
+}
+
 impl Conf {
-    pub fn new() -> Result<Conf, &'static str> {
-        let mut cwd = env::current_dir().unwrap();
-        cwd.push(Path::new("conf"));
-        cwd.push(Path::new("app.toml"));
-        let conf_path = cwd.to_str().unwrap();
-        let mut file = match File::open(conf_path) {
-            Ok(f) => f,
-            Err(e) => panic!("open file {} exception:{}", conf_path, e)
-        };
-        let mut str_val = String::new();
-        match file.read_to_string(&mut str_val) {
-            Ok(s) => s,
-            Err(e) => panic!("Error Reading file: {}", e)
-        };
-        let config: Conf = toml::from_str(&str_val).unwrap();
+    pub fn new() -> Result<Conf, ConfError> {
+        Conf::load(conf_rs::Loader::new("APP").args(std::env::args()))
+    }
+
+    /// Layers `loader` over the built-in `src/conf/app.toml`: the file from `--config`,
+    /// `APP_CONFIG` or `conf/app.toml`, then `APP__*` environment overrides.
+    /// `{{addr}}` defaults to `127.0.0.1`. The built-in file holds no secrets, without
+    /// `jwt.secret` from one of the other layers validation fails.
+    pub fn load(loader: conf_rs::Loader) -> Result<Conf, ConfError> {
+        let config: Conf = layers(loader).load()?;
         // validate config
-        config.validate().unwrap();
+        if let Err(e) = config.validate() {
+            return Err(field_error(String::new(), &e).unwrap_or(ConfError::Field {
+                path: String::new(),
+                msg: e.to_string(),
+            }));
+        }
         let s = config.clone();
         debug!("config.package.name:{:?}", s.package.name);
         debug!("config.package.version:{:?}", s.package.version);
@@ -146,6 +677,74 @@ impl Conf {
         debug!("config.log.file:{:?}", s.log.file);
         Ok(config)
     }
+
+    /// Sections differing from `old` that are only read when the server or a worker starts.
+    pub fn restart_required(&self, old: &Conf) -> Vec<&'static str> {
+        let mut sections = Vec::new();
+        if self.server.services != old.server.services {
+            sections.push("server.services");
+        }
+        if self.db != old.db {
+            sections.push("db");
+        }
+        if self.jwt != old.jwt {
+            sections.push("jwt");
+        }
+        if self.sign != old.sign {
+            sections.push("sign");
+        }
+        let cors = |c: &Cors| [c.app.startup_fields(), c.sys.startup_fields(), c.public.startup_fields()];
+        if cors(&self.cors) != cors(&old.cors) {
+            sections.push("cors");
+        }
+        if self.log.access_format != old.log.access_format {
+            sections.push("log.access_format");
+        }
+        // the certificate watcher keeps the paths it started with
+        if self.tls.cert != old.tls.cert {
+            sections.push("tls.cert");
+        }
+        if self.tls.key != old.tls.key {
+            sections.push("tls.key");
+        }
+        if (&self.tls.client_ca, self.tls.client_auth_required) != (&old.tls.client_ca, old.tls.client_auth_required) {
+            sections.push("tls.client_ca");
+        }
+        if self.shutdown.drain_timeout != old.shutdown.drain_timeout {
+            sections.push("shutdown.drain_timeout");
+        }
+        if self.events != old.events {
+            sections.push("events");
+        }
+        sections
+    }
+}
+
+/// First validation error, with the path of the field in the config.
+fn field_error(path: String, errors: &ValidationErrors) -> Option<ConfError> {
+    let mut fields: Vec<_> = errors.errors().iter().collect();
+    fields.sort_by_key(|(field, _)| **field);
+    for (field, kind) in fields {
+        let path = match (*field, path.is_empty()) {
+            ("__all__", _) => path.clone(),
+            (field, true) => field.to_string(),
+            (field, false) => format!("{}.{}", path, field),
+        };
+        let error = match kind {
+            ValidationErrorsKind::Field(errs) => errs.first().map(|e| ConfError::Field {
+                path: path.clone(),
+                msg: e.message.clone().unwrap_or_else(|| e.code.clone()).to_string(),
+            }),
+            ValidationErrorsKind::Struct(nested) => field_error(path.clone(), nested),
+            ValidationErrorsKind::List(items) => items
+                .iter()
+                .find_map(|(i, nested)| field_error(format!("{}[{}]", path, i), nested)),
+        };
+        if error.is_some() {
+            return error;
+        }
+    }
+    None
 }
 
 impl Clone for Conf {
@@ -153,7 +752,18 @@ impl Clone for Conf {
         Conf {
             package: self.package.clone(),
             log: self.log.clone(),
+            cors: self.cors.clone(),
+            body_log: self.body_log.clone(),
+            rate_limit: self.rate_limit.clone(),
+            proxy: self.proxy.clone(),
             server: self.server.clone(),
+            jwt: self.jwt.clone(),
+            auth: self.auth.clone(),
+            sign: self.sign.clone(),
+            db: self.db.clone(),
+            tls: self.tls.clone(),
+            shutdown: self.shutdown.clone(),
+            events: self.events.clone(),
         }
     }
 }
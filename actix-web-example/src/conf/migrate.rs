@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::AnyPool;
+
+/// Schema migrations under `migrations/`, embedded at compile time.
+///
+/// Applied versions are tracked by sqlx in the `_sqlx_migrations` table. The scripts use
+/// SQLite syntax, the only `db.db_type` the config accepts.
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// One row of `migrate status`.
+#[derive(Debug)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub description: String,
+    pub applied: bool,
+}
+
+/// Applies all pending migrations.
+pub async fn up(pool: &AnyPool) -> Result<(), MigrateError> {
+    MIGRATOR.run(pool).await
+}
+
+/// Reverts applied migrations newer than `target`, by default only the latest one.
+pub async fn down(pool: &AnyPool, target: Option<i64>) -> Result<(), MigrateError> {
+    let target = match target {
+        Some(target) => target,
+        None => {
+            let applied = applied_versions(pool).await?;
+            match applied.iter().max() {
+                // the latest applied migration goes back to the one before it
+                Some(latest) => applied.iter().filter(|v| *v < latest).max().copied().unwrap_or(0),
+                None => return Ok(()),
+            }
+        }
+    };
+    MIGRATOR.undo(pool, target).await
+}
+
+/// Lists every known migration and whether it has been applied.
+pub async fn status(pool: &AnyPool) -> Result<Vec<MigrationStatus>, MigrateError> {
+    let applied = applied_versions(pool).await?;
+    Ok(MIGRATOR
+        .iter()
+        .filter(|m| !m.migration_type.is_down_migration())
+        .map(|m| MigrationStatus {
+            version: m.version,
+            description: m.description.to_string(),
+            applied: applied.contains(&m.version),
+        })
+        .collect())
+}
+
+async fn applied_versions(pool: &AnyPool) -> Result<HashSet<i64>, MigrateError> {
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    Ok(conn
+        .list_applied_migrations()
+        .await?
+        .into_iter()
+        .map(|m| m.version)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::any::AnyPoolOptions;
+    use sqlx::Row;
+
+    use super::*;
+
+    /// One connection, an in-memory database lives as long as its connection.
+    async fn memory_pool() -> AnyPool {
+        sqlx::any::install_default_drivers();
+        let pool = AnyPoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        // as set by `setup_db`, the down scripts have to cope with it
+        sqlx::query("PRAGMA foreign_keys = ON").execute(&pool).await.unwrap();
+        pool
+    }
+
+    async fn applied(pool: &AnyPool) -> Vec<i64> {
+        let status = status(pool).await.unwrap();
+        status.iter().filter(|m| m.applied).map(|m| m.version).collect()
+    }
+
+    async fn tables(pool: &AnyPool) -> Vec<String> {
+        let sql = "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE '\\_%' ESCAPE '\\' \
+                   AND name NOT LIKE 'sqlite%' ORDER BY name";
+        let rows = sqlx::query(sql).fetch_all(pool).await.unwrap();
+        rows.iter().map(|r| r.get::<String, _>("name")).collect()
+    }
+
+    /// Every down script undoes its up script, `DROP COLUMN` of 0006 needs SQLite 3.35.
+    #[actix_web::test]
+    async fn up_down_to_zero_and_up_again() {
+        let pool = memory_pool().await;
+        let all: Vec<i64> = status(&pool).await.unwrap().iter().map(|m| m.version).collect();
+        assert_eq!(all.len(), 6);
+
+        up(&pool).await.unwrap();
+        assert_eq!(applied(&pool).await, all);
+        assert_eq!(tables(&pool).await, ["courses", "enrollments", "refresh_tokens", "teachers", "users"]);
+
+        // without a target only the latest one
+        down(&pool, None).await.unwrap();
+        assert_eq!(applied(&pool).await, all[..5]);
+        let columns = sqlx::query("SELECT name FROM pragma_table_info('courses') WHERE name = 'capacity'")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert!(columns.is_empty());
+
+        down(&pool, Some(0)).await.unwrap();
+        assert!(applied(&pool).await.is_empty());
+        assert!(tables(&pool).await.is_empty(), "{:?}", tables(&pool).await);
+
+        up(&pool).await.unwrap();
+        assert_eq!(applied(&pool).await, all);
+        sqlx::query("INSERT INTO teachers (name, created_at, updated_at) VALUES ('t', '', '')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO courses (id, teacher_id, name, time, capacity, version, created_at, updated_at) \
+             VALUES ('c', 1, 'n', '', 3, 1, '', '')",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+}
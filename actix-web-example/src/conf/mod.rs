@@ -1,3 +1,3 @@
 pub mod config;
-
-pub use config::CACHE;
+pub mod migrate;
+pub mod watch;
@@ -0,0 +1,77 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use super::config;
+use crate::utils::signal;
+
+/// Editors write a file in several steps, events closer than this are one change.
+pub(crate) const SETTLE: Duration = Duration::from_millis(200);
+
+/// Reloads [`config::GLOBAL_CONFIG`] whenever the config file changes or the process
+/// receives SIGHUP. Must be called inside the tokio runtime.
+pub fn spawn() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let watcher = config::path().and_then(|path| match watch_files(&[path.as_path()], tx) {
+        Ok(watcher) => {
+            info!("watching {} for config changes", path.display());
+            Some(watcher)
+        }
+        Err(e) => {
+            warn!("cannot watch {}, reload with SIGHUP: {}", path.display(), e);
+            None
+        }
+    });
+
+    tokio::spawn(async move {
+        // dropping the watcher stops the events
+        let _watcher = watcher;
+        let mut hangup = signal::hangup();
+        loop {
+            tokio::select! {
+                Some(()) = rx.recv() => {
+                    tokio::time::sleep(SETTLE).await;
+                    while rx.try_recv().is_ok() {}
+                }
+                () = hangup.recv() => {}
+            }
+            reload();
+        }
+    });
+}
+
+fn reload() {
+    match config::reload() {
+        Ok(restart) if restart.is_empty() => info!("config reloaded"),
+        Ok(restart) => warn!("config reloaded, restart to apply the changes of {}", restart.join(", ")),
+        Err(e) => error!("config not reloaded, keeping the current one: {}", e),
+    }
+}
+
+/// Sends on `tx` when one of `paths` is written. Watches their directories, editors and
+/// tools like cert-manager often replace a file instead of writing it.
+pub(crate) fn watch_files(paths: &[&Path], tx: mpsc::UnboundedSender<()>) -> notify::Result<RecommendedWatcher> {
+    let names: Vec<_> = paths.iter().filter_map(|p| p.file_name().map(|n| n.to_os_string())).collect();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let Ok(event) = event else { return };
+        let changed = matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+            && event.paths.iter().any(|p| p.file_name().is_some_and(|n| names.iter().any(|m| m == n)));
+        if changed {
+            let _ = tx.send(());
+        }
+    })?;
+    let mut dirs: Vec<PathBuf> = paths
+        .iter()
+        .map(|path| match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        })
+        .collect();
+    dirs.dedup();
+    for dir in dirs {
+        watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+    }
+    Ok(watcher)
+}
@@ -0,0 +1,348 @@
+use std::collections::HashMap;
+
+use actix_web::{error::PayloadError, http::{header, StatusCode}, HttpResponse, ResponseError};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+use validator::ValidationErrors;
+
+use crate::utils::request_id;
+
+/// JSON body of every error response.
+///
+/// `code` is stable and can be matched by clients:
+///
+/// code | status | meaning
+/// ---- | ------ | -------
+/// `ACTIX_000001` | 400 | validation failed, see `fields`
+/// `ACTIX_000002` | 401 | missing or invalid credentials
+/// `ACTIX_000003` | 404 | resource not found
+/// `ACTIX_000004` | 409 | conflicting state
+/// `ACTIX_000005` | 500 | database error
+/// `ACTIX_000006` | 400 | malformed request
+/// `ACTIX_000007` | 412 | `If-Match` does not match the current version
+/// `ACTIX_000008` | 428 | `If-Match` is required
+/// `ACTIX_000009` | 429 | rate limit exceeded, retry after `Retry-After` seconds
+/// `ACTIX_000010` | 500 | unexpected server error, quote `request_id` when reporting it
+/// `ACTIX_000011` | 403 | authenticated but not allowed, `msg` carries the reason
+#[derive(Debug, Deserialize, Serialize)]
+pub struct HttpError {
+    pub code: String,
+    pub msg: String,
+    /// field name -> validation messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<HashMap<String, Vec<String>>>,
+    /// id of the failed request, set for server errors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl HttpError {
+    /// Body for an error response that was not built from an [`AppError`].
+    pub fn for_status(status: StatusCode, request_id: Option<String>) -> HttpError {
+        let code = match status.as_u16() {
+            401 => "ACTIX_000002",
+            403 => "ACTIX_000011",
+            404 => "ACTIX_000003",
+            409 => "ACTIX_000004",
+            412 => "ACTIX_000007",
+            428 => "ACTIX_000008",
+            429 => "ACTIX_000009",
+            s if s >= 500 => "ACTIX_000010",
+            _ => "ACTIX_000006",
+        };
+        HttpError {
+            code: code.to_string(),
+            msg: status.canonical_reason().unwrap_or("error").to_lowercase(),
+            fields: None,
+            request_id: request_id.filter(|_| status.is_server_error()),
+        }
+    }
+}
+
+/// Crate-wide error returned by the handlers.
+#[derive(Debug, Display)]
+pub enum AppError {
+    #[display(fmt = "Validation error: {}", _0)]
+    Validation(ValidationErrors),
+    #[display(fmt = "Unauthorized: {}", _0)]
+    Unauthorized(String),
+    #[display(fmt = "Not found: {}", _0)]
+    NotFound(String),
+    #[display(fmt = "Conflict: {}", _0)]
+    Conflict(String),
+    #[display(fmt = "Database error: {}", _0)]
+    Db(sqlx::Error),
+    #[display(fmt = "Bad request: {}", _0)]
+    BadRequest(String),
+    #[display(fmt = "Precondition failed: {}", _0)]
+    PreconditionFailed(String),
+    #[display(fmt = "Precondition required: {}", _0)]
+    PreconditionRequired(String),
+    #[display(fmt = "Too many requests: retry after {}s", retry_after)]
+    TooManyRequests { limit: u32, retry_after: u64 },
+    #[display(fmt = "Internal error: {}", _0)]
+    Internal(String),
+    #[display(fmt = "Forbidden: {}", _0)]
+    Forbidden(String),
+}
+
+impl AppError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "ACTIX_000001",
+            AppError::Unauthorized(_) => "ACTIX_000002",
+            AppError::NotFound(_) => "ACTIX_000003",
+            AppError::Conflict(_) => "ACTIX_000004",
+            AppError::Db(_) => "ACTIX_000005",
+            AppError::BadRequest(_) => "ACTIX_000006",
+            AppError::PreconditionFailed(_) => "ACTIX_000007",
+            AppError::PreconditionRequired(_) => "ACTIX_000008",
+            AppError::TooManyRequests { .. } => "ACTIX_000009",
+            AppError::Internal(_) => "ACTIX_000010",
+            AppError::Forbidden(_) => "ACTIX_000011",
+        }
+    }
+
+    fn fields(&self) -> Option<HashMap<String, Vec<String>>> {
+        match self {
+            AppError::Validation(errors) => Some(
+                errors
+                    .field_errors()
+                    .into_iter()
+                    .map(|(field, errs)| {
+                        let msgs = errs
+                            .iter()
+                            .map(|e| e.message.clone().unwrap_or_else(|| e.code.clone()).to_string())
+                            .collect();
+                        (field.to_string(), msgs)
+                    })
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Db(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::PreconditionRequired(_) => StatusCode::PRECONDITION_REQUIRED,
+            AppError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let msg = match self {
+            AppError::Db(e) => {
+                // keep driver details out of the response
+                error!("database error: {:?}", e);
+                "database error".to_string()
+            }
+            AppError::Internal(e) => {
+                error!("internal error: {}", e);
+                "internal server error".to_string()
+            }
+            AppError::Validation(_) => "validation failed".to_string(),
+            _ => self.to_string(),
+        };
+        let mut resp = HttpResponse::build(self.status_code());
+        match self {
+            AppError::Unauthorized(_) => {
+                resp.insert_header((header::WWW_AUTHENTICATE, "Bearer"));
+            }
+            AppError::TooManyRequests { limit, retry_after } => {
+                resp.insert_header((header::RETRY_AFTER, retry_after.to_string()))
+                    .insert_header(("x-ratelimit-limit", limit.to_string()))
+                    .insert_header(("x-ratelimit-remaining", "0"))
+                    .insert_header(("x-ratelimit-reset", retry_after.to_string()));
+            }
+            _ => {}
+        }
+        resp.json(HttpError {
+            code: self.code().to_string(),
+            msg,
+            fields: self.fields(),
+            // error responses are rendered inside the request scope, see `utils::request_id`
+            request_id: request_id::current()
+                .map(|id| id.to_string())
+                .filter(|_| self.status_code().is_server_error()),
+        })
+    }
+}
+
+impl From<ValidationErrors> for AppError {
+    fn from(e: ValidationErrors) -> Self {
+        AppError::Validation(e)
+    }
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => AppError::NotFound("row not found".to_string()),
+            sqlx::Error::Database(ref db) if db.is_unique_violation() || db.is_foreign_key_violation() => {
+                // the driver message names tables and columns, keep it in the log
+                warn!("constraint violation: {}", db.message());
+                AppError::Conflict("conflicts with existing data".to_string())
+            }
+            e => AppError::Db(e),
+        }
+    }
+}
+
+impl From<PayloadError> for AppError {
+    fn from(e: PayloadError) -> Self {
+        AppError::BadRequest(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for AppError {
+    fn from(e: serde_json::Error) -> Self {
+        AppError::BadRequest(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use std::error::Error;
+    use std::fmt;
+
+    use actix_web::body;
+    use serde_json::Value;
+    use sqlx::error::{DatabaseError, ErrorKind};
+    use validator::ValidationError;
+
+    use super::*;
+
+    /// Constraint violation as a driver reports it.
+    #[derive(Debug)]
+    struct Violation(ErrorKind);
+
+    impl fmt::Display for Violation {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str(self.message())
+        }
+    }
+
+    impl Error for Violation {}
+
+    impl DatabaseError for Violation {
+        fn message(&self) -> &str {
+            "UNIQUE constraint failed: users.username"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            None
+        }
+
+        fn as_error(&self) -> &(dyn Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn kind(&self) -> ErrorKind {
+            match &self.0 {
+                ErrorKind::UniqueViolation => ErrorKind::UniqueViolation,
+                ErrorKind::ForeignKeyViolation => ErrorKind::ForeignKeyViolation,
+                _ => ErrorKind::Other,
+            }
+        }
+    }
+
+    async fn body(err: AppError) -> (StatusCode, Value) {
+        let resp = err.error_response();
+        let status = resp.status();
+        let bytes = body::to_bytes(resp.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[test]
+    fn status_codes() {
+        let cases = [
+            (StatusCode::BAD_REQUEST, "ACTIX_000006"),
+            (StatusCode::UNAUTHORIZED, "ACTIX_000002"),
+            (StatusCode::FORBIDDEN, "ACTIX_000011"),
+            (StatusCode::NOT_FOUND, "ACTIX_000003"),
+            (StatusCode::METHOD_NOT_ALLOWED, "ACTIX_000006"),
+            (StatusCode::CONFLICT, "ACTIX_000004"),
+            (StatusCode::PRECONDITION_FAILED, "ACTIX_000007"),
+            (StatusCode::PRECONDITION_REQUIRED, "ACTIX_000008"),
+            (StatusCode::TOO_MANY_REQUESTS, "ACTIX_000009"),
+            (StatusCode::INTERNAL_SERVER_ERROR, "ACTIX_000010"),
+            (StatusCode::SERVICE_UNAVAILABLE, "ACTIX_000010"),
+        ];
+        for (status, code) in cases {
+            let err = HttpError::for_status(status, Some("req-1".to_string()));
+            assert_eq!(err.code, code, "{}", status);
+            // the id is only quoted for server errors
+            assert_eq!(err.request_id.is_some(), status.is_server_error(), "{}", status);
+        }
+        assert_eq!(HttpError::for_status(StatusCode::NOT_FOUND, None).msg, "not found");
+    }
+
+    #[test]
+    fn codes_match_status() {
+        let errors = [
+            AppError::Unauthorized(String::new()),
+            AppError::NotFound(String::new()),
+            AppError::Conflict(String::new()),
+            AppError::PreconditionFailed(String::new()),
+            AppError::PreconditionRequired(String::new()),
+            AppError::TooManyRequests { limit: 1, retry_after: 1 },
+            AppError::Internal(String::new()),
+            AppError::Forbidden(String::new()),
+        ];
+        for err in errors {
+            assert_eq!(HttpError::for_status(err.status_code(), None).code, err.code(), "{}", err);
+        }
+    }
+
+    #[actix_web::test]
+    async fn json_shape() {
+        let (status, json) = body(AppError::NotFound("course 1".to_string())).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(json, serde_json::json!({"code": "ACTIX_000003", "msg": "Not found: course 1"}));
+
+        let mut errors = ValidationErrors::new();
+        let mut error = ValidationError::new("length");
+        error.message = Some("name is too long".into());
+        errors.add("name", error);
+        let (status, json) = body(AppError::Validation(errors)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(json["code"], "ACTIX_000001");
+        assert_eq!(json["msg"], "validation failed");
+        assert_eq!(json["fields"], serde_json::json!({"name": ["name is too long"]}));
+    }
+
+    #[actix_web::test]
+    async fn database_details_stay_out_of_the_body() {
+        for kind in [ErrorKind::UniqueViolation, ErrorKind::ForeignKeyViolation] {
+            let err = AppError::from(sqlx::Error::Database(Box::new(Violation(kind))));
+            let (status, json) = body(err).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+            let expected = serde_json::json!({"code": "ACTIX_000004", "msg": "Conflict: conflicts with existing data"});
+            assert_eq!(json, expected);
+        }
+
+        let (status, json) = body(AppError::from(sqlx::Error::Database(Box::new(Violation(ErrorKind::Other))))).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(json, serde_json::json!({"code": "ACTIX_000005", "msg": "database error"}));
+    }
+}
@@ -1 +1,4 @@
-pub mod user_error;
\ No newline at end of file
+pub mod user_error;
+pub mod app_error;
+
+pub use self::app_error::{AppError, HttpError};
@@ -0,0 +1,56 @@
+//! [`KafkaPublisher`], built with the `kafka` feature.
+use std::time::Duration;
+
+use async_trait::async_trait;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::ClientConfig;
+
+use super::{EnrollmentEvent, EventPublisher};
+use crate::conf::config;
+use crate::utils::request_id::REQUEST_ID_HEADER;
+
+/// how long a send waits for the broker before it fails
+const MESSAGE_TIMEOUT_MS: &str = "5000";
+
+/// Sends the events as JSON to `events.topic`, keyed by course so the events of a course
+/// stay in order.
+pub struct KafkaPublisher {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaPublisher {
+    pub fn from_conf(conf: &config::Events) -> Result<KafkaPublisher, String> {
+        let brokers = conf.brokers.clone().filter(|b| !b.is_empty())
+            .ok_or_else(|| "events.brokers is required for the kafka sink".to_string())?;
+        let topic = conf.topic.clone().filter(|t| !t.is_empty())
+            .ok_or_else(|| "events.topic is required for the kafka sink".to_string())?;
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .set("message.timeout.ms", MESSAGE_TIMEOUT_MS)
+            .create()
+            .map_err(|e| format!("kafka producer {}: {}", brokers, e))?;
+        Ok(KafkaPublisher { producer, topic })
+    }
+}
+
+#[async_trait]
+impl EventPublisher for KafkaPublisher {
+    async fn publish(&self, event: &EnrollmentEvent) -> Result<(), String> {
+        let payload = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+        let mut headers = OwnedHeaders::new();
+        if let Some(request_id) = event.request_id.as_deref() {
+            headers = headers.insert(Header { key: REQUEST_ID_HEADER, value: Some(request_id) });
+        }
+        let record = FutureRecord::to(&self.topic)
+            .key(&event.enrollment.course_id)
+            .payload(&payload)
+            .headers(headers);
+        self.producer
+            .send(record, Duration::from_secs(0))
+            .await
+            .map(|_| ())
+            .map_err(|(e, _)| e.to_string())
+    }
+}
@@ -0,0 +1,119 @@
+//! Domain events for other services, published after the change is stored.
+//!
+//! The `[events]` section picks the [`EventPublisher`]: [`LogPublisher`] writes one JSON
+//! object per event to the [`TARGET`] log target, [`kafka::KafkaPublisher`] sends them to a
+//! topic and is only built with the `kafka` feature. Events carry the id of the request
+//! that caused them, the Kafka publisher also sends it as the `x-request-id` header.
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{Local, NaiveDateTime};
+use serde::Serialize;
+
+use crate::conf::config;
+use crate::model::{Enrollment, EnrollmentStatus};
+use crate::utils::request_id;
+
+#[cfg(feature = "kafka")]
+pub mod kafka;
+
+/// Log target of [`LogPublisher`].
+pub const TARGET: &str = "events";
+/// how long [`publish_all`] waits for one event before giving up on it
+pub const PUBLISH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What happened to an enrollment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnrollmentEventKind {
+    Enrolled,
+    Waitlisted,
+    /// moved from the waitlist into a free seat
+    Promoted,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EnrollmentEvent {
+    pub kind: EnrollmentEventKind,
+    /// state after the change, before it for [`EnrollmentEventKind::Cancelled`]
+    pub enrollment: Enrollment,
+    pub request_id: Option<String>,
+    pub at: NaiveDateTime,
+}
+
+impl EnrollmentEvent {
+    /// Event of the current request.
+    pub fn new(kind: EnrollmentEventKind, enrollment: Enrollment) -> EnrollmentEvent {
+        EnrollmentEvent {
+            kind,
+            enrollment,
+            request_id: request_id::current().map(|id| id.to_string()),
+            at: Local::now().naive_local(),
+        }
+    }
+
+    /// `Enrolled` or `Waitlisted`, after the status of a new enrollment.
+    pub fn created(enrollment: Enrollment) -> EnrollmentEvent {
+        let kind = match enrollment.status {
+            EnrollmentStatus::Enrolled => EnrollmentEventKind::Enrolled,
+            EnrollmentStatus::Waitlisted => EnrollmentEventKind::Waitlisted,
+        };
+        EnrollmentEvent::new(kind, enrollment)
+    }
+}
+
+/// Sends events, registered as `web::Data<dyn EventPublisher>`.
+#[async_trait]
+pub trait EventPublisher: Send + Sync {
+    async fn publish(&self, event: &EnrollmentEvent) -> Result<(), String>;
+}
+
+/// Writes the events to the [`TARGET`] log target.
+pub struct LogPublisher;
+
+#[async_trait]
+impl EventPublisher for LogPublisher {
+    async fn publish(&self, event: &EnrollmentEvent) -> Result<(), String> {
+        let json = serde_json::to_string(event).map_err(|e| e.to_string())?;
+        info!(target: TARGET, "{}", json);
+        Ok(())
+    }
+}
+
+/// Publisher of the `sink` in `conf`.
+pub fn from_conf(conf: &config::Events) -> Result<Arc<dyn EventPublisher>, String> {
+    match conf.sink.as_deref().unwrap_or("log") {
+        "log" => Ok(Arc::new(LogPublisher)),
+        #[cfg(feature = "kafka")]
+        "kafka" => Ok(Arc::new(kafka::KafkaPublisher::from_conf(conf)?)),
+        sink => Err(format!("events.sink {} is not supported by this build", sink)),
+    }
+}
+
+/// Publishes `events` in order on a task of its own, so a slow or unreachable sink does not
+/// hold up the response. The change they describe is already stored, so failures and sends
+/// that take longer than [`PUBLISH_TIMEOUT`] are only logged.
+pub fn publish_all(publisher: Arc<dyn EventPublisher>, events: Vec<EnrollmentEvent>) {
+    if events.is_empty() {
+        return;
+    }
+    let publish = async move {
+        for event in events {
+            let error = match tokio::time::timeout(PUBLISH_TIMEOUT, publisher.publish(&event)).await {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => e,
+                Err(_) => format!("timed out after {:?}", PUBLISH_TIMEOUT),
+            };
+            error!(
+                "publishing {:?} of enrollment {} failed: {}",
+                event.kind, event.enrollment.id, error,
+            );
+        }
+    };
+    match request_id::current() {
+        Some(id) => actix_web::rt::spawn(request_id::scope(id, publish)),
+        None => actix_web::rt::spawn(publish),
+    };
+}
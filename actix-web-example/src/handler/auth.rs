@@ -0,0 +1,230 @@
+//! Accounts and tokens under `/auth`.
+//!
+//! Passwords are stored as argon2 hashes. A login hands out a short lived access token,
+//! accepted by [`crate::middleware::Jwt`], a signing key for [`crate::middleware::Sign`]
+//! that expires with it, and a refresh token. Refresh tokens are single use: every refresh
+//! returns a new one of the same login, and a token presented a second time revokes the
+//! whole login since one of the copies was stolen. Logout revokes the login as well, access
+//! tokens stay valid until they expire. After `auth.max_failed_logins` failed logins in a
+//! row the account is locked for `auth.lockout` seconds.
+//!
+//! Registered accounts are students. Admins change roles through
+//! `PUT /app/users/{user_id}/role`, the first admin is created with the `create-admin`
+//! subcommand, see [`create_user`].
+use actix_web::{post, put, web, HttpResponse};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use chrono::{Duration, Local, NaiveDateTime};
+use data_encoding::{BASE64URL_NOPAD, HEXLOWER};
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use ring::digest;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::conf::config;
+use crate::error::AppError;
+use crate::handler::teacher::{self, TeacherRepo};
+use crate::middleware::{self, Claims, JwtIssuer, Principal, RequireRole, ADMINS};
+use crate::model::{self, RefreshToken, Role, TokenPair, User};
+use crate::repository::UserRepository;
+
+/// Account storage shared by the handlers, see [`UserRepository`].
+pub type UserRepo = web::Data<dyn UserRepository>;
+
+const DEFAULT_ACCESS_TTL: u64 = 900;
+const DEFAULT_REFRESH_TTL: u64 = 14 * 24 * 3600;
+const DEFAULT_MAX_FAILED_LOGINS: u32 = 5;
+const DEFAULT_LOCKOUT: u64 = 900;
+
+/// verified for unknown usernames, so they take as long as wrong passwords
+static DUMMY_HASH: Lazy<String> = Lazy::new(|| hash_password("dummy password").unwrap_or_default());
+
+#[post("/register")]
+pub async fn register(repo: UserRepo, info: web::Json<model::Register>) -> Result<HttpResponse, AppError> {
+    let user = create_user(repo.get_ref(), info.into_inner(), Role::Student).await?;
+    info!("user registered: {} {}", user.id, user.username);
+    Ok(HttpResponse::Created().json(user))
+}
+
+/// Validates and stores a new account with `role`. Besides [`register`] it creates the first
+/// admin, which no request can do.
+pub async fn create_user(repo: &dyn UserRepository, info: model::Register, role: Role) -> Result<User, AppError> {
+    info.validate()?;
+    let password_hash = web::block(move || hash_password(&info.password)).await
+        .map_err(|e| AppError::Internal(e.to_string()))??;
+    repo.create(&User {
+        id: String::new(),
+        username: info.username,
+        password_hash,
+        role,
+        teacher_id: None,
+        failed_logins: 0,
+        locked_until: None,
+        created_at: None,
+        updated_at: None,
+    }).await
+}
+
+/// Changes the role of an account. Access tokens already handed out keep the old role until
+/// they expire, the next refresh carries the new one.
+#[put("/users/{user_id}/role", wrap = "RequireRole::any(ADMINS)")]
+pub async fn set_role(
+    repo: UserRepo,
+    teachers: TeacherRepo,
+    user_id: web::Path<String>,
+    info: web::Json<model::RoleChange>,
+    principal: Principal,
+) -> Result<HttpResponse, AppError> {
+    info.validate()?;
+    if let Some(teacher_id) = info.teacher_id {
+        teacher::check_exists(&teachers, teacher_id).await?;
+    }
+    let user = repo.set_role(&user_id, info.role, info.teacher_id).await?;
+    info!("user {} is now {} {:?}, set by: {}", user.id, user.role, user.teacher_id, principal.claims.sub);
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[post("/login")]
+pub async fn login(
+    repo: UserRepo,
+    issuer: web::Data<JwtIssuer>,
+    info: web::Json<model::Login>,
+) -> Result<HttpResponse, AppError> {
+    let conf = config::GLOBAL_CONFIG.load().auth.clone();
+    let max_failed = conf.max_failed_logins.unwrap_or(DEFAULT_MAX_FAILED_LOGINS);
+    let user = repo.find_by_username(&info.username).await?;
+    let now = Local::now().naive_local();
+    if let Some(until) = user.as_ref().and_then(|u| u.locked_until).filter(|until| *until > now) {
+        warn!("login refused, {} is locked until {}", info.username, until);
+        let retry_after = (until - now).num_seconds().max(1) as u64;
+        return Err(AppError::TooManyRequests { limit: max_failed, retry_after });
+    }
+
+    let hash = user.as_ref().map_or_else(|| DUMMY_HASH.clone(), |u| u.password_hash.clone());
+    let password = info.into_inner().password;
+    let verified = web::block(move || verify_password(&hash, &password)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let user = match user {
+        Some(user) if verified => user,
+        Some(user) => {
+            let lockout = Duration::seconds(conf.lockout.unwrap_or(DEFAULT_LOCKOUT) as i64);
+            repo.login_failed(&user.id, i64::from(max_failed), now + lockout).await?;
+            warn!("login failed: {}", user.username);
+            return Err(AppError::Unauthorized("invalid username or password".to_string()));
+        }
+        None => return Err(AppError::Unauthorized("invalid username or password".to_string())),
+    };
+    if user.failed_logins > 0 || user.locked_until.is_some() {
+        repo.login_succeeded(&user.id).await?;
+    }
+
+    let family = Uuid::new_v4().to_string();
+    let expires_at = now + Duration::seconds(conf.refresh_ttl.unwrap_or(DEFAULT_REFRESH_TTL) as i64);
+    let tokens = issue(&repo, &issuer, &conf, &user, family, expires_at).await?;
+    info!("login: {} {}", user.id, user.username);
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Exchanges a refresh token for new tokens, the old refresh token is used up.
+#[post("/refresh")]
+pub async fn refresh(
+    repo: UserRepo,
+    issuer: web::Data<JwtIssuer>,
+    info: web::Json<model::RefreshRequest>,
+) -> Result<HttpResponse, AppError> {
+    let conf = config::GLOBAL_CONFIG.load().auth.clone();
+    let token = repo.use_refresh_token(&token_hash(&info.refresh_token)).await?
+        .ok_or_else(|| AppError::Unauthorized("invalid refresh token".to_string()))?;
+    if token.revoked {
+        warn!("refresh token reused, revoking login {} of user {}", token.family, token.user_id);
+        repo.revoke_family(&token.family).await?;
+        return Err(AppError::Unauthorized("refresh token was already used".to_string()));
+    }
+    if token.expires_at <= Local::now().naive_local() {
+        return Err(AppError::Unauthorized("refresh token expired".to_string()));
+    }
+    let user = repo.get(&token.user_id).await?
+        .ok_or_else(|| AppError::Unauthorized("invalid refresh token".to_string()))?;
+
+    let tokens = issue(&repo, &issuer, &conf, &user, token.family, token.expires_at).await?;
+    Ok(HttpResponse::Ok().json(tokens))
+}
+
+/// Revokes the login of a refresh token, unknown tokens are ignored.
+#[post("/logout")]
+pub async fn logout(repo: UserRepo, info: web::Json<model::RefreshRequest>) -> Result<HttpResponse, AppError> {
+    if let Some(token) = repo.use_refresh_token(&token_hash(&info.refresh_token)).await? {
+        repo.revoke_family(&token.family).await?;
+        info!("logout: user {}, login {}", token.user_id, token.family);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Access token and signing key for `user` and a new refresh token of the login `family`.
+async fn issue(
+    repo: &UserRepo,
+    issuer: &JwtIssuer,
+    conf: &config::Auth,
+    user: &User,
+    family: String,
+    expires_at: NaiveDateTime,
+) -> Result<TokenPair, AppError> {
+    let ttl = conf.access_ttl.unwrap_or(DEFAULT_ACCESS_TTL);
+    let now = Local::now().timestamp() as u64;
+    let access_token = issuer.issue(Claims {
+        sub: user.id.clone(),
+        exp: now + ttl,
+        nbf: None,
+        iat: Some(now),
+        iss: None,
+        aud: None,
+        role: Some(user.role),
+        teacher_id: user.teacher_id,
+    }).map_err(AppError::Internal)?;
+
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let refresh_token = BASE64URL_NOPAD.encode(&bytes);
+    repo.add_refresh_token(&RefreshToken {
+        hash: token_hash(&refresh_token),
+        user_id: user.id.clone(),
+        family,
+        expires_at,
+        revoked: false,
+    }).await?;
+
+    let sign_key = middleware::session_key((now + ttl) as i64);
+    Ok(TokenPair {
+        access_token,
+        token_type: "Bearer".to_string(),
+        expires_in: ttl,
+        refresh_token,
+        sign_client: sign_key.client,
+        sign_secret: sign_key.secret,
+    })
+}
+
+fn hash_password(password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| AppError::Internal(format!("password hash: {}", e)))
+}
+
+fn verify_password(hash: &str, password: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(e) => {
+            error!("unreadable password hash: {}", e);
+            false
+        }
+    }
+}
+
+/// refresh tokens are stored as their hex SHA-256
+fn token_hash(token: &str) -> String {
+    HEXLOWER.encode(digest::digest(&digest::SHA256, token.as_bytes()).as_ref())
+}
@@ -1,5 +1,4 @@
 use std::collections::HashMap;
-use std::sync::Mutex;
 
 use actix_web::{
     get,
@@ -13,6 +12,8 @@ use actix_web::{
 use actix_web_lab::respond::Html;
 use askama::Template;
 
+use crate::utils::metrics;
+
 #[derive(Template)]
 #[template(path = "user.html")]
 struct UserTemplate<'a> {
@@ -58,18 +59,8 @@ pub async fn greet(req: HttpRequest) -> impl Responder {
     format!("Hello {}!", &name)
 }
 
-pub struct AppStateWithCounter {
-    pub counter: Mutex<i32>, // <- Mutex is necessary to mutate safely across threads
-}
-
-pub fn new_counter() -> web::Data<AppStateWithCounter> {
-    web::Data::new(AppStateWithCounter {
-        counter: Mutex::new(0),
-    })
-}
-
-pub async fn state(data: web::Data<AppStateWithCounter>) -> impl Responder {
-    let mut counter = data.counter.lock().unwrap(); // <- get counter's MutexGuard
-    *counter += 1; // <- access counter inside MutexGuard
-    format!("Request number: {}", counter) // <- response with count
+/// Counts its calls in the `state_requests_total` metric.
+pub async fn state() -> impl Responder {
+    metrics::STATE_REQUESTS.inc();
+    format!("Request number: {}", metrics::STATE_REQUESTS.get())
 }
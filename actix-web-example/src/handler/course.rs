@@ -1,178 +1,113 @@
-use crate::conf::config;
+use crate::error::AppError;
+use crate::handler::enrollment::{self, EnrollmentRepo, Events};
+use crate::handler::teacher::{self, TeacherRepo};
+use crate::middleware::{Principal, RequireRole, COURSE_EDITORS};
 use crate::model;
+use crate::repository::CourseRepository;
 
-use std::fmt::Debug;
-
-use actix_web::web::Json;
-use actix_web::{delete, get, post, web, HttpResponse};
-use chrono::{Local, NaiveDateTime};
-use futures::TryStreamExt;
-use serde::{Deserialize, Serialize};
-use sqlx;
-use sqlx::Row;
-use uuid::Uuid;
+use actix_web::{delete, get, http::header, patch, post, web, HttpResponse};
 use validator::Validate;
 
-// macro_rules! ok (($result:expr) => ($result.unwrap()));
-
-async fn gen_courses() -> Result<Vec<model::Course>, &'static str> {
-    let conn = config::SQLITE_CONN.clone();
-    let query = "SELECT * FROM courses";
-    let mut rows = sqlx::query(query).fetch(&conn);
-
-    let mut courses = Vec::new();
-    while let Some(row) = rows.try_next().await.unwrap() {
-        let fmt = "%Y-%m-%d %H:%M:%S";
-        let str_date = row.try_get("time").unwrap();
-        let result = NaiveDateTime::parse_from_str(str_date, fmt);
-        let date_time = result.unwrap();
-
-        let id: String = row.try_get("id").unwrap();
-        // let teacher_id =  row.try_get("teacher_id").unwrap();
-        let name: String = row.try_get("name").unwrap();
-        let description: String = row.try_get("description").unwrap();
-        let format: String = row.try_get("format").unwrap();
-        let structure: String = row.try_get("structure").unwrap();
-        let duration: String = row.try_get("duration").unwrap();
-        let price: f64 = row.try_get("price").unwrap();
-        let language: String = row.try_get("language").unwrap();
-        let level: String = row.try_get("level").unwrap();
-
-        courses.push(model::Course {
-            id: Option::from(id),
-            teacher_id: row.try_get("teacher_id").unwrap_or_default(),
-            name: Option::from(name),
-            time: Option::from(date_time),
-            description: Option::from(description),
-            format: Option::from(format),
-            structure: Option::from(structure),
-            duration: Option::from(duration),
-            price: Option::from(price),
-            language: Option::from(language),
-            level: Option::from(level),
-        })
-    }
-    Ok(courses)
-}
+/// Course storage shared by the handlers, see [`CourseRepository`].
+pub type CourseRepo = web::Data<dyn CourseRepository>;
 
 #[get("/courses")]
-pub async fn get_courses() -> HttpResponse {
-    let r = gen_courses().await.unwrap();
-    HttpResponse::Ok().json(r)
+pub async fn get_courses(repo: CourseRepo, query: web::Query<model::CourseQuery>) -> Result<HttpResponse, AppError> {
+    let r = repo.list(&query).await?;
+    Ok(HttpResponse::Ok().json(r))
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct HttpError {
-    code: String,
-    msg: String,
+#[get("/courses/{course_id}")]
+pub async fn get_course(repo: CourseRepo, course_id: web::Path<String>) -> Result<HttpResponse, AppError> {
+    let course = find(&repo, &course_id).await?;
+    Ok(HttpResponse::Ok().insert_header(etag(&course)).json(course))
 }
 
-#[post("/courses")]
-pub async fn add_courses(info: web::Json<model::Course>) -> HttpResponse {
-    let result = insert_test(info).await;
-    match result {
-        Err(e) => {
-            let msg = HttpError {
-                code: "ACTIX_000001".parse().unwrap(),
-                msg: e.to_string(),
-            };
-            return HttpResponse::BadRequest().json(msg);
-        }
-        _ => {}
-    };
-
-    let r = gen_courses().await.unwrap();
-    HttpResponse::Ok().json(r)
+#[post("/courses", wrap = "RequireRole::any(COURSE_EDITORS)")]
+pub async fn add_courses(
+    repo: CourseRepo,
+    teachers: TeacherRepo,
+    info: web::Json<model::Course>,
+    principal: Principal,
+) -> Result<HttpResponse, AppError> {
+    principal.authorize_course(None, info.teacher_id)?;
+    info!("add course by: {}", principal.claims.sub);
+    info.validate()?;
+    teacher::check_exists(&teachers, info.teacher_id).await?;
+    repo.create(&info).await?;
+
+    let r = repo.list(&model::CourseQuery::default()).await?;
+    Ok(HttpResponse::Ok().json(r))
 }
 
+/// Changes the fields present in the body, PATCH is the only update verb since there is no
+/// full replacement. The `If-Match` header must carry the `ETag`
+/// returned by `GET /courses/{id}` (or `*`), a stale version is answered with 412.
+/// Teachers may neither change the courses of others nor hand theirs over. A new
+/// `capacity` promotes the waitlist into the seats it adds. A body without any field is
+/// answered with 400 and leaves the version as it is.
+#[patch("/courses/{course_id}", wrap = "RequireRole::any(COURSE_EDITORS)")]
+#[allow(clippy::too_many_arguments)] // one per extractor
+pub async fn update_courses(
+    repo: CourseRepo,
+    teachers: TeacherRepo,
+    enrollments: EnrollmentRepo,
+    events: Events,
+    course_id: web::Path<String>,
+    if_match: Option<web::Header<header::IfMatch>>,
+    info: web::Json<model::CoursePatch>,
+    principal: Principal,
+) -> Result<HttpResponse, AppError> {
+    let current = find(&repo, &course_id).await?;
+    principal.authorize_course(Some(&course_id), current.teacher_id)?;
+    if let Some(teacher_id) = info.teacher_id.filter(|t| *t != current.teacher_id) {
+        principal.authorize_course(Some(&course_id), teacher_id)?;
+    }
 
-#[post("/courses/update")]
-pub async fn update_courses(info: web::Json<model::Course>) -> HttpResponse {
-    let result = update_test(info).await;
-    match result {
-        Err(e) => {
-            let msg = HttpError {
-                code: "ACTIX_000001".parse().unwrap(),
-                msg: e.to_string(),
-            };
-            return HttpResponse::BadRequest().json(msg);
+    // an absent header is parsed as an empty list
+    let expected = match if_match.map(|h| h.into_inner()) {
+        None => return Err(AppError::PreconditionRequired("If-Match header is required".to_string())),
+        Some(header::IfMatch::Items(tags)) if tags.is_empty() => {
+            return Err(AppError::PreconditionRequired("If-Match header is required".to_string()));
         }
-        _ => {}
+        Some(header::IfMatch::Any) => None,
+        Some(header::IfMatch::Items(tags)) => Some(
+            tags.iter()
+                .filter(|t| !t.weak)
+                .find_map(|t| t.tag().parse::<i64>().ok())
+                .ok_or_else(|| AppError::PreconditionFailed("If-Match carries no valid version".to_string()))?,
+        ),
     };
+    info.validate()?;
+    if info.is_empty() {
+        return Err(AppError::BadRequest("the patch changes no field".to_string()));
+    }
+    if let Some(teacher_id) = info.teacher_id {
+        teacher::check_exists(&teachers, teacher_id).await?;
+    }
 
-    let r = gen_courses().await.unwrap();
-    HttpResponse::Ok().json(r)
-}
-
-async fn update_test(info: Json<model::Course>) -> Result<(), &'static str> {
-    let conn = config::SQLITE_CONN.clone();
-    let query = "UPDATE courses SET teacher_id=?, name=?, time=?, description=?, format=?, structure=?, duration=?, price=?, language=? WHERE id=?";
-    let _last_id = sqlx::query(query)
-        .bind(info.teacher_id)
-        .bind(info.name.clone().unwrap_or_default().as_str())
-        .bind(info.time.clone().unwrap_or_default().to_string().as_str())
-        .bind(info.description.clone().unwrap_or_default().as_str())
-        .bind(info.format.clone().unwrap_or_default().as_str())
-        .bind(info.structure.clone().unwrap_or_default().as_str())
-        .bind(info.duration.clone().unwrap_or_default().as_str())
-        .bind(info.price.clone().unwrap_or_default())
-        .bind(info.language.clone().unwrap_or_default().as_str())
-        .bind(info.id.clone().unwrap_or_default().as_str())
-        .execute(&conn)
-        .await
-        .unwrap()
-        .last_insert_rowid();
-
-    Ok(())
+    let course = repo.update(&course_id, &info, expected).await?;
+    if info.capacity.is_some() {
+        let promoted = enrollments.promote(&course_id).await?;
+        enrollment::publish_promoted(&events, None, promoted);
+    }
+    Ok(HttpResponse::Ok().insert_header(etag(&course)).json(course))
 }
 
-#[delete("/courses/{course_id}")]
-pub async fn del_courses(course_id: web::Path<String>) -> HttpResponse {
-    delete_test(course_id.into_inner()).await.unwrap();
-    let r = gen_courses().await.unwrap();
-    HttpResponse::Ok().json(r)
+async fn find(repo: &CourseRepo, course_id: &str) -> Result<model::Course, AppError> {
+    repo.get(course_id).await?
+        .ok_or_else(|| AppError::NotFound(format!("course {}", course_id)))
 }
 
-async fn delete_test(course_id: String) -> Result<(), &'static str> {
-    let conn = config::SQLITE_CONN.clone();
-    let query = "DELETE FROM courses WHERE id=?";
-    let _last_id = sqlx::query(query)
-        .bind(course_id)
-        .execute(&conn)
-        .await
-        .unwrap()
-        .last_insert_rowid();
-
-    Ok(())
+fn etag(course: &model::Course) -> header::ETag {
+    header::ETag(header::EntityTag::new_strong(course.version.unwrap_or_default().to_string()))
 }
 
-async fn insert_test(info: Json<model::Course>) -> Result<(), &'static str> {
-    info.validate().unwrap();
-    let conn = config::SQLITE_CONN.clone();
-    let query = "INSERT INTO courses VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)";
-
-    // let mut statement = connection.prepare(query).expect("prepare error");
-    let fmt = "%Y-%m-%d %H:%M:%S";
-    let dft = Local::now().format(fmt);
-    let result = NaiveDateTime::parse_from_str(dft.to_string().as_str(), fmt);
-    let date_time = result.unwrap();
-    let id = Uuid::new_v4();
-
-    let _last_id = sqlx::query(query)
-        .bind(id.to_string().as_str())
-        .bind(info.teacher_id.clone())
-        .bind(info.name.clone().unwrap_or_default().as_str())
-        .bind(date_time.to_string().as_str())
-        .bind(info.description.clone().unwrap_or_default().as_str())
-        .bind(info.format.clone().unwrap_or_default().as_str())
-        .bind(info.structure.clone().unwrap_or_default().as_str())
-        .bind(info.duration.clone().unwrap_or_default().as_str())
-        .bind(info.price.clone().unwrap_or_default())
-        .bind(info.language.clone().unwrap_or_default().as_str())
-        .bind("1")
-        .execute(&conn)
-        .await
-        .unwrap()
-        .last_insert_rowid();
-    Ok(())
+#[delete("/courses/{course_id}", wrap = "RequireRole::any(COURSE_EDITORS)")]
+pub async fn del_courses(repo: CourseRepo, course_id: web::Path<String>, principal: Principal) -> Result<HttpResponse, AppError> {
+    let current = find(&repo, &course_id).await?;
+    principal.authorize_course(Some(&course_id), current.teacher_id)?;
+    repo.delete(&course_id).await?;
+    let r = repo.list(&model::CourseQuery::default()).await?;
+    Ok(HttpResponse::Ok().json(r))
 }
@@ -0,0 +1,62 @@
+//! Enrollments of the caller under `/app`.
+//!
+//! A course with a `capacity` takes students until it is full, later ones are put on its
+//! waitlist and promoted in order when a seat becomes free or the capacity is raised.
+//! Every change is published as an [`EnrollmentEvent`] without waiting for the sink, see
+//! [`events::publish_all`].
+use actix_web::{delete, get, post, web, HttpResponse};
+
+use crate::error::AppError;
+use crate::events::{self, EnrollmentEvent, EnrollmentEventKind, EventPublisher};
+use crate::middleware::Principal;
+use crate::model;
+use crate::repository::EnrollmentRepository;
+
+/// Enrollment storage shared by the handlers, see [`EnrollmentRepository`].
+pub type EnrollmentRepo = web::Data<dyn EnrollmentRepository>;
+/// Publisher of the `[events]` section, see [`crate::events`].
+pub type Events = web::Data<dyn EventPublisher>;
+
+/// Enrolls the caller, answered with 201 and `status` `enrolled` or `waitlisted`.
+#[post("/courses/{course_id}/enrollments")]
+pub async fn enroll(
+    repo: EnrollmentRepo,
+    events: Events,
+    course_id: web::Path<String>,
+    principal: Principal,
+) -> Result<HttpResponse, AppError> {
+    let enrollment = repo.enroll(&course_id, &principal.claims.sub).await?;
+    info!("{} {} in course {}", principal.claims.sub, enrollment.status, course_id);
+    events::publish_all(events.clone().into_inner(), vec![EnrollmentEvent::created(enrollment.clone())]);
+    Ok(HttpResponse::Created().json(enrollment))
+}
+
+/// Cancels the enrollment of the caller, the freed seat goes to the waitlist.
+#[delete("/courses/{course_id}/enrollments")]
+pub async fn unenroll(
+    repo: EnrollmentRepo,
+    events: Events,
+    course_id: web::Path<String>,
+    principal: Principal,
+) -> Result<HttpResponse, AppError> {
+    let (cancelled, promoted) = repo.cancel(&course_id, &principal.claims.sub).await?;
+    info!("{} cancelled course {}, {} promoted", principal.claims.sub, course_id, promoted.len());
+    publish_promoted(&events, Some(cancelled), promoted);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[get("/me/enrollments")]
+pub async fn my_enrollments(repo: EnrollmentRepo, principal: Principal) -> Result<HttpResponse, AppError> {
+    Ok(HttpResponse::Ok().json(repo.list_by_user(&principal.claims.sub).await?))
+}
+
+/// Publishes a cancellation followed by the promotions it caused.
+pub(crate) fn publish_promoted(
+    events: &Events,
+    cancelled: Option<model::Enrollment>,
+    promoted: Vec<model::Enrollment>,
+) {
+    let cancelled = cancelled.map(|e| EnrollmentEvent::new(EnrollmentEventKind::Cancelled, e));
+    let promoted = promoted.into_iter().map(|e| EnrollmentEvent::new(EnrollmentEventKind::Promoted, e));
+    events::publish_all(events.clone().into_inner(), cancelled.into_iter().chain(promoted).collect());
+}
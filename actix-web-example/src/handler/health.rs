@@ -0,0 +1,203 @@
+//! Probes for Kubernetes, served under `/sys/health`.
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use actix_web::{get, web, HttpResponse};
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::AnyPool;
+
+use crate::conf::config;
+use crate::utils::shutdown::{Phase, Shutdown};
+use crate::utils::{scheduler, tls};
+
+/// The database must answer within this time.
+const DB_TIMEOUT: Duration = Duration::from_secs(2);
+/// The ticker job runs every 10 seconds, three missed ticks mean the loop is stuck.
+const SCHEDULER_STALE_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    /// `down` as soon as one check is down
+    pub status: Status,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub checks: Vec<Check>,
+}
+
+/// Liveness, answers as long as the workers serve requests.
+#[get("/health/live")]
+pub async fn live() -> HttpResponse {
+    HttpResponse::Ok().json(Health {
+        status: Status::Up,
+        checks: Vec::new(),
+    })
+}
+
+/// Readiness, 503 while any dependency is down, before the scheduler first ticks
+/// and once a shutdown started.
+#[get("/health/ready")]
+pub async fn ready(shutdown: web::Data<Shutdown>) -> HttpResponse {
+    let tls = config::GLOBAL_CONFIG.load().server.services.iter().flatten().any(config::Address::tls);
+    readiness(shutdown.progress().phase, &config::DB_POOL, scheduler::last_tick(), tls).await
+}
+
+/// [`ready`] for the given state, `tls` adds the certificate check.
+async fn readiness(phase: Phase, pool: &AnyPool, last_tick: Option<u64>, tls: bool) -> HttpResponse {
+    let mut checks = vec![
+        check("shutdown", async { shutdown_check(phase) }).await,
+        check("db", db_check(pool)).await,
+        check("scheduler", async { scheduler_check(last_tick) }).await,
+    ];
+    if tls {
+        checks.push(check("tls", async { tls_check() }).await);
+    }
+
+    let status = if checks.iter().all(|c| c.status == Status::Up) {
+        Status::Up
+    } else {
+        Status::Down
+    };
+    let mut resp = match status {
+        Status::Up => HttpResponse::Ok(),
+        Status::Down => HttpResponse::ServiceUnavailable(),
+    };
+    resp.json(Health { status, checks })
+}
+
+/// Runs one check and times it, `Err` marks it down with the message as detail.
+async fn check<F>(name: &'static str, f: F) -> Check
+    where F: Future<Output=Result<Option<String>, String>>,
+{
+    let start = Instant::now();
+    let result = f.await;
+    let latency_ms = start.elapsed().as_secs_f64() * 1000.0;
+    let (status, detail) = match result {
+        Ok(detail) => (Status::Up, detail),
+        Err(e) => {
+            warn!("health check {} down: {}", name, e);
+            (Status::Down, Some(e))
+        }
+    };
+    Check { name, status, latency_ms, detail }
+}
+
+fn shutdown_check(phase: Phase) -> Result<Option<String>, String> {
+    match phase {
+        Phase::Running => Ok(None),
+        phase => Err(format!("shutting down: {:?}", phase)),
+    }
+}
+
+/// The body is public, the error itself only goes to the log.
+async fn db_check(pool: &AnyPool) -> Result<Option<String>, String> {
+    let ping = sqlx::query("SELECT 1").execute(pool);
+    match tokio::time::timeout(DB_TIMEOUT, ping).await {
+        Ok(Ok(_)) => Ok(None),
+        Ok(Err(e)) => {
+            error!("health check db query failed: {}", e);
+            Err("query failed".to_string())
+        }
+        Err(_) => Err(format!("no answer within {}s", DB_TIMEOUT.as_secs())),
+    }
+}
+
+fn scheduler_check(last_tick: Option<u64>) -> Result<Option<String>, String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    match last_tick {
+        None => Err("no tick yet".to_string()),
+        Some(tick) if now.saturating_sub(tick) > SCHEDULER_STALE_SECS => {
+            Err(format!("last tick {}s ago", now - tick))
+        }
+        Some(tick) => Ok(Some(format!("last tick {}s ago", now.saturating_sub(tick)))),
+    }
+}
+
+fn tls_check() -> Result<Option<String>, String> {
+    let not_after = tls::cert_not_after()?;
+    let days = (not_after - Utc::now()).num_days();
+    if not_after <= Utc::now() {
+        return Err(format!("certificate expired at {}", not_after.to_rfc3339()));
+    }
+    Ok(Some(format!("certificate expires at {}, in {} days", not_after.to_rfc3339(), days)))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::body::MessageBody;
+    use actix_web::http::StatusCode;
+    use serde_json::Value;
+    use sqlx::any::AnyPoolOptions;
+
+    use super::*;
+
+    async fn memory_pool() -> AnyPool {
+        sqlx::any::install_default_drivers();
+        AnyPoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap()
+    }
+
+    fn now() -> Option<u64> {
+        Some(SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs())
+    }
+
+    /// Status and the `status` and `detail` of each check by name.
+    async fn probe(phase: Phase, pool: &AnyPool, last_tick: Option<u64>) -> (StatusCode, Value) {
+        let resp = readiness(phase, pool, last_tick, false).await;
+        let status = resp.status();
+        let body: Value = serde_json::from_slice(&resp.into_body().try_into_bytes().unwrap()).unwrap();
+        let checks = body["checks"].as_array().unwrap().iter()
+            .map(|c| (c["name"].as_str().unwrap().to_string(), serde_json::json!([c["status"], c["detail"]])))
+            .collect();
+        (status, Value::Object(checks))
+    }
+
+    #[actix_web::test]
+    async fn ready_once_running_and_ticking() {
+        let pool = memory_pool().await;
+        let (status, checks) = probe(Phase::Running, &pool, now()).await;
+        assert_eq!(status, StatusCode::OK, "{}", checks);
+        assert_eq!(checks["db"][0], "up");
+    }
+
+    #[actix_web::test]
+    async fn unavailable_until_the_scheduler_ticks() {
+        let pool = memory_pool().await;
+        let (status, checks) = probe(Phase::Running, &pool, None).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(checks["scheduler"], serde_json::json!(["down", "no tick yet"]));
+        assert_eq!(checks["shutdown"][0], "up");
+    }
+
+    #[actix_web::test]
+    async fn unavailable_while_draining() {
+        let pool = memory_pool().await;
+        let (status, checks) = probe(Phase::Draining, &pool, now()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(checks["shutdown"], serde_json::json!(["down", "shutting down: Draining"]));
+        assert_eq!(checks["scheduler"][0], "up");
+    }
+
+    #[actix_web::test]
+    async fn database_errors_stay_out_of_the_body() {
+        let pool = memory_pool().await;
+        pool.close().await;
+        let (status, checks) = probe(Phase::Running, &pool, now()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(checks["db"], serde_json::json!(["down", "query failed"]));
+    }
+}
@@ -0,0 +1,14 @@
+use actix_web::{get, HttpRequest, HttpResponse};
+
+use crate::error::AppError;
+use crate::handler::stop;
+use crate::utils::metrics;
+
+/// Prometheus scrape endpoint, same credential as [`stop::stop`].
+#[get("/metrics")]
+pub async fn get_metrics(req: HttpRequest) -> Result<HttpResponse, AppError> {
+    stop::authorize(&req)?;
+    Ok(HttpResponse::Ok()
+        .content_type(prometheus::TEXT_FORMAT)
+        .body(metrics::render()))
+}
@@ -2,6 +2,12 @@ pub mod basic;
 pub mod user;
 pub mod err_handlers;
 pub mod course;
+pub mod stop;
+pub mod health;
+pub mod metrics;
+pub mod auth;
+pub mod teacher;
+pub mod enrollment;
 
 pub use self::user::*;
 pub use self::basic::*;
@@ -1,28 +1,47 @@
-use actix_web::{dev::ServerHandle, post, web, HttpResponse};
+use actix_web::{get, http::header, post, web, HttpRequest, HttpResponse};
 use actix_web_lab::extract::Path;
-use parking_lot::Mutex;
+use ring::constant_time;
 
+use crate::conf::config;
+use crate::error::AppError;
+use crate::utils::shutdown::Shutdown;
+
+/// Starts a shutdown, `graceful` lets in-flight requests finish first.
+///
+/// Requires `Authorization: Bearer <shutdown.admin_token>`, answers 202 with the progress.
 #[post("/stop/{graceful}")]
-pub async fn stop(Path(graceful): Path<bool>, stop_handle: web::Data<StopHandle>) -> HttpResponse {
+pub async fn stop(
+    req: HttpRequest,
+    Path(graceful): Path<bool>,
+    shutdown: web::Data<Shutdown>,
+) -> Result<HttpResponse, AppError> {
+    authorize(&req)?;
     info!("graceful: {:?}", graceful);
-    stop_handle.stop(graceful);
-    HttpResponse::NoContent().finish()
+    Ok(HttpResponse::Accepted().json(shutdown.stop(graceful)))
 }
 
-#[derive(Default)]
-pub struct StopHandle {
-    inner: Mutex<Option<ServerHandle>>,
+/// Progress of the shutdown, same credential as [`stop`].
+#[get("/stop")]
+pub async fn progress(req: HttpRequest, shutdown: web::Data<Shutdown>) -> Result<HttpResponse, AppError> {
+    authorize(&req)?;
+    Ok(HttpResponse::Ok().json(shutdown.progress()))
 }
 
-impl StopHandle {
-    /// Sets the server handle to stop.
-    pub(crate) fn register(&self, handle: ServerHandle) {
-        *self.inner.lock() = Some(handle);
-    }
-
-    /// Sends stop signal through contained server handle.
-    pub(crate) fn stop(&self, graceful: bool) {
-        #[allow(clippy::let_underscore_future)]
-            let _ = self.inner.lock().as_ref().unwrap().stop(graceful);
+/// Checks `Authorization: Bearer <shutdown.admin_token>` of the `/sys` admin routes, which are
+/// all disabled while the token is empty.
+pub(crate) fn authorize(req: &HttpRequest) -> Result<(), AppError> {
+    let expected = config::GLOBAL_CONFIG.load().shutdown.admin_token.clone().unwrap_or_default();
+    if expected.is_empty() {
+        warn!("{} rejected, shutdown.admin_token is not set", req.path());
+        return Err(AppError::Unauthorized("admin endpoints are disabled".to_string()));
     }
+    let token = req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    constant_time::verify_slices_are_equal(token.as_bytes(), expected.as_bytes()).map_err(|_| {
+        warn!("{} rejected, invalid admin token", req.path());
+        AppError::Unauthorized("invalid admin token".to_string())
+    })
 }
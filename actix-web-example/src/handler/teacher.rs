@@ -0,0 +1,88 @@
+//! Teachers under `/app/teachers`, the `teacher_id` of every course must name one of them.
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::error::AppError;
+use crate::handler::course::CourseRepo;
+use crate::middleware::{Principal, RequireRole, ADMINS};
+use crate::model;
+use crate::repository::TeacherRepository;
+
+/// Teacher storage shared by the handlers, see [`TeacherRepository`].
+pub type TeacherRepo = web::Data<dyn TeacherRepository>;
+
+#[get("/teachers")]
+pub async fn get_teachers(repo: TeacherRepo) -> Result<HttpResponse, AppError> {
+    Ok(HttpResponse::Ok().json(repo.list().await?))
+}
+
+#[get("/teachers/{teacher_id}")]
+pub async fn get_teacher(repo: TeacherRepo, teacher_id: web::Path<i64>) -> Result<HttpResponse, AppError> {
+    Ok(HttpResponse::Ok().json(find(&repo, *teacher_id).await?))
+}
+
+/// Courses of one teacher, takes the paging and sorting parameters of `GET /courses`.
+#[get("/teachers/{teacher_id}/courses")]
+pub async fn get_teacher_courses(
+    repo: TeacherRepo,
+    courses: CourseRepo,
+    teacher_id: web::Path<i64>,
+    query: web::Query<model::CourseQuery>,
+) -> Result<HttpResponse, AppError> {
+    find(&repo, *teacher_id).await?;
+    let query = model::CourseQuery { teacher_id: Some(*teacher_id), ..query.into_inner() };
+    Ok(HttpResponse::Ok().json(courses.list(&query).await?))
+}
+
+#[post("/teachers", wrap = "RequireRole::any(ADMINS)")]
+pub async fn add_teacher(
+    repo: TeacherRepo,
+    info: web::Json<model::Teacher>,
+    principal: Principal,
+) -> Result<HttpResponse, AppError> {
+    info.validate()?;
+    let teacher = repo.create(&info).await?;
+    info!("teacher {} added by: {}", teacher.id.unwrap_or_default(), principal.claims.sub);
+    Ok(HttpResponse::Created().json(teacher))
+}
+
+#[patch("/teachers/{teacher_id}", wrap = "RequireRole::any(ADMINS)")]
+pub async fn update_teacher(
+    repo: TeacherRepo,
+    teacher_id: web::Path<i64>,
+    info: web::Json<model::TeacherPatch>,
+) -> Result<HttpResponse, AppError> {
+    info.validate()?;
+    Ok(HttpResponse::Ok().json(repo.update(*teacher_id, &info).await?))
+}
+
+/// Answered with 409 while the teacher has courses, `?cascade=true` deletes them as well.
+#[delete("/teachers/{teacher_id}", wrap = "RequireRole::any(ADMINS)")]
+pub async fn del_teacher(
+    repo: TeacherRepo,
+    teacher_id: web::Path<i64>,
+    query: web::Query<model::TeacherDelete>,
+    principal: Principal,
+) -> Result<HttpResponse, AppError> {
+    let cascade = query.cascade.unwrap_or(false);
+    repo.delete(*teacher_id, cascade).await?;
+    info!("teacher {} deleted by: {}, cascade: {}", teacher_id, principal.claims.sub, cascade);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+async fn find(repo: &TeacherRepo, teacher_id: i64) -> Result<model::Teacher, AppError> {
+    repo.get(teacher_id).await?
+        .ok_or_else(|| AppError::NotFound(format!("teacher {}", teacher_id)))
+}
+
+/// Fails like a validator on the `teacher_id` field when no such teacher exists.
+pub(crate) async fn check_exists(repo: &TeacherRepo, teacher_id: i64) -> Result<(), AppError> {
+    if repo.get(teacher_id).await?.is_some() {
+        return Ok(());
+    }
+    let mut error = ValidationError::new("teacher_id");
+    error.message = Some("unknown teacher".into());
+    let mut errors = ValidationErrors::new();
+    errors.add("teacher_id", error);
+    Err(AppError::Validation(errors))
+}
@@ -1,10 +1,8 @@
 use std::collections::HashMap;
 
 use actix_web::{
-    error,
     post,
     web,
-    Error,
     // Result,
     HttpRequest,
     HttpResponse,
@@ -15,6 +13,8 @@ use serde::{Deserialize, Serialize};
 // use serde_json::value::Value;
 use serde_json::{json, Value};
 
+use crate::error::AppError;
+
 // #[derive(Deserialize)]
 #[derive(Serialize, Deserialize)]
 pub struct Info {
@@ -29,12 +29,12 @@ pub struct UserInfo {
 }
 
 #[post("/json")]
-pub async fn json_handler(info: web::Json<UserInfo>) -> HttpResponse {
-    HttpResponse::Ok().json(info.0)
+pub async fn json_handler(info: web::Json<UserInfo>) -> Result<HttpResponse, AppError> {
+    Ok(HttpResponse::Ok().json(info.0))
 }
 
 #[post("/extract_json")]
-pub async fn extract_json_handler(item: web::Json<UserInfo>, req: HttpRequest) -> HttpResponse {
+pub async fn extract_json_handler(item: web::Json<UserInfo>, req: HttpRequest) -> Result<HttpResponse, AppError> {
     let mut header_map: HashMap<&str, &str> = HashMap::new();
     for (k, v) in req.headers().iter() {
         if let Ok(v1) = v.to_str() {
@@ -43,14 +43,15 @@ pub async fn extract_json_handler(item: web::Json<UserInfo>, req: HttpRequest) -
     }
 
     info!("request headers: {:?}", json!(header_map).to_string());
-    HttpResponse::Ok().json(item.0) // <- send json response
+    Ok(HttpResponse::Ok().json(item.0)) // <- send json response
 }
 
 const MAX_SIZE: usize = 262_144; // max payload size is 256k
 
 #[post("/bytes")]
-pub async fn bytes_handler(body: web::Bytes) -> Result<HttpResponse, Error> {
-    let result = json::parse(std::str::from_utf8(&body).unwrap()); // return Result
+pub async fn bytes_handler(body: web::Bytes) -> Result<HttpResponse, AppError> {
+    let text = std::str::from_utf8(&body).map_err(|e| AppError::BadRequest(e.to_string()))?;
+    let result = json::parse(text); // return Result
     let res = match result {
         Ok(v) => v,
         Err(e) => json::object! {"err" => e.to_string() },
@@ -61,13 +62,13 @@ pub async fn bytes_handler(body: web::Bytes) -> Result<HttpResponse, Error> {
 }
 
 #[post("/payload")]
-pub async fn payload_handler(mut payload: web::Payload) -> Result<HttpResponse, Error> {
+pub async fn payload_handler(mut payload: web::Payload) -> Result<HttpResponse, AppError> {
     let mut body = web::BytesMut::new();
     while let Some(chunk) = payload.next().await {
         let chunk = chunk?;
         // limit max size of in-memory payload
         if (body.len() + chunk.len()) > MAX_SIZE {
-            return Err(error::ErrorBadRequest("overflow"));
+            return Err(AppError::BadRequest("overflow".to_string()));
         }
         body.extend_from_slice(&chunk);
     }
@@ -5,9 +5,11 @@ extern crate log4rs;
 
 pub mod conf;
 pub mod error;
+pub mod events;
 pub mod handler;
 pub mod log;
 pub mod middleware;
 pub mod model;
+pub mod repository;
 pub mod router;
 pub mod utils;
@@ -3,103 +3,218 @@ extern crate log;
 extern crate log4rs;
 extern crate lazy_static;
 
-use actix_cors::Cors;
+use std::sync::Arc;
+
+use tokio_cron_scheduler::JobScheduler;
 use actix_web::{
     App,
     HttpServer,
-    http::header,
     web,
 };
 
 use actix_web_example::{
+    events,
     middleware,
-    handler::{basic},
+    model,
+    handler,
+    handler::err_handlers::ErrorHandlers,
     router::routes,
+    repository::{
+        CourseRepository, EnrollmentRepository, SqlCourseRepository, SqlEnrollmentRepository, SqlTeacherRepository,
+        SqlUserRepository, TeacherRepository, UserRepository,
+    },
+    conf::{config, migrate, watch},
+    utils::{
+        log as sys_log,
+        scheduler,
+        scheduler::JobTrait,
+        counter::Iterator,
+        counter,
+        tls,
+        shutdown::Shutdown,
+    },
 };
 
-pub mod utils;
-pub mod conf;
-pub mod model;
-pub mod handler;
-
-use crate::utils::{
-    log as sys_log,
-    scheduler,
-    scheduler::JobTrait,
-    counter::Iterator,
-    counter,
-    tls,
-    // signal,
-};
-use crate::conf::config;
-use crate::handler::StopHandle;
-
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
     // init log
     sys_log::init().unwrap();
+    // --config <path>, APP_CONFIG and APP__* overrides
+    if let Err(e) = config::init() {
+        error!("config error: {}", e);
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("config error: {}", e)));
+    }
+    let args = positional_args();
+    match args.get(1).map(String::as_str) {
+        Some("migrate") => return migrate(&args[2..]).await,
+        Some("create-admin") => return create_admin(&args[2..]).await,
+        _ => {}
+    }
     counter().await;
     // init db
     config::init_db().await;
-    scheduler_job().await;
-    run().await
+    let shutdown = web::Data::new(Shutdown::default());
+    shutdown.register_scheduler(scheduler_job().await);
+    run(shutdown).await
+}
 
-    // tokio::select! {
-    //     _ = signal::shutdown() => {
-    //         info!("Received shutdown signal");
-    //     }
-    //     // _ = shutdown_rx.recv() => {
-    //     //     info!("Received shutdown via admin interface");
-    //     // }
-    // }
-    // Ok(())
+/// process arguments without `--config <path>`
+fn positional_args() -> Vec<String> {
+    let mut args = Vec::new();
+    let mut iter = std::env::args();
+    while let Some(arg) = iter.next() {
+        if arg == "--config" {
+            iter.next();
+        } else if !arg.starts_with("--config=") {
+            args.push(arg);
+        }
+    }
+    args
 }
 
-async fn run() -> std::io::Result<()> {
-    let conf = config::GLOBAL_CONFIG.lock().unwrap();
-    info!("GLOBAL_CONFIG: {:?}",  conf.clone());
-    let counter = basic::new_counter();
-    let stop_handle = web::Data::new(StopHandle::default());
+/// `actix-web-example migrate up|down [version]|status`
+async fn migrate(args: &[String]) -> std::io::Result<()> {
+    match args.first().map(String::as_str) {
+        Some("up") => config::init_db().await,
+        Some("down") => {
+            let target = match args.get(1) {
+                Some(v) => Some(v.parse::<i64>()
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?),
+                None => None,
+            };
+            migrate::down(&config::DB_POOL, target).await.map_err(std::io::Error::other)?;
+        }
+        Some("status") => {
+            for m in migrate::status(&config::DB_POOL).await.map_err(std::io::Error::other)? {
+                println!("{:<16} {:<8} {}", m.version, if m.applied { "applied" } else { "pending" }, m.description);
+            }
+        }
+        _ => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "usage: migrate up|down [version]|status",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// `actix-web-example create-admin <username>`, reads the password from stdin so it stays out
+/// of the shell history and the process list.
+async fn create_admin(args: &[String]) -> std::io::Result<()> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidInput, msg);
+    let username = args.first().ok_or_else(|| invalid("usage: create-admin <username>".to_string()))?;
+    eprint!("password for {}: ", username);
+    let mut password = String::new();
+    std::io::stdin().read_line(&mut password)?;
+    let password = password.trim_end_matches(['\r', '\n']).to_string();
+
+    config::init_db().await;
+    let repo = SqlUserRepository::new(config::DB_POOL.clone());
+    let info = model::Register { username: username.clone(), password };
+    let user = handler::auth::create_user(&repo, info, model::Role::Admin).await
+        .map_err(|e| invalid(format!("create-admin: {}", e)))?;
+    println!("admin {} created: {}", user.username, user.id);
+    Ok(())
+}
+
+async fn run(shutdown: web::Data<Shutdown>) -> std::io::Result<()> {
+    // snapshot for the bind addresses, reloads swap GLOBAL_CONFIG without touching it
+    let conf = config::GLOBAL_CONFIG.load_full();
+    info!("GLOBAL_CONFIG: {:?}",  conf);
+    watch::spawn();
+    let courses: web::Data<dyn CourseRepository> =
+        web::Data::from(Arc::new(SqlCourseRepository::new(config::DB_POOL.clone())) as Arc<dyn CourseRepository>);
+    let teachers: web::Data<dyn TeacherRepository> =
+        web::Data::from(Arc::new(SqlTeacherRepository::new(config::DB_POOL.clone())) as Arc<dyn TeacherRepository>);
+    let enrollments: web::Data<dyn EnrollmentRepository> =
+        web::Data::from(Arc::new(SqlEnrollmentRepository::new(config::DB_POOL.clone())) as Arc<dyn EnrollmentRepository>);
+    let events = events::from_conf(&conf.events).map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("events error: {}", e))
+    })?;
+    let events: web::Data<dyn events::EventPublisher> = web::Data::from(events);
+    let users: web::Data<dyn UserRepository> =
+        web::Data::from(Arc::new(SqlUserRepository::new(config::DB_POOL.clone())) as Arc<dyn UserRepository>);
+    let issuer = middleware::JwtIssuer::from_conf(&conf.jwt).map_err(|e| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("jwt error: {}", e))
+    })?;
+    let issuer = web::Data::new(issuer);
     let mut app = HttpServer::new({
-        let stop_handle = stop_handle.clone();
+        let shutdown = shutdown.clone();
+        let log = conf.log.clone();
         move || App::new()
             .service(web::scope("/sys")
-                .app_data(stop_handle.clone())
-                .service(handler::stop)
+                .app_data(shutdown.clone())
+                .service(handler::stop::stop)
+                .service(handler::stop::progress)
+                .service(handler::health::live)
+                .service(handler::health::ready)
+                .service(handler::metrics::get_metrics)
+                .wrap(ErrorHandlers::api())
+                .wrap(middleware::cors(middleware::CorsScope::Sys))
             )
-            .wrap(cors())
-            .wrap(middleware::AccessLogging::default().log_target("http_log"))
-            .app_data(counter.clone()) // <- register the created data
+            // html pages for the template routes, catches panics the scopes let through
+            .wrap(ErrorHandlers::pages())
+            .wrap(middleware::BodyAudit)
+            .wrap(access_log(&log).log_target("http_log"))
+            // outermost, so the access log and every middleware log line carry the id
+            .wrap(middleware::RequestId)
+            .app_data(courses.clone())
+            .app_data(teachers.clone())
+            .app_data(enrollments.clone())
+            .app_data(events.clone())
+            .app_data(users.clone())
+            .app_data(issuer.clone())
             .configure(routes)
-    });
+    })
+    // client certificates of mTLS connections, see `tls::ClientIdentity`
+    .on_connect(tls::on_connect);
 
-    let config = tls::load_tls_config();
-    for s in conf.clone().server.services.unwrap() {
-        let addr = s.address.unwrap();
-        if String::from("127.0.0.1").eq(addr.as_str()) {
-            app = app.bind(format!("{}:{}", addr, s.port.unwrap())).unwrap();
-        } else {
-            app = app.bind_rustls(format!("{}:{}", addr, s.port.unwrap()), config.clone()).unwrap();
+    let services = conf.server.services.clone().unwrap_or_default();
+    let tls_config = if services.iter().any(config::Address::tls) {
+        let tls_config = tls::load_tls_config().map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("tls error: {}", e))
+        })?;
+        tls::spawn_reload();
+        Some(tls_config)
+    } else {
+        None
+    };
+    for s in services {
+        let addr = format!("{}:{}", s.address.clone().unwrap(), s.port.unwrap());
+        match &tls_config {
+            Some(tls_config) if s.tls() => app = app.bind_rustls(addr, tls_config.clone())?,
+            _ => app = app.bind(addr)?,
         }
     }
-    app = app.workers(10);
+    // SIGTERM/SIGINT go through Shutdown so the scheduler and pool are closed as well
+    app = app.workers(10)
+        .disable_signals()
+        .shutdown_timeout(conf.shutdown.drain_timeout.unwrap_or(30));
     let srv = app.run();
-    stop_handle.register(srv.handle());
-    srv.await
+    shutdown.register(srv.handle());
+    let coordinator = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { shutdown.run().await }
+    });
+    let result = srv.await;
+    // the server may also stop on its own, the rest is closed either way
+    shutdown.stop(true);
+    if let Err(e) = coordinator.await {
+        error!("shutdown error: {}", e);
+    }
+    result
 }
 
-fn cors() -> Cors {
-    Cors::default()
-        .allow_any_origin() // .allowed_origin("*")
-        .allowed_methods(vec!["GET", "POST", "DELETE"])
-        .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT])
-        .allowed_header(header::CONTENT_TYPE)
-        .allowed_header("sign")
-        .supports_credentials()
-        .max_age(3600)
+fn access_log(log: &config::Log) -> middleware::AccessLogging {
+    if log.json_access_log() {
+        middleware::AccessLogging::json()
+    } else {
+        middleware::AccessLogging::default()
+    }
 }
 
-async fn scheduler_job() {
+async fn scheduler_job() -> JobScheduler {
     let scheduler_expr: &str = "1/10 * * * * *";
     // job run
     let scheduler_job = scheduler::SchedulerJob::new().expect("scheduler job run");
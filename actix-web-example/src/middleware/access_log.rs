@@ -5,7 +5,7 @@ use std::{
     collections::HashSet,
     convert::TryFrom,
     env,
-    fmt::{self, Display as _},
+    fmt::{self, Display as _, Write as _},
     future::Future,
     marker::PhantomData,
     pin::Pin,
@@ -24,9 +24,9 @@ use time::{format_description::well_known::Rfc3339, OffsetDateTime};
 
 use actix_web::{
     body::{BodySize, MessageBody},
-    http::header::HeaderName,
+    http::header::{self, HeaderName},
     // service::{ServiceRequest, ServiceResponse},
-    Error, HttpResponse, Result,
+    Error, HttpMessage, HttpResponse, Result,
 };
 
 use actix_web::dev::{
@@ -34,6 +34,10 @@ use actix_web::dev::{
     ServiceResponse,
 };
 
+use crate::utils::ip;
+use crate::utils::metrics::RequestTimer;
+use crate::utils::request_id::{ReqId, REQUEST_ID_HEADER};
+
 /// Middleware for logging request and response summaries to the terminal.
 ///
 /// This middleware uses the `log` crate to output information. Enable `log`'s output for the
@@ -73,21 +77,27 @@ use actix_web::dev::{
 /// `%T` | Time taken to serve the request, in seconds to 6 decimal places
 /// `%D` | Time taken to serve the request, in milliseconds
 /// `%U` | Request URL
-/// `%{r}a` | "Real IP" remote address **\***
+/// `%m` | Request method
+/// `%R` | Matched route pattern like `/app/courses/{course_id}`, `-` when nothing matched
+/// `%L` | Request id, see [`crate::middleware::RequestId`]
+/// `%{r}a` | Client address behind trusted proxies **\***
 /// `%{FOO}i` |  `request.headers["FOO"]`
 /// `%{FOO}o` | `response.headers["FOO"]`
 /// `%{FOO}e` | `env_var["FOO"]`
 /// `%{FOO}xi` | [Custom request replacement](Logger::custom_request_replace) labelled "FOO"
 ///
-/// # Security
-/// **\*** "Real IP" remote address is calculated using
-/// [`ConnectionInfo::realip_remote_addr()`](crate::dev::ConnectionInfo::realip_remote_addr())
+/// Quotes, backslashes and control characters in header and custom values are escaped, so
+/// a value cannot break the line. [`AccessLogging::json`] logs one JSON object per request
+/// instead.
 ///
-/// If you use this value, ensure that all requests come from trusted hosts. Otherwise, it is
-/// trivial for the remote client to falsify their source IP address.
+/// # Security
+/// **\*** The client address is resolved with [`ip::client_ip`], forwarding headers are only
+/// believed when they were added by a proxy listed in `[proxy] trusted`. Requests from other
+/// peers are logged with the peer address, whatever headers they send.
 #[derive(Debug)]
 pub struct AccessLogging(Rc<Inner>);
 
+
 #[derive(Debug, Clone)]
 struct Inner {
     format: Format,
@@ -107,6 +117,27 @@ impl AccessLogging {
         }))
     }
 
+    /// One JSON object per request with all [`JsonField`]s except custom ones.
+    ///
+    /// ```plain
+    /// {"timestamp":"2023-07-01T08:00:00Z","peer_ip":"10.0.0.2","real_ip":"203.0.113.7","method":"GET",
+    ///  "path":"/app/courses/42","route":"/app/courses/{course_id}","status":404,"bytes":61,
+    ///  "duration_ms":0.412,"user_agent":"curl/8.0.1","request_id":null}
+    /// ```
+    pub fn json() -> AccessLogging {
+        AccessLogging::json_fields(JsonField::standard())
+    }
+
+    /// JSON log with the given fields in this order. Values that are not available are `null`.
+    pub fn json_fields<I: IntoIterator<Item = JsonField>>(fields: I) -> AccessLogging {
+        AccessLogging(Rc::new(Inner {
+            format: Format::json(fields),
+            exclude: HashSet::new(),
+            exclude_regex: RegexSet::empty(),
+            log_target: Cow::Borrowed(module_path!()),
+        }))
+    }
+
     /// Ignore and do not log access info for specified path.
     pub fn exclude<T: Into<String>>(mut self, path: T) -> Self {
         Rc::get_mut(&mut self.0)
@@ -165,7 +196,7 @@ impl AccessLogging {
     ) -> Self {
         let inner = Rc::get_mut(&mut self.0).unwrap();
 
-        let ft = inner.format.0.iter_mut().find(
+        let ft = inner.format.units.iter_mut().find(
             |ft| matches!(ft, FormatText::CustomRequest(unit_label, _) if label == unit_label),
         );
 
@@ -214,7 +245,7 @@ impl<S, B> Transform<S, ServiceRequest> for AccessLogging
     type Future = Ready<Result<Self::Transform, Self::InitError>>;
 
     fn new_transform(&self, service: S) -> Self::Future {
-        for unit in &self.0.format.0 {
+        for unit in &self.0.format.units {
             // missing request replacement function diagnostic
             if let FormatText::CustomRequest(label, None) = unit {
                 warn!(
@@ -251,6 +282,8 @@ impl<S, B> Service<ServiceRequest> for AccessLoggingMiddleware<S>
     fn call(&self, req: ServiceRequest) -> Self::Future {
         let excluded = self.inner.exclude.contains(req.path())
             || self.inner.exclude_regex.is_match(req.path());
+        // excluded paths are not logged but still measured
+        let timer = Some(RequestTimer::start(req.method().as_str(), req.match_pattern()));
 
         if excluded {
             LoggerResponse {
@@ -258,13 +291,14 @@ impl<S, B> Service<ServiceRequest> for AccessLoggingMiddleware<S>
                 format: None,
                 time: OffsetDateTime::now_utc(),
                 log_target: Cow::Borrowed(""),
+                timer,
                 _phantom: PhantomData,
             }
         } else {
             let now = OffsetDateTime::now_utc();
             let mut format = self.inner.format.clone();
 
-            for unit in &mut format.0 {
+            for unit in &mut format.units {
                 unit.render_request(now, &req);
             }
 
@@ -273,6 +307,7 @@ impl<S, B> Service<ServiceRequest> for AccessLoggingMiddleware<S>
                 format: Some(format),
                 time: now,
                 log_target: self.inner.log_target.clone(),
+                timer,
                 _phantom: PhantomData,
             }
         }
@@ -290,6 +325,7 @@ pin_project! {
         time: OffsetDateTime,
         format: Option<Format>,
         log_target: Cow<'static, str>,
+        timer: Option<RequestTimer>,
         _phantom: PhantomData<B>,
     }
 }
@@ -306,15 +342,23 @@ impl<S, B> Future for LoggerResponse<S, B>
 
         let res = match ready!(this.fut.poll(cx)) {
             Ok(res) => res,
-            Err(e) => return Poll::Ready(Err(e)),
+            Err(e) => {
+                if let Some(timer) = this.timer.take() {
+                    timer.finish(e.as_response_error().status_code());
+                }
+                return Poll::Ready(Err(e));
+            }
         };
+        if let Some(timer) = this.timer.take() {
+            timer.finish(res.status());
+        }
 
         if let Some(error) = res.response().error() {
             debug!("Error in response: {:?}", error);
         }
 
         if let Some(ref mut format) = this.format {
-            for unit in &mut format.0 {
+            for unit in &mut format.units {
                 unit.render_response(res.response());
             }
         }
@@ -346,11 +390,14 @@ pin_project! {
     impl<B> PinnedDrop for StreamLog<B> {
         fn drop(this: Pin<&mut Self>) {
             if let Some(ref format) = this.format {
-                let render = |fmt: &mut fmt::Formatter<'_>| {
-                    for unit in &format.0 {
-                        unit.render(fmt, this.size, this.time)?;
+                let render = |fmt: &mut fmt::Formatter<'_>| match format.json {
+                    Some(ref keys) => format.render_json(fmt, keys, this.size, this.time),
+                    None => {
+                        for unit in &format.units {
+                            unit.render(fmt, this.size, this.time)?;
+                        }
+                        Ok(())
                     }
-                    Ok(())
                 };
 
                 log::info!(
@@ -387,9 +434,83 @@ impl<B: MessageBody> MessageBody for StreamLog<B> {
     }
 }
 
+/// Field of the JSON access log, see [`AccessLogging::json_fields`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JsonField {
+    /// request start, RFC 3339
+    Timestamp,
+    /// address of the connected peer
+    PeerIp,
+    /// client address taken from `Forwarded`/`X-Forwarded-For`, see the security note
+    RealIp,
+    Method,
+    Path,
+    /// matched route pattern
+    Route,
+    Status,
+    /// response body size
+    Bytes,
+    /// milliseconds until the body was sent
+    Duration,
+    UserAgent,
+    /// id set by [`crate::middleware::RequestId`], else the `X-Request-Id` of the request
+    /// or of the response
+    RequestId,
+    /// `%{label}xi` value registered with [`AccessLogging::custom_request_replace`]
+    Custom(String),
+}
+
+impl JsonField {
+    /// every field except [`JsonField::Custom`]
+    pub fn standard() -> Vec<JsonField> {
+        vec![
+            JsonField::Timestamp,
+            JsonField::PeerIp,
+            JsonField::RealIp,
+            JsonField::Method,
+            JsonField::Path,
+            JsonField::Route,
+            JsonField::Status,
+            JsonField::Bytes,
+            JsonField::Duration,
+            JsonField::UserAgent,
+            JsonField::RequestId,
+        ]
+    }
+
+    fn unit(&self) -> (JsonKey, FormatText) {
+        let (name, number, unit): (Cow<'static, str>, bool, FormatText) = match self {
+            JsonField::Timestamp => ("timestamp".into(), false, FormatText::RequestTime),
+            JsonField::PeerIp => ("peer_ip".into(), false, FormatText::RemoteAddr),
+            JsonField::RealIp => ("real_ip".into(), false, FormatText::RealIpRemoteAddr),
+            JsonField::Method => ("method".into(), false, FormatText::Method),
+            JsonField::Path => ("path".into(), false, FormatText::UrlPath),
+            JsonField::Route => ("route".into(), false, FormatText::Route),
+            JsonField::Status => ("status".into(), true, FormatText::ResponseStatus),
+            JsonField::Bytes => ("bytes".into(), true, FormatText::ResponseSize),
+            JsonField::Duration => ("duration_ms".into(), true, FormatText::TimeMillis),
+            JsonField::UserAgent => ("user_agent".into(), false, FormatText::RequestHeader(header::USER_AGENT)),
+            JsonField::RequestId => ("request_id".into(), false, FormatText::RequestId),
+            JsonField::Custom(label) => (label.clone().into(), false, FormatText::CustomRequest(label.clone(), None)),
+        };
+        (JsonKey { name, number }, unit)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct JsonKey {
+    name: Cow<'static, str>,
+    /// logged as a JSON number instead of a string
+    number: bool,
+}
+
 /// A formatting style for the `Logger` consisting of multiple concatenated `FormatText` items.
 #[derive(Debug, Clone)]
-struct Format(Vec<FormatText>);
+struct Format {
+    units: Vec<FormatText>,
+    /// key of each unit when logging JSON
+    json: Option<Vec<JsonKey>>,
+}
 
 impl Default for Format {
     /// Return the default formatting style for the `Logger`:
@@ -404,7 +525,7 @@ impl Format {
     /// Returns `None` if the format string syntax is incorrect.
     pub fn new(s: &str) -> Format {
         log::trace!("Access log format: {}", s);
-        let fmt = Regex::new(r"%(\{([A-Za-z0-9\-_]+)\}([aioe]|xi)|[%atPrUsbTD]?)").unwrap();
+        let fmt = Regex::new(r"%(\{([A-Za-z0-9\-_]+)\}([aioe]|xi)|[%atPrUsbTDmRL]?)").unwrap();
 
         let mut idx = 0;
         let mut results = Vec::new();
@@ -447,6 +568,9 @@ impl Format {
                     "U" => FormatText::UrlPath,
                     "T" => FormatText::Time,
                     "D" => FormatText::TimeMillis,
+                    "m" => FormatText::Method,
+                    "R" => FormatText::Route,
+                    "L" => FormatText::RequestId,
                     _ => FormatText::Str(m.as_str().to_owned()),
                 });
             }
@@ -455,7 +579,42 @@ impl Format {
             results.push(FormatText::Str(s[idx..].to_owned()));
         }
 
-        Format(results)
+        Format { units: results, json: None }
+    }
+
+    fn json<I: IntoIterator<Item = JsonField>>(fields: I) -> Format {
+        let (keys, units) = fields.into_iter().map(|f| f.unit()).unzip();
+        Format { units, json: Some(keys) }
+    }
+
+    fn render_json(
+        &self,
+        fmt: &mut fmt::Formatter<'_>,
+        keys: &[JsonKey],
+        size: usize,
+        entry_time: OffsetDateTime,
+    ) -> Result<(), fmt::Error> {
+        fmt.write_str("{")?;
+        for (i, (key, unit)) in keys.iter().zip(&self.units).enumerate() {
+            if i > 0 {
+                fmt.write_str(",")?;
+            }
+            let value = match unit {
+                FormatText::Value(v) | FormatText::Str(v) => v.clone(),
+                unit => FormatDisplay(&|f: &mut fmt::Formatter<'_>| unit.render(f, size, entry_time)).to_string(),
+            };
+            let value = match value.as_str() {
+                "-" | "" => serde_json::Value::Null,
+                v if key.number => v.parse::<u64>()
+                    .map(serde_json::Value::from)
+                    .ok()
+                    .or_else(|| v.parse::<f64>().ok().and_then(serde_json::Number::from_f64).map(serde_json::Value::Number))
+                    .unwrap_or(serde_json::Value::Null),
+                _ => serde_json::Value::String(value),
+            };
+            write!(fmt, "{}:{}", serde_json::Value::from(key.name.as_ref()), value)?;
+        }
+        fmt.write_str("}")
     }
 }
 
@@ -466,6 +625,8 @@ impl Format {
 #[derive(Debug, Clone)]
 enum FormatText {
     Str(String),
+    /// value taken from the request or response, escaped in the text format
+    Value(String),
     Percent,
     RequestLine,
     RequestTime,
@@ -476,6 +637,9 @@ enum FormatText {
     RemoteAddr,
     RealIpRemoteAddr,
     UrlPath,
+    Method,
+    Route,
+    RequestId,
     RequestHeader(HeaderName),
     ResponseHeader(HeaderName),
     EnvironHeader(String),
@@ -508,6 +672,7 @@ impl FormatText {
     ) -> Result<(), fmt::Error> {
         match self {
             FormatText::Str(ref string) => fmt.write_str(string),
+            FormatText::Value(ref value) => write_escaped(fmt, value),
             FormatText::Percent => "%".fmt(fmt),
             FormatText::ResponseSize => size.fmt(fmt),
             FormatText::Time => {
@@ -546,7 +711,14 @@ impl FormatText {
                 } else {
                     "-"
                 };
-                *self = FormatText::Str(s.to_string())
+                *self = FormatText::Value(s.to_string())
+            }
+            FormatText::RequestId => {
+                let s = res.headers()
+                    .get(REQUEST_ID_HEADER)
+                    .and_then(|val| val.to_str().ok())
+                    .unwrap_or("-");
+                *self = FormatText::Value(s.to_string())
             }
             _ => {}
         }
@@ -556,14 +728,14 @@ impl FormatText {
         match self {
             FormatText::RequestLine => {
                 *self = if req.query_string().is_empty() {
-                    FormatText::Str(format!(
+                    FormatText::Value(format!(
                         "{} {} {:?}",
                         req.method(),
                         req.path(),
                         req.version()
                     ))
                 } else {
-                    FormatText::Str(format!(
+                    FormatText::Value(format!(
                         "{} {}?{} {:?}",
                         req.method(),
                         req.path(),
@@ -572,7 +744,20 @@ impl FormatText {
                     ))
                 };
             }
-            FormatText::UrlPath => *self = FormatText::Str(req.path().to_string()),
+            FormatText::UrlPath => *self = FormatText::Value(req.path().to_string()),
+            FormatText::Method => *self = FormatText::Str(req.method().to_string()),
+            FormatText::Route => {
+                *self = FormatText::Value(req.match_pattern().unwrap_or_else(|| "-".to_string()))
+            }
+            FormatText::RequestId => {
+                // otherwise taken from the response
+                let id = req.extensions().get::<ReqId>().map(|id| id.to_string()).or_else(|| {
+                    req.headers().get(REQUEST_ID_HEADER).and_then(|val| val.to_str().ok()).map(str::to_string)
+                });
+                if let Some(id) = id {
+                    *self = FormatText::Value(id);
+                }
+            }
             FormatText::RequestTime => *self = FormatText::Str(now.format(&Rfc3339).unwrap()),
             FormatText::RequestHeader(ref name) => {
                 let s = if let Some(val) = req.headers().get(name) {
@@ -584,7 +769,7 @@ impl FormatText {
                 } else {
                     "-"
                 };
-                *self = FormatText::Str(s.to_string());
+                *self = FormatText::Value(s.to_string());
             }
             FormatText::RemoteAddr => {
                 let s = if let Some(peer) = req.connection_info().peer_addr() {
@@ -595,7 +780,7 @@ impl FormatText {
                 *self = s;
             }
             FormatText::RealIpRemoteAddr => {
-                let s = if let Some(remote) = req.connection_info().realip_remote_addr() {
+                let s = if let Some(remote) = ip::client_ip(req.request()) {
                     FormatText::Str(remote.to_string())
                 } else {
                     FormatText::Str("-".to_string())
@@ -604,7 +789,7 @@ impl FormatText {
             }
             FormatText::CustomRequest(_, request_fn) => {
                 let s = match request_fn {
-                    Some(f) => FormatText::Str(f.call(req)),
+                    Some(f) => FormatText::Value(f.call(req)),
                     None => FormatText::Str("-".to_owned()),
                 };
 
@@ -615,6 +800,22 @@ impl FormatText {
     }
 }
 
+/// Writes `value` so it cannot end the log line or a quoted field early.
+fn write_escaped(fmt: &mut fmt::Formatter<'_>, value: &str) -> Result<(), fmt::Error> {
+    for c in value.chars() {
+        match c {
+            '"' => fmt.write_str("\\\"")?,
+            '\\' => fmt.write_str("\\\\")?,
+            '\n' => fmt.write_str("\\n")?,
+            '\r' => fmt.write_str("\\r")?,
+            '\t' => fmt.write_str("\\t")?,
+            c if c.is_control() => write!(fmt, "\\u{{{:04x}}}", c as u32)?,
+            c => fmt.write_char(c)?,
+        }
+    }
+    Ok(())
+}
+
 /// Converter to get a String from something that writes to a Formatter.
 pub(crate) struct FormatDisplay<'a>(
     &'a dyn Fn(&mut fmt::Formatter<'_>) -> Result<(), fmt::Error>,
@@ -628,7 +829,73 @@ impl<'a> fmt::Display for FormatDisplay<'a> {
 
 #[cfg(test)]
 mod tests {
-    async fn test_logger() {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    /// Renders one log line for `req` the way [`StreamLog`] does, with a 200 and `size` bytes.
+    fn line(logger: &AccessLogging, req: TestRequest, size: usize) -> String {
+        let req = req.to_srv_request();
+        let now = OffsetDateTime::now_utc();
+        let mut format = logger.0.format.clone();
+        for unit in &mut format.units {
+            unit.render_request(now, &req);
+        }
+        let res = HttpResponse::Ok().finish();
+        for unit in &mut format.units {
+            unit.render_response(&res);
+        }
+        let render = |fmt: &mut fmt::Formatter<'_>| match format.json {
+            Some(ref keys) => format.render_json(fmt, keys, size, now),
+            None => format.units.iter().try_for_each(|unit| unit.render(fmt, size, now)),
+        };
+        FormatDisplay(&render).to_string()
+    }
+
+    fn escaped(value: &str) -> String {
+        FormatDisplay(&|fmt: &mut fmt::Formatter<'_>| write_escaped(fmt, value)).to_string()
+    }
+
+    #[test]
+    fn escapes_quotes_and_line_breaks() {
+        assert_eq!(escaped("plain"), "plain");
+        assert_eq!(escaped(r#"a "b" \c"#), r#"a \"b\" \\c"#);
+        assert_eq!(escaped("a\nb\r\tc\u{1b}"), r#"a\nb\r\tc\u{001b}"#);
+    }
+
+    #[test]
+    fn text_format_keeps_one_line_per_request() {
+        let logger = AccessLogging::new(r#""%{User-Agent}i" "%{NOTE}xi" %s %b"#)
+            .custom_request_replace("NOTE", |_| "line\nbreak".to_string());
+        let req = TestRequest::get().uri("/app/courses").insert_header((header::USER_AGENT, r#"x" 500 "y"#));
+        let out = line(&logger, req, 12);
+        assert_eq!(out, r#""x\" 500 \"y" "line\nbreak" 200 12"#);
+        assert!(!out.contains('\n'));
+    }
 
+    #[test]
+    fn json_format_is_one_object_per_request() {
+        let logger = AccessLogging::json_fields(vec![
+            JsonField::Method,
+            JsonField::Path,
+            JsonField::Status,
+            JsonField::Bytes,
+            JsonField::UserAgent,
+            JsonField::RequestId,
+            JsonField::Custom("NOTE".to_string()),
+        ])
+        .custom_request_replace("NOTE", |_| "quote \" and\nbreak".to_string());
+        let req = TestRequest::post().uri("/app/courses?page=2").insert_header((header::USER_AGENT, r#"agent "1""#));
+        let out = line(&logger, req, 42);
+        assert!(!out.contains('\n'));
+
+        let value: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(value["method"], "POST");
+        assert_eq!(value["path"], "/app/courses");
+        assert_eq!(value["status"], 200);
+        assert_eq!(value["bytes"], 42);
+        assert_eq!(value["user_agent"], r#"agent "1""#);
+        assert_eq!(value["request_id"], serde_json::Value::Null);
+        assert_eq!(value["NOTE"], "quote \" and\nbreak");
+        assert!(out.starts_with(r#"{"method":"POST","path":"/app/courses","status":200,"bytes":42,"#), "{}", out);
     }
 }
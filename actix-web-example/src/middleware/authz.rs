@@ -0,0 +1,329 @@
+//! Role based authorization on top of [`super::Jwt`].
+//!
+//! [`RequireRole`] lets a request through only when the `role` claim is one of the listed
+//! roles. Checks that need the resource, like whether a teacher owns a course, are made in
+//! the handler through [`Principal`]. Every request gets one decision on the [`TARGET`] log
+//! target, the one of the handler if it made one, denied requests are answered with 403 and
+//! the reason.
+use std::cell::{Cell, RefCell};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest};
+use futures::future::{ok, ready, Future, Ready};
+
+use super::jwt::Claims;
+use crate::error::AppError;
+use crate::model::Role;
+use crate::utils::ip;
+use crate::utils::request_id::ReqId;
+
+/// Log target of the decisions, routed to its own appender in `conf/log4rs.yaml`.
+pub const TARGET: &str = "authz";
+
+/// Roles allowed to create, change and delete courses.
+pub const COURSE_EDITORS: &[Role] = &[Role::Admin, Role::Teacher];
+
+/// Roles allowed to create, change and delete teachers.
+pub const ADMINS: &[Role] = &[Role::Admin];
+
+/// Rejects requests whose `role` claim is not one of the given roles with 403, requests
+/// without claims with 401. Wrap it inside [`super::Jwt`], usually on single routes:
+///
+/// ```ignore
+/// #[post("/courses", wrap = "RequireRole::any(COURSE_EDITORS)")]
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct RequireRole(&'static [Role]);
+
+impl RequireRole {
+    pub fn any(roles: &'static [Role]) -> Self {
+        RequireRole(roles)
+    }
+}
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for RequireRole
+    where
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequireRoleMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequireRoleMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            roles: self.0,
+        })
+    }
+}
+
+pub struct RequireRoleMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    roles: &'static [Role],
+}
+
+impl<S, B> Service<ServiceRequest> for RequireRoleMiddleware<S>
+    where
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error> + 'static,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>>>>;
+
+    actix_service::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let principal = match Principal::from_http(req.request()) {
+            Ok(principal) => principal,
+            Err(e) => return Box::pin(async move { Err(e.into()) }),
+        };
+        let allowed = principal.role().is_some_and(|role| self.roles.contains(&role));
+        let reason = match principal.role() {
+            _ if allowed => None,
+            Some(role) => Some(format!("role {} may not {}", role, principal.action)),
+            None => Some(format!("token carries no role, {} needs one of {}", principal.action, join(self.roles))),
+        };
+        if let Some(reason) = reason {
+            let e = principal.deny(&principal.action, reason);
+            return Box::pin(async move { Err(e.into()) });
+        }
+        // the role only lets the request in, the allow is logged unless the handler decided itself
+        let decided = Decided::default();
+        req.extensions_mut().insert(decided.clone());
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            if !decided.0.get() {
+                principal.allow(&principal.action);
+            }
+            res
+        })
+    }
+}
+
+/// Set once a [`Principal`] of the request logged a decision.
+#[derive(Debug, Clone, Default)]
+struct Decided(Rc<Cell<bool>>);
+
+fn join(roles: &[Role]) -> String {
+    roles.iter().map(Role::to_string).collect::<Vec<_>>().join(", ")
+}
+
+/// Authenticated caller of the request, for checks that need the resource.
+///
+/// Extracting it fails with 401 when the request carries no [`Claims`].
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub claims: Claims,
+    /// method and matched route, like `DELETE /app/courses/{course_id}`
+    action: String,
+    request_id: Option<String>,
+    client_ip: Option<IpAddr>,
+    /// of the [`RequireRole`] guard, if the route has one
+    decided: Option<Decided>,
+}
+
+impl Principal {
+    fn from_http(req: &HttpRequest) -> Result<Principal, AppError> {
+        let claims = req.extensions().get::<Claims>().cloned();
+        let claims = claims.ok_or_else(|| AppError::Unauthorized("authentication required".to_string()))?;
+        let path = req.match_pattern().unwrap_or_else(|| req.path().to_string());
+        Ok(Principal {
+            claims,
+            action: format!("{} {}", req.method(), path),
+            request_id: req.extensions().get::<ReqId>().map(ReqId::to_string),
+            client_ip: ip::client_ip(req),
+            decided: req.extensions().get::<Decided>().cloned(),
+        })
+    }
+
+    pub fn role(&self) -> Option<Role> {
+        self.claims.role
+    }
+
+    /// Admins may change every course, teachers only the ones of their own `teacher_id`.
+    /// `course` names the course in the audit record, `None` for one that is being created.
+    pub fn authorize_course(&self, course: Option<&str>, teacher_id: i64) -> Result<(), AppError> {
+        let resource = match course {
+            Some(id) => format!("{} course={} teacher_id={}", self.action, id, teacher_id),
+            None => format!("{} teacher_id={}", self.action, teacher_id),
+        };
+        let reason = match (self.role(), self.claims.teacher_id) {
+            (Some(Role::Admin), _) => None,
+            (Some(Role::Teacher), Some(own)) if own == teacher_id => None,
+            (Some(Role::Teacher), Some(own)) => {
+                Some(format!("teacher {} may not change the courses of teacher {}", own, teacher_id))
+            }
+            (Some(Role::Teacher), None) => Some("token carries no teacher_id".to_string()),
+            (Some(role), _) => Some(format!("role {} may not change courses", role)),
+            (None, _) => Some("token carries no role".to_string()),
+        };
+        self.decide(&resource, reason)
+    }
+
+    /// Logs the decision about `resource`, `reason` is why it was denied. It replaces the allow
+    /// of the [`RequireRole`] guard.
+    fn decide(&self, resource: &str, reason: Option<String>) -> Result<(), AppError> {
+        if let Some(decided) = &self.decided {
+            decided.0.set(true);
+        }
+        match reason {
+            None => {
+                self.allow(resource);
+                Ok(())
+            }
+            Some(reason) => Err(self.deny(resource, reason)),
+        }
+    }
+
+    fn allow(&self, resource: &str) {
+        let (role, request_id, client_ip) = self.fields();
+        info!(
+            target: TARGET,
+            "allow sub={} role={} {} request_id={} client_ip={}",
+            self.claims.sub, role, resource, request_id, client_ip,
+        );
+    }
+
+    fn deny(&self, resource: &str, reason: String) -> AppError {
+        let (role, request_id, client_ip) = self.fields();
+        warn!(
+            target: TARGET,
+            "deny sub={} role={} {} request_id={} client_ip={} reason={:?}",
+            self.claims.sub, role, resource, request_id, client_ip, reason,
+        );
+        AppError::Forbidden(reason)
+    }
+
+    /// Role, request id and client ip of the records, `-` when unknown.
+    fn fields(&self) -> (String, &str, String) {
+        let role = self.role().map_or_else(|| "-".to_string(), |r| r.to_string());
+        let client_ip = self.client_ip.map_or_else(|| "-".to_string(), |ip| ip.to_string());
+        (role, self.request_id.as_deref().unwrap_or("-"), client_ip)
+    }
+}
+
+impl FromRequest for Principal {
+    type Error = AppError;
+    type Future = Ready<Result<Principal, AppError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Principal::from_http(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use actix_web::dev::Service as _;
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpResponse};
+
+    use super::*;
+
+    /// Keeps the records of the [`TARGET`] log target.
+    struct Capture;
+
+    static RECORDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    impl log::Log for Capture {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == TARGET
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                RECORDS.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    /// Decisions logged for the request `id`.
+    fn decisions(id: &str) -> Vec<String> {
+        let needle = format!(" request_id={} ", id);
+        RECORDS.lock().unwrap().iter().filter(|r| r.contains(&needle)).cloned().collect()
+    }
+
+    async fn delete_course(principal: Principal, teacher_id: web::Path<i64>) -> Result<HttpResponse, AppError> {
+        principal.authorize_course(Some("c1"), *teacher_id)?;
+        Ok(HttpResponse::NoContent().finish())
+    }
+
+    /// Status of `method uri` as `role` of teacher 1, with `id` as request id.
+    async fn call(method: &str, uri: &str, role: Role, id: &str) -> StatusCode {
+        let _ = log::set_logger(&Capture);
+        log::set_max_level(log::LevelFilter::Info);
+        let app = test::init_service(
+            App::new()
+                .service(web::resource("/courses/{teacher_id}")
+                    .wrap(RequireRole::any(COURSE_EDITORS))
+                    .route(web::delete().to(delete_course)))
+                .service(web::resource("/teachers")
+                    .wrap(RequireRole::any(ADMINS))
+                    .route(web::post().to(HttpResponse::Created)))
+                .wrap_fn(move |req, srv| {
+                    let claims = Claims {
+                        sub: "u1".to_string(),
+                        exp: u64::MAX,
+                        nbf: None,
+                        iat: None,
+                        iss: None,
+                        aud: None,
+                        role: Some(role),
+                        teacher_id: Some(1),
+                    };
+                    req.extensions_mut().insert(claims);
+                    let id = req.headers().get("x-test-id").and_then(|v| ReqId::parse(v.to_str().ok()?));
+                    req.extensions_mut().insert(id.unwrap());
+                    srv.call(req)
+                }),
+        )
+        .await;
+        let req = test::TestRequest::default()
+            .method(method.parse().unwrap())
+            .uri(uri)
+            .insert_header(("x-test-id", id))
+            .to_request();
+        match test::try_call_service(&app, req).await {
+            Ok(resp) => resp.status(),
+            Err(e) => e.as_response_error().status_code(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn one_decision_per_request() {
+        assert_eq!(call("DELETE", "/courses/1", Role::Teacher, "own").await, StatusCode::NO_CONTENT);
+        let own = decisions("own");
+        assert_eq!(own.len(), 1, "{:?}", own);
+        assert!(own[0].starts_with("allow ") && own[0].contains("course=c1 teacher_id=1"), "{:?}", own);
+
+        assert_eq!(call("DELETE", "/courses/2", Role::Teacher, "other").await, StatusCode::FORBIDDEN);
+        let other = decisions("other");
+        assert_eq!(other.len(), 1, "{:?}", other);
+        assert!(other[0].starts_with("deny ") && other[0].contains("teacher_id=2"), "{:?}", other);
+
+        // decided by the guard alone
+        assert_eq!(call("DELETE", "/courses/1", Role::Student, "role").await, StatusCode::FORBIDDEN);
+        let role = decisions("role");
+        assert_eq!(role.len(), 1, "{:?}", role);
+        assert!(role[0].starts_with("deny ") && role[0].contains("role student may not"), "{:?}", role);
+
+        assert_eq!(call("POST", "/teachers", Role::Admin, "admin").await, StatusCode::CREATED);
+        let admin = decisions("admin");
+        assert_eq!(admin.len(), 1, "{:?}", admin);
+        assert!(admin[0].starts_with("allow ") && admin[0].contains("POST /teachers"), "{:?}", admin);
+    }
+}
@@ -0,0 +1,397 @@
+//! Audit log of request and response bodies.
+//!
+//! Bodies are copied while the handler reads them and while the response is sent, so
+//! nothing is buffered beyond `body_log.max_bytes`. One record per exchange is written on
+//! the [`TARGET`] log target once the response body is dropped. Multipart and binary
+//! bodies are never captured, only their content type is logged.
+use std::cell::RefCell;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::body::{BodySize, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderMap, CONTENT_TYPE};
+use actix_web::web::{Bytes, BytesMut};
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, Future, Ready};
+use futures::stream::StreamExt;
+use pin_project::{pin_project, pinned_drop};
+use rand::Rng;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::conf::config::{self, BodyLog};
+use crate::utils::ip;
+use crate::utils::request_id::ReqId;
+
+/// Log target of the audit records, routed to its own appender in `conf/log4rs.yaml`.
+pub const TARGET: &str = "body_audit";
+const REDACTED: &str = "***";
+const DEFAULT_MAX_BYTES: usize = 4096;
+
+/// Logs sampled request and response bodies as configured in `[body_log]`.
+pub struct BodyAudit;
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for BodyAudit
+    where
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<AuditedBody<B>>;
+    type Error = Error;
+    type Transform = BodyAuditMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(BodyAuditMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+pub struct BodyAuditMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service<ServiceRequest> for BodyAuditMiddleware<S>
+    where
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error> + 'static,
+        S::Future: 'static,
+        B: MessageBody + 'static,
+{
+    type Response = ServiceResponse<AuditedBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>>>>;
+
+    actix_service::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let conf = config::GLOBAL_CONFIG.load().body_log.clone();
+        if !sampled(&conf) {
+            return Box::pin(async move {
+                let res = svc.call(req).await?;
+                Ok(res.map_body(|_, body| AuditedBody { body, audit: None }))
+            });
+        }
+
+        let max_bytes = conf.max_bytes.unwrap_or(DEFAULT_MAX_BYTES);
+        let request = Rc::new(RefCell::new(Capture::new(content_type(req.headers()), max_bytes)));
+        if request.borrow().kind.is_some() {
+            let capture = request.clone();
+            let payload = req.take_payload().map(move |chunk| {
+                if let Ok(bytes) = &chunk {
+                    capture.borrow_mut().push(bytes);
+                }
+                chunk
+            });
+            req.set_payload(Payload::Stream { payload: Box::pin(payload) });
+        }
+        let method = req.method().to_string();
+        let path = req.path().to_string();
+        let request_id = req.extensions().get::<ReqId>().map(ReqId::to_string);
+        let client_ip = ip::client_ip(req.request());
+
+        Box::pin(async move {
+            let res = svc.call(req).await?;
+            let response = Capture::new(content_type(res.headers()), max_bytes);
+            let audit = Audit {
+                method,
+                path,
+                status: res.status().as_u16(),
+                request_id,
+                client_ip,
+                request,
+                response,
+                redact: conf.redact.unwrap_or_default(),
+            };
+            Ok(res.map_body(|_, body| AuditedBody { body, audit: Some(audit) }))
+        })
+    }
+}
+
+fn sampled(conf: &BodyLog) -> bool {
+    if !conf.enable.unwrap_or(false) {
+        return false;
+    }
+    let percent = conf.sample_percent.unwrap_or(100);
+    percent >= 100 || rand::thread_rng().gen_range(0..100) < percent
+}
+
+fn content_type(headers: &HeaderMap) -> Option<String> {
+    headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).map(str::to_string)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Json,
+    Form,
+    Text,
+    /// no content type, logged when the bytes are UTF-8
+    Unknown,
+}
+
+impl Kind {
+    /// `None` for multipart and binary content, which is never captured.
+    fn of(content_type: Option<&str>) -> Option<Kind> {
+        let essence = match content_type {
+            Some(ct) => ct.split(';').next().unwrap_or_default().trim().to_ascii_lowercase(),
+            None => return Some(Kind::Unknown),
+        };
+        match essence.as_str() {
+            "application/json" => Some(Kind::Json),
+            "application/x-www-form-urlencoded" => Some(Kind::Form),
+            "application/xml" | "application/javascript" => Some(Kind::Text),
+            e if e.ends_with("+json") => Some(Kind::Json),
+            e if e.ends_with("+xml") || e.starts_with("text/") => Some(Kind::Text),
+            _ => None,
+        }
+    }
+}
+
+/// First `max` bytes of a body and its total length.
+struct Capture {
+    content_type: Option<String>,
+    kind: Option<Kind>,
+    buf: BytesMut,
+    total: usize,
+    max: usize,
+}
+
+impl Capture {
+    fn new(content_type: Option<String>, max: usize) -> Capture {
+        Capture {
+            kind: Kind::of(content_type.as_deref()),
+            content_type,
+            buf: BytesMut::new(),
+            total: 0,
+            max,
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        self.total += chunk.len();
+        let room = self.max.saturating_sub(self.buf.len());
+        self.buf.extend_from_slice(&chunk[..room.min(chunk.len())]);
+    }
+
+    fn truncated(&self) -> bool {
+        self.total > self.buf.len()
+    }
+
+    /// The captured body with configured fields redacted.
+    fn render(&self, redact: &[String]) -> String {
+        let kind = match self.kind {
+            Some(kind) => kind,
+            None => return format!("<{} omitted>", self.content_type.as_deref().unwrap_or("-")),
+        };
+        if self.total == 0 {
+            return "-".to_string();
+        }
+        let text = match std::str::from_utf8(&self.buf) {
+            Ok(text) => text,
+            // cut inside a character by the size limit
+            Err(e) if e.error_len().is_none() => {
+                std::str::from_utf8(&self.buf[..e.valid_up_to()]).unwrap_or_default()
+            }
+            Err(_) => return format!("<binary {} bytes>", self.total),
+        };
+        let mut text = match kind {
+            Kind::Json => redact_json(text, !self.truncated(), redact),
+            Kind::Form => redact_form(text, redact),
+            Kind::Text | Kind::Unknown => text.to_string(),
+        };
+        if self.truncated() {
+            text.push_str(&format!("...(+{} bytes)", self.total - self.buf.len()));
+        }
+        text
+    }
+}
+
+fn redacted(key: &str, redact: &[String]) -> bool {
+    redact.iter().any(|r| r.eq_ignore_ascii_case(key))
+}
+
+/// Complete documents are redacted at any depth, cut or invalid ones by pattern.
+fn redact_json(text: &str, complete: bool, redact: &[String]) -> String {
+    if redact.is_empty() {
+        return text.to_string();
+    }
+    if complete {
+        if let Ok(mut value) = serde_json::from_str::<Value>(text) {
+            redact_value(&mut value, redact);
+            return value.to_string();
+        }
+    }
+    let keys: Vec<_> = redact.iter().map(|k| regex::escape(k)).collect();
+    let pattern = format!(r#"(?i)"({})"\s*:\s*("(?:[^"\\]|\\.)*"?|[^,\}}\]]*)"#, keys.join("|"));
+    match Regex::new(&pattern) {
+        Ok(re) => re.replace_all(text, format!(r#""$1":"{}""#, REDACTED).as_str()).into_owned(),
+        Err(e) => {
+            error!("body audit redact pattern error: {}", e);
+            REDACTED.to_string()
+        }
+    }
+}
+
+fn redact_value(value: &mut Value, redact: &[String]) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if redacted(key, redact) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_value(value, redact);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|v| redact_value(v, redact)),
+        _ => {}
+    }
+}
+
+fn redact_form(text: &str, redact: &[String]) -> String {
+    text.split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) if redacted(&urlencoding::decode(key).unwrap_or_default(), redact) => {
+                format!("{}={}", key, REDACTED)
+            }
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+struct Audit {
+    method: String,
+    path: String,
+    status: u16,
+    request_id: Option<String>,
+    client_ip: Option<IpAddr>,
+    request: Rc<RefCell<Capture>>,
+    response: Capture,
+    redact: Vec<String>,
+}
+
+impl Audit {
+    fn log(&self) {
+        info!(
+            target: TARGET,
+            "{} {} {} request_id={} client_ip={} request={} response={}",
+            self.method,
+            self.path,
+            self.status,
+            self.request_id.as_deref().unwrap_or("-"),
+            self.client_ip.map_or_else(|| "-".to_string(), |ip| ip.to_string()),
+            self.request.borrow().render(&self.redact),
+            self.response.render(&self.redact),
+        );
+    }
+}
+
+/// Response body that copies what it sends into the audit record.
+#[pin_project(PinnedDrop)]
+pub struct AuditedBody<B> {
+    #[pin]
+    body: B,
+    audit: Option<Audit>,
+}
+
+#[pinned_drop]
+impl<B> PinnedDrop for AuditedBody<B> {
+    fn drop(self: Pin<&mut Self>) {
+        if let Some(audit) = &self.audit {
+            audit.log();
+        }
+    }
+}
+
+impl<B: MessageBody> MessageBody for AuditedBody<B> {
+    type Error = B::Error;
+
+    fn size(&self) -> BodySize {
+        self.body.size()
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.project();
+        let poll = this.body.poll_next(cx);
+        if let (Some(audit), Poll::Ready(Some(Ok(chunk)))) = (this.audit.as_mut(), &poll) {
+            if audit.response.kind.is_some() {
+                audit.response.push(chunk);
+            }
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys() -> Vec<String> {
+        vec!["password".to_string(), "token".to_string()]
+    }
+
+    #[test]
+    fn kind_of_content_types() {
+        assert_eq!(Kind::of(Some("application/json; charset=utf-8")), Some(Kind::Json));
+        assert_eq!(Kind::of(Some("application/problem+json")), Some(Kind::Json));
+        assert_eq!(Kind::of(Some("Application/X-WWW-Form-Urlencoded")), Some(Kind::Form));
+        assert_eq!(Kind::of(Some("text/plain")), Some(Kind::Text));
+        assert_eq!(Kind::of(None), Some(Kind::Unknown));
+        assert_eq!(Kind::of(Some("multipart/form-data; boundary=x")), None);
+        assert_eq!(Kind::of(Some("application/octet-stream")), None);
+    }
+
+    #[test]
+    fn redacts_nested_json() {
+        let text = r#"{"user":{"name":"a","Password":"secret"},"items":[{"token":1}]}"#;
+        let value: Value = serde_json::from_str(&redact_json(text, true, &keys())).unwrap();
+        assert_eq!(value["user"]["name"], "a");
+        assert_eq!(value["user"]["Password"], REDACTED);
+        assert_eq!(value["items"][0]["token"], REDACTED);
+    }
+
+    #[test]
+    fn redacts_json_cut_inside_a_value() {
+        let text = r#"{"user":{"name":"a","password":"sec"#;
+        assert_eq!(redact_json(text, false, &keys()), r#"{"user":{"name":"a","password":"***""#);
+        let text = r#"{"token": 12, "password": "se\"cret", "name":"b"}"#;
+        let out = redact_json(text, false, &keys());
+        assert!(!out.contains("12") && !out.contains("cret"), "{}", out);
+        assert!(out.contains(r#""name":"b""#), "{}", out);
+    }
+
+    #[test]
+    fn redacts_percent_encoded_form_keys() {
+        let text = "name=a&pass%77ord=secret&token=t&tokens=kept";
+        assert_eq!(redact_form(text, &keys()), "name=a&pass%77ord=***&token=***&tokens=kept");
+    }
+
+    #[test]
+    fn skips_multipart_and_binary_bodies() {
+        let mut capture = Capture::new(Some("multipart/form-data; boundary=x".to_string()), 64);
+        capture.push(b"--x\r\npassword=secret");
+        assert_eq!(capture.render(&keys()), "<multipart/form-data; boundary=x omitted>");
+
+        let mut capture = Capture::new(None, 64);
+        capture.push(&[0xff, 0xfe, 0x00, 0x01]);
+        assert_eq!(capture.render(&keys()), "<binary 4 bytes>");
+    }
+
+    #[test]
+    fn marks_truncated_bodies() {
+        let mut capture = Capture::new(Some("application/json".to_string()), 16);
+        capture.push(br#"{"password":"secret-value"}"#);
+        assert_eq!(capture.render(&keys()), r#"{"password":"***"...(+11 bytes)"#);
+    }
+}
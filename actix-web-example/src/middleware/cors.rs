@@ -0,0 +1,47 @@
+use actix_cors::Cors;
+
+use crate::conf::config::{self, CorsPolicy};
+
+/// Scope whose `[cors.*]` policy applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorsScope {
+    App,
+    Sys,
+    Public,
+}
+
+impl CorsScope {
+    pub fn policy(self, cors: &config::Cors) -> &CorsPolicy {
+        match self {
+            CorsScope::App => &cors.app,
+            CorsScope::Sys => &cors.sys,
+            CorsScope::Public => &cors.public,
+        }
+    }
+}
+
+/// CORS middleware of `scope`, built from the config when a worker starts.
+///
+/// Origins are looked up in the current config on every request so reloads apply.
+pub fn cors(scope: CorsScope) -> Cors {
+    let conf = config::GLOBAL_CONFIG.load();
+    let policy = scope.policy(&conf.cors);
+    let mut cors = Cors::default()
+        .allowed_origin_fn(move |origin, _| {
+            origin.to_str().is_ok_and(|o| scope.policy(&config::GLOBAL_CONFIG.load().cors).allows(o))
+        })
+        .max_age(policy.max_age);
+    if let Some(methods) = &policy.allowed_methods {
+        cors = cors.allowed_methods(methods.iter().map(String::as_str));
+    }
+    if let Some(headers) = &policy.allowed_headers {
+        cors = cors.allowed_headers(headers.iter().map(String::as_str));
+    }
+    if let Some(headers) = &policy.expose_headers {
+        cors = cors.expose_headers(headers.iter().map(String::as_str));
+    }
+    if policy.supports_credentials.unwrap_or(false) {
+        cors = cors.supports_credentials();
+    }
+    cors
+}
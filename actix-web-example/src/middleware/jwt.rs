@@ -1,17 +1,15 @@
 use std::pin::Pin;
 use std::rc::Rc;
 use std::cell::RefCell;
-use std::{io::Write as _};
-use std::collections::HashMap;
+use std::str::FromStr;
 
 use actix_web::dev::{
     ServiceRequest,
     ServiceResponse,
 };
 use futures::{
-    future::{Ready, ok},
+    future::{Ready, ok, err},
     Future,
-    stream::StreamExt,
 };
 
 use actix_web::{
@@ -19,19 +17,49 @@ use actix_web::{
     Error,
     HttpMessage,
     HttpResponse,
-    web::{BytesMut, BufMut},
 };
 
 use actix_service::{Service, Transform};
 use actix_http::{
     header,
     StatusCode,
-    Response,
-    body::BoxBody,
 };
+use jsonwebtoken::{decode, encode, errors::ErrorKind, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 
+use crate::conf::config;
+use crate::model::Role;
+
+/// Verifies the `Authorization: Bearer <token>` header of every request.
+///
+/// Keys, issuer, audience and leeway come from the `[jwt]` section of `app.toml`.
+/// On success the decoded [`Claims`] are stored in the request extensions, so handlers
+/// can take them as `web::ReqData<Claims>`.
 pub struct Jwt;
 
+/// Claims carried by the access token.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    /// caller identity
+    pub sub: String,
+    pub exp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nbf: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iat: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+    /// checked by [`super::RequireRole`], tokens without one may only read
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    /// teacher the caller acts as, required for the `teacher` role
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub teacher_id: Option<i64>,
+}
+
 #[derive(Debug, derive_more::Display, derive_more::Error)]
 pub enum UserError {
     #[display(fmt = "Validation error on field: {}", field)]
@@ -46,15 +74,123 @@ impl ResponseError for UserError {
     }
 
     fn error_response(&self) -> HttpResponse {
-        let mut res = Response::new(self.status_code());
-        let mut buf = BytesMut::new().writer();
-        let _ = write!(buf, "{}", Error::from(UserError::ValidationError { field: "token invalid".to_string() }).to_string());
-
-        res.headers_mut().insert(
-            header::CONTENT_TYPE,
-            header::HeaderValue::from_static("application/json"),
-        );
-        HttpResponse::from(res.set_body(BoxBody::new(buf.into_inner())))
+        HttpResponse::build(self.status_code())
+            .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+            .json(json!({
+                "code": "ACTIX_000002",
+                "msg": self.to_string(),
+            }))
+    }
+}
+
+/// Decoding key and validation rules built from the `[jwt]` config section.
+pub(crate) struct JwtVerifier {
+    key: DecodingKey,
+    validation: Validation,
+}
+
+impl JwtVerifier {
+    pub(crate) fn from_conf(conf: &config::Jwt) -> Result<JwtVerifier, String> {
+        let (algorithm, key) = load_key(
+            conf,
+            ("jwt.public_key", conf.public_key.as_deref()),
+            DecodingKey::from_secret,
+            DecodingKey::from_rsa_pem,
+        )?;
+
+        let mut validation = Validation::new(algorithm);
+        validation.leeway = conf.leeway.unwrap_or(0);
+        validation.validate_nbf = true;
+        validation.set_required_spec_claims(&["exp", "sub"]);
+        if let Some(iss) = conf.issuer.as_ref().filter(|s| !s.is_empty()) {
+            validation.set_issuer(&[iss]);
+        }
+        if let Some(aud) = conf.audience.as_ref().filter(|s| !s.is_empty()) {
+            validation.set_audience(&[aud]);
+        }
+        Ok(JwtVerifier { key, validation })
+    }
+
+    pub(crate) fn verify(&self, token: &str) -> Result<Claims, UserError> {
+        decode::<Claims>(token, &self.key, &self.validation)
+            .map(|data| data.claims)
+            .map_err(|e| {
+                let field = match e.kind() {
+                    ErrorKind::ExpiredSignature => "exp".to_string(),
+                    ErrorKind::ImmatureSignature => "nbf".to_string(),
+                    ErrorKind::InvalidIssuer => "iss".to_string(),
+                    ErrorKind::InvalidAudience => "aud".to_string(),
+                    ErrorKind::InvalidSignature => "signature".to_string(),
+                    ErrorKind::InvalidAlgorithm => "alg".to_string(),
+                    ErrorKind::MissingRequiredClaim(claim) => claim.clone(),
+                    _ => "token".to_string(),
+                };
+                warn!("jwt rejected: {:?}", e);
+                UserError::ValidationError { field }
+            })
+    }
+}
+
+/// Algorithm of the `[jwt]` section and its key, built by `secret` from `jwt.secret` for HS256
+/// or by `pem` from the file at `pem_path` (config name and value) for RS256.
+fn load_key<K>(
+    conf: &config::Jwt,
+    pem_path: (&str, Option<&str>),
+    secret: impl FnOnce(&[u8]) -> K,
+    pem: impl FnOnce(&[u8]) -> jsonwebtoken::errors::Result<K>,
+) -> Result<(Algorithm, K), String> {
+    let name = conf.algorithm.clone().unwrap_or_else(|| "HS256".to_string());
+    let algorithm = Algorithm::from_str(name.as_str())
+        .map_err(|e| format!("jwt.algorithm {}: {}", name, e))?;
+    let key = match algorithm {
+        Algorithm::HS256 => {
+            let value = conf.secret.clone().unwrap_or_default();
+            if value.is_empty() {
+                return Err("jwt.secret is required for HS256".to_string());
+            }
+            secret(value.as_bytes())
+        }
+        Algorithm::RS256 => {
+            let (field, path) = (pem_path.0, pem_path.1.unwrap_or_default());
+            let bytes = std::fs::read(path)
+                .map_err(|e| format!("{} {}: {}", field, path, e))?;
+            pem(&bytes).map_err(|e| format!("{} {}: {}", field, path, e))?
+        }
+        _ => return Err(format!("jwt.algorithm {} is not supported", name)),
+    };
+    Ok((algorithm, key))
+}
+
+/// Signs the access tokens of `/auth` with the keys of the `[jwt]` section, so [`Jwt`]
+/// accepts them.
+pub struct JwtIssuer {
+    key: EncodingKey,
+    header: Header,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl JwtIssuer {
+    pub fn from_conf(conf: &config::Jwt) -> Result<JwtIssuer, String> {
+        let (algorithm, key) = load_key(
+            conf,
+            ("jwt.private_key", conf.private_key.as_deref()),
+            EncodingKey::from_secret,
+            EncodingKey::from_rsa_pem,
+        )?;
+        Ok(JwtIssuer {
+            key,
+            header: Header::new(algorithm),
+            issuer: conf.issuer.clone().filter(|s| !s.is_empty()),
+            audience: conf.audience.clone().filter(|s| !s.is_empty()),
+        })
+    }
+
+    /// Token for `claims`, `iss` and `aud` are filled in from the config.
+    pub fn issue(&self, mut claims: Claims) -> Result<String, String> {
+        claims.iss = claims.iss.or_else(|| self.issuer.clone());
+        claims.aud = claims.aud.or_else(|| self.audience.clone());
+        encode(&self.header, &claims, &self.key).map_err(|e| e.to_string())
     }
 }
 
@@ -72,14 +208,23 @@ impl<S: 'static, B> Transform<S, ServiceRequest> for Jwt
 
     fn new_transform(&self, service: S) -> Self::Future {
         debug!("new_transform in coming");
-        ok(JwtMiddleware {
-            service: Rc::new(RefCell::new(service))
-        })
+        let conf = config::GLOBAL_CONFIG.load().jwt.clone();
+        match JwtVerifier::from_conf(&conf) {
+            Ok(verifier) => ok(JwtMiddleware {
+                service: Rc::new(RefCell::new(service)),
+                verifier: Rc::new(verifier),
+            }),
+            Err(e) => {
+                error!("jwt middleware init error: {}", e);
+                err(())
+            }
+        }
     }
 }
 
 pub struct JwtMiddleware<S> {
     service: Rc<RefCell<S>>,
+    verifier: Rc<JwtVerifier>,
 }
 
 impl<S, B> Service<ServiceRequest> for JwtMiddleware<S>
@@ -94,50 +239,27 @@ impl<S, B> Service<ServiceRequest> for JwtMiddleware<S>
 
     actix_service::forward_ready!(service);
 
-    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+    fn call(&self, req: ServiceRequest) -> Self::Future {
         let svc = self.service.clone();
+        let verifier = self.verifier.clone();
         Box::pin(async move {
-            let mut body = BytesMut::new();
-            let mut stream = req.take_payload();
-            while let Some(chunk) = stream.next().await {
-                body.extend_from_slice(&chunk?);
-            }
-            if let Some(_sign) = get_header(&req, "sign".to_string()) {
-                // let v: Vec<&str> = sign.split('.').collect();
-                // for s in v {
-                //     debug!("{}", s);
-                // }
-                debug!("sign check ok");
-            }
-            // else {
-            //     error!("token invalid");
-            //     return Err(Error::from(UserError::ValidationError { field: "token invalid".to_string() }));
-            // }
-
-            // debug!("request body: {:?}", body);
-            // 回写body
-            let (_, mut payload) = actix_http::h1::Payload::create(true);
-            // let mut payload = actix_http::h1::Payload::empty();
-            payload.unread_data(body.into());
-            req.set_payload(payload.into());
-
-            let res = svc.call(req).await?;
-
-            // debug!("response: {:?}", res.headers());
-            let mut header_map: HashMap<&str, &str> = HashMap::new();
-            for (k, v) in res.headers().iter() {
-                if let Ok(v) = v.to_str() {
-                    header_map.insert(k.as_str(), v);
+            let claims = match bearer_token(&req) {
+                Some(token) => verifier.verify(token)?,
+                None => {
+                    error!("token invalid");
+                    return Err(Error::from(UserError::ValidationError { field: "authorization".to_string() }));
                 }
-            }
-            // debug!("response headers: {:?}", json!(header_map).to_string());
+            };
+            debug!("jwt check ok, sub: {}", claims.sub);
+            req.extensions_mut().insert(claims);
 
-            Ok(res)
-            // Err(Error::from(myRespError::MyError::BadClientData))
+            svc.call(req).await
         })
     }
 }
 
-fn get_header(req: &ServiceRequest, key: String) -> Option<&str> {
-    req.headers().get(key)?.to_str().ok()
+fn bearer_token(req: &ServiceRequest) -> Option<&str> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let token = value.strip_prefix("Bearer ").or_else(|| value.strip_prefix("bearer "))?;
+    Some(token.trim()).filter(|t| !t.is_empty())
 }
@@ -1,9 +1,18 @@
 mod jwt;
-mod read_request_body;
-mod read_response_body;
 mod access_log;
 mod logger;
+mod sign;
+mod request_id;
+mod body_audit;
+mod rate_limit;
+mod cors;
+mod authz;
 
-pub use self::jwt::Jwt;
-pub use self::read_request_body::ReadReqBody;
-pub use self::access_log::AccessLogging;
+pub use self::jwt::{Jwt, JwtIssuer, Claims};
+pub use self::access_log::{AccessLogging, JsonField};
+pub use self::sign::{session_key, SessionKey, Sign};
+pub use self::request_id::RequestId;
+pub use self::body_audit::BodyAudit;
+pub use self::rate_limit::RateLimit;
+pub use self::cors::{cors, CorsScope};
+pub use self::authz::{Principal, RequireRole, ADMINS, COURSE_EDITORS};
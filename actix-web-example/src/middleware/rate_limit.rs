@@ -0,0 +1,295 @@
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, Future, Ready};
+use hashlink::linked_hash_map::Entry;
+use hashlink::LinkedHashMap;
+use once_cell::sync::Lazy;
+
+use super::jwt::Claims;
+use crate::conf::config::{self, RateLimitGroup};
+use crate::error::AppError;
+use crate::utils::{ip, metrics};
+
+/// header identifying API clients that do not send a jwt, see `rate_limit.api_keys`
+pub const API_KEY_HEADER: &str = "x-api-key";
+/// buckets unused for this long are evicted when `idle_timeout` is not set
+const DEFAULT_IDLE_TIMEOUT: u64 = 600;
+/// bucket count the store is held to when `max_buckets` is not set
+const DEFAULT_MAX_BUCKETS: usize = 100_000;
+
+/// `group` + client key -> bucket, shared by all workers
+static BUCKETS: Lazy<Mutex<Store>> = Lazy::new(|| Mutex::new(Store::new()));
+
+/// Limits requests per client with the token buckets of the `[rate_limit]` section.
+///
+/// Clients are keyed by jwt subject, then an `x-api-key` listed in `api_keys`, then client
+/// ip, so wrap it inside [`super::Jwt`]. Unknown api keys are ignored, otherwise a client
+/// could get a fresh bucket per request by sending a new key. Allowed responses carry
+/// `X-RateLimit-Limit`, `X-RateLimit-Remaining` and `X-RateLimit-Reset`, rejected requests
+/// get 429 with `Retry-After`.
+pub struct RateLimit;
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for RateLimit
+    where
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+    where
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error> + 'static,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>>>>;
+
+    actix_service::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let conf = config::GLOBAL_CONFIG.load();
+        let group = match conf.rate_limit.group(req.method().as_str(), req.path()) {
+            Some(group) => group,
+            None => return Box::pin(svc.call(req)),
+        };
+        let idle = Duration::from_secs(conf.rate_limit.idle_timeout.unwrap_or(DEFAULT_IDLE_TIMEOUT));
+        let max = conf.rate_limit.max_buckets.unwrap_or(DEFAULT_MAX_BUCKETS);
+        let key = client_key(&req, &conf.rate_limit);
+        let taken = BUCKETS
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take(group, &key, idle, max, Instant::now());
+
+        let quota = match taken {
+            Ok(quota) => quota,
+            Err(retry_after) => {
+                warn!("rate limited, group: {}, client: {}", group.name, key);
+                metrics::RATE_LIMITED.with_label_values(&[group.name.as_str()]).inc();
+                let e = AppError::TooManyRequests { limit: group.capacity, retry_after };
+                return Box::pin(async move { Err(e.into()) });
+            }
+        };
+        Box::pin(async move {
+            let mut res = svc.call(req).await?;
+            let headers = res.headers_mut();
+            for (name, value) in quota.headers() {
+                headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+            }
+            Ok(res)
+        })
+    }
+}
+
+/// `sub:` jwt subject, `key:` id of a configured api key or `ip:` client address.
+fn client_key(req: &ServiceRequest, conf: &config::RateLimit) -> String {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        return format!("sub:{}", claims.sub);
+    }
+    let api_key = req.headers().get(API_KEY_HEADER).and_then(|v| v.to_str().ok());
+    if let Some(id) = api_key.and_then(|k| conf.api_key_id(k)) {
+        return format!("key:{}", id);
+    }
+    match ip::client_ip(req.request()) {
+        Some(ip) => format!("ip:{}", ip),
+        None => "ip:-".to_string(),
+    }
+}
+
+/// What is left of a bucket after a request was let through.
+struct Quota {
+    limit: u32,
+    remaining: u64,
+    /// seconds until the bucket is full again
+    reset: u64,
+}
+
+impl Quota {
+    fn headers(&self) -> [(&'static str, u64); 3] {
+        [
+            ("x-ratelimit-limit", self.limit as u64),
+            ("x-ratelimit-remaining", self.remaining),
+            ("x-ratelimit-reset", self.reset),
+        ]
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Buckets in the order they were last used, so evicting takes constant time per bucket
+/// while the lock is held.
+struct Store {
+    /// least recently used first
+    buckets: LinkedHashMap<(String, String), Bucket>,
+}
+
+impl Store {
+    fn new() -> Store {
+        Store { buckets: LinkedHashMap::new() }
+    }
+
+    /// Takes one token, `Err` holds the seconds until one is available.
+    ///
+    /// Idle buckets are dropped first, a new client finding `max` buckets then evicts the
+    /// least recently used one.
+    fn take(&mut self, group: &RateLimitGroup, key: &str, idle: Duration, max: usize, now: Instant) -> Result<Quota, u64> {
+        self.evict_idle(idle, now);
+        let id = (group.name.clone(), key.to_string());
+        let capacity = group.capacity as f64;
+        if !self.buckets.contains_key(&id) {
+            while self.buckets.len() >= max.max(1) {
+                if let Some(((group, key), _)) = self.buckets.pop_front() {
+                    debug!("rate limit store full, evicting the bucket of {}/{}", group, key);
+                }
+            }
+        }
+        let bucket = match self.buckets.entry(id) {
+            Entry::Occupied(mut entry) => {
+                entry.to_back();
+                entry.into_mut()
+            }
+            Entry::Vacant(entry) => entry.insert(Bucket { tokens: capacity, updated: now }),
+        };
+        // a lowered capacity applies to existing buckets as well
+        let refill = now.saturating_duration_since(bucket.updated).as_secs_f64() * group.per_second;
+        bucket.tokens = (bucket.tokens + refill).min(capacity);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            return Err(((1.0 - bucket.tokens) / group.per_second).ceil() as u64);
+        }
+        bucket.tokens -= 1.0;
+        Ok(Quota {
+            limit: group.capacity,
+            remaining: bucket.tokens.floor() as u64,
+            reset: ((capacity - bucket.tokens) / group.per_second).ceil() as u64,
+        })
+    }
+
+    /// Drops the buckets unused for `idle`, they are all at the front.
+    fn evict_idle(&mut self, idle: Duration, now: Instant) {
+        let mut evicted = 0;
+        while let Some((_, bucket)) = self.buckets.front() {
+            if now.saturating_duration_since(bucket.updated) < idle {
+                break;
+            }
+            self.buckets.pop_front();
+            evicted += 1;
+        }
+        if evicted > 0 {
+            debug!("rate limit evicted {} idle buckets", evicted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDLE: Duration = Duration::from_secs(600);
+
+    fn group(capacity: u32, per_second: f64) -> RateLimitGroup {
+        RateLimitGroup {
+            name: "app".to_string(),
+            prefix: "/app".to_string(),
+            methods: None,
+            capacity,
+            per_second,
+        }
+    }
+
+    #[test]
+    fn refill() {
+        let (mut store, group, start) = (Store::new(), group(2, 1.0), Instant::now());
+        let quota = store.take(&group, "ip:a", IDLE, 10, start).unwrap();
+        assert_eq!((quota.limit, quota.remaining, quota.reset), (2, 1, 1));
+        assert_eq!(store.take(&group, "ip:a", IDLE, 10, start).unwrap().remaining, 0);
+        assert!(store.take(&group, "ip:a", IDLE, 10, start).is_err());
+
+        // one token back per second, never more than the capacity
+        let later = start + Duration::from_secs(1);
+        assert_eq!(store.take(&group, "ip:a", IDLE, 10, later).unwrap().remaining, 0);
+        let much_later = start + Duration::from_secs(60);
+        assert_eq!(store.take(&group, "ip:a", IDLE, 10, much_later).unwrap().remaining, 1);
+        // other clients have their own bucket
+        assert_eq!(store.take(&group, "ip:b", IDLE, 10, start).unwrap().remaining, 1);
+    }
+
+    #[test]
+    fn retry_after() {
+        let (mut store, group, now) = (Store::new(), group(1, 0.25), Instant::now());
+        assert!(store.take(&group, "ip:a", IDLE, 10, now).is_ok());
+        assert_eq!(store.take(&group, "ip:a", IDLE, 10, now).err(), Some(4));
+        assert_eq!(store.take(&group, "ip:a", IDLE, 10, now + Duration::from_secs(3)).err(), Some(1));
+        assert!(store.take(&group, "ip:a", IDLE, 10, now + Duration::from_secs(4)).is_ok());
+    }
+
+    #[test]
+    fn evicts_idle_buckets() {
+        let (mut store, group, start) = (Store::new(), group(1, 0.001), Instant::now());
+        store.take(&group, "ip:a", IDLE, 10, start).unwrap();
+        let later = start + IDLE;
+        store.take(&group, "ip:b", IDLE, 10, later).unwrap();
+        assert_eq!(store.buckets.len(), 1);
+        // a evicted, so it starts over with a full bucket
+        assert!(store.take(&group, "ip:a", IDLE, 10, later).is_ok());
+    }
+
+    #[test]
+    fn evicts_only_the_idle_buckets_at_the_front() {
+        let (mut store, group, start) = (Store::new(), group(5, 0.001), Instant::now());
+        store.take(&group, "ip:a", IDLE, 10, start).unwrap();
+        store.take(&group, "ip:b", IDLE, 10, start + Duration::from_secs(1)).unwrap();
+        // a was used last, b is the only idle one
+        store.take(&group, "ip:a", IDLE, 10, start + Duration::from_secs(2)).unwrap();
+        store.take(&group, "ip:c", IDLE, 10, start + IDLE + Duration::from_secs(1)).unwrap();
+        let keys: Vec<&str> = store.buckets.keys().map(|(_, k)| k.as_str()).collect();
+        assert_eq!(keys, ["ip:a", "ip:c"]);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_when_full() {
+        let (mut store, group, start) = (Store::new(), group(1, 0.001), Instant::now());
+        for (i, key) in ["ip:a", "ip:b", "ip:c"].iter().enumerate() {
+            store.take(&group, key, IDLE, 3, start + Duration::from_secs(i as u64)).unwrap();
+        }
+        let now = start + Duration::from_secs(5);
+        // known clients do not evict anyone
+        assert!(store.take(&group, "ip:a", IDLE, 3, now).is_err());
+        assert_eq!(store.buckets.len(), 3);
+
+        store.take(&group, "ip:d", IDLE, 3, now).unwrap();
+        assert_eq!(store.buckets.len(), 3);
+        let keys: Vec<&str> = store.buckets.keys().map(|(_, k)| k.as_str()).collect();
+        assert!(!keys.contains(&"ip:b"), "{:?}", keys);
+    }
+}
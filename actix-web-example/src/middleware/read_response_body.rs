@@ -1,124 +0,0 @@
-use std::{
-    future::{ready, Future, Ready},
-    marker::PhantomData,
-    pin::Pin,
-    task::{Context, Poll},
-};
-
-use actix_web::{
-    body::{BodySize, MessageBody},
-    dev::{self, Service, ServiceRequest, ServiceResponse, Transform},
-    web::{Bytes, BytesMut},
-    Error,
-};
-use pin_project::__private::PinnedDrop;
-
-pub struct Logging;
-
-impl<S: 'static, B> Transform<S, ServiceRequest> for Logging
-    where
-        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
-        B: MessageBody + 'static,
-{
-    type Response = ServiceResponse<BodyLogger<B>>;
-    type Error = Error;
-    type Transform = LoggingMiddleware<S>;
-    type InitError = ();
-    type Future = Ready<Result<Self::Transform, Self::InitError>>;
-
-    fn new_transform(&self, service: S) -> Self::Future {
-        ready(Ok(LoggingMiddleware { service }))
-    }
-}
-
-pub struct LoggingMiddleware<S> {
-    service: S,
-}
-
-impl<S, B> Service<ServiceRequest> for LoggingMiddleware<S>
-    where
-        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
-        B: MessageBody,
-{
-    type Response = ServiceResponse<BodyLogger<B>>;
-    type Error = Error;
-    type Future = WrapperStream<S, B>;
-
-    dev::forward_ready!(service);
-
-    fn call(&self, req: ServiceRequest) -> Self::Future {
-        WrapperStream {
-            fut: self.service.call(req),
-            _t: PhantomData,
-        }
-    }
-}
-
-#[pin_project::pin_project]
-pub struct WrapperStream<S, B>
-    where
-        B: MessageBody,
-        S: Service<ServiceRequest>,
-{
-    #[pin]
-    fut: S::Future,
-    _t: PhantomData<(B, )>,
-}
-
-impl<S, B> Future for WrapperStream<S, B>
-    where
-        B: MessageBody,
-        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
-{
-    type Output = Result<ServiceResponse<BodyLogger<B>>, Error>;
-
-    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let res = futures_util::ready!(self.project().fut.poll(cx));
-
-        Poll::Ready(res.map(|res| {
-            res.map_body(move |_, body| BodyLogger {
-                body,
-                body_accum: BytesMut::new(),
-            })
-        }))
-    }
-}
-
-#[pin_project::pin_project(PinnedDrop)]
-pub struct BodyLogger<B> {
-    #[pin]
-    body: B,
-    body_accum: BytesMut,
-}
-
-#[pin_project::pinned_drop]
-impl<B> PinnedDrop for BodyLogger<B> {
-    fn drop(self: Pin<&mut Self>) {
-        println!("response body: {:?}", self.body_accum);
-    }
-}
-
-impl<B: MessageBody> MessageBody for BodyLogger<B> {
-    type Error = B::Error;
-
-    fn size(&self) -> BodySize {
-        self.body.size()
-    }
-
-    fn poll_next(
-        self: Pin<&mut Self>,
-        cx: &mut Context<'_>,
-    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
-        let this = self.project();
-
-        match this.body.poll_next(cx) {
-            Poll::Ready(Some(Ok(chunk))) => {
-                this.body_accum.extend_from_slice(&chunk);
-                Poll::Ready(Some(Ok(chunk)))
-            }
-            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
-            Poll::Ready(None) => Poll::Ready(None),
-            Poll::Pending => Poll::Pending,
-        }
-    }
-}
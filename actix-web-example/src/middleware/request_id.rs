@@ -0,0 +1,91 @@
+use std::cell::RefCell;
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::error::InternalError;
+use actix_web::{Error, HttpMessage};
+use futures::future::{ok, Future, Ready};
+
+use crate::utils::request_id::{self, ReqId, REQUEST_ID_HEADER};
+
+/// Takes the `X-Request-Id` of the request or generates one, see [`request_id`].
+///
+/// The id is stored in the request extensions as [`ReqId`] and echoed on every response,
+/// error responses of inner middlewares included. Wrap it outside the other middlewares so
+/// their log lines carry the id.
+pub struct RequestId;
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for RequestId
+    where
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestIdMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+pub struct RequestIdMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddleware<S>
+    where
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error> + 'static,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>>>>;
+
+    actix_service::forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let sent = req.headers().get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok());
+        let id = match sent.map(|v| (v, ReqId::parse(v))) {
+            Some((_, Some(id))) => id,
+            Some((v, None)) => {
+                let id = ReqId::generate();
+                debug!("invalid {} {:?} replaced by {}", REQUEST_ID_HEADER, v, id);
+                id
+            }
+            None => ReqId::generate(),
+        };
+        req.extensions_mut().insert(id.clone());
+
+        Box::pin(request_id::scope(id.clone(), async move {
+            let header = HeaderName::from_static(REQUEST_ID_HEADER);
+            let value = HeaderValue::from_str(id.as_str()).ok();
+            match svc.call(req).await {
+                Ok(mut res) => {
+                    if let Some(value) = value {
+                        res.headers_mut().insert(header, value);
+                    }
+                    Ok(res)
+                }
+                // the request is gone, so the error is rendered here to carry the header
+                Err(e) => {
+                    let mut resp = e.error_response();
+                    if let Some(value) = value {
+                        resp.headers_mut().insert(header, value);
+                    }
+                    Err(InternalError::from_response(e, resp).into())
+                }
+            }
+        }))
+    }
+}
@@ -0,0 +1,228 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::web::BytesMut;
+use actix_web::{error, Error, HttpMessage};
+use futures::future::{ok, Future, Ready};
+use futures::stream::StreamExt;
+use once_cell::sync::Lazy;
+use rand::RngCore;
+use sign_rs::{SIGN_CLIENT_HEADER, SIGN_HEADER, SIGN_NONCE_HEADER, SIGN_TIMESTAMP_HEADER};
+
+use super::jwt::UserError;
+use crate::conf::config;
+
+/// max request body covered by a signature
+const MAX_SIGNED_BODY: usize = 262_144;
+/// nonce cache size that triggers eviction of expired entries
+const NONCE_EVICT_THRESHOLD: usize = 1024;
+
+/// `client:nonce` -> unix time after which the nonce may be forgotten, shared by all workers
+static NONCES: Lazy<Mutex<HashMap<String, i64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// prefix of the client ids handed out by [`session_key`]
+const SESSION_PREFIX: &str = "session.";
+
+/// `sign.session_secret`, or random bytes that last until the process exits
+static SESSION_SECRET: Lazy<Vec<u8>> = Lazy::new(|| {
+    match config::GLOBAL_CONFIG.load().sign.session_secret.clone().filter(|s| !s.is_empty()) {
+        Some(secret) => secret.into_bytes(),
+        None => {
+            let mut bytes = vec![0u8; 32];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            bytes
+        }
+    }
+});
+
+/// Verifies the `sign` header produced by [`sign_rs::canonical_request`] and
+/// [`sign_rs::sign`].
+///
+/// The client is looked up by the `x-sign-client` header in the `[sign]` section of
+/// `app.toml`, or is a session key from [`session_key`]. Requests outside the replay window
+/// or reusing a nonce are rejected with 401. With `sign.required = false` requests without
+/// `x-sign-client` are passed on unchecked, [`super::Jwt`] still applies to them.
+pub struct Sign;
+
+/// Signing key of one login, for clients like the browser that cannot keep a secret of
+/// their own.
+///
+/// The client id carries the expiry and the secret is the HMAC of the id under
+/// `sign.session_secret`, so any worker can check it without storing the key.
+#[derive(Debug, Clone)]
+pub struct SessionKey {
+    /// sent as `x-sign-client`
+    pub client: String,
+    pub secret: String,
+}
+
+/// New session key, valid until the unix time `expires_at`.
+pub fn session_key(expires_at: i64) -> SessionKey {
+    let mut id = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut id);
+    let id: String = id.iter().map(|b| format!("{:02x}", b)).collect();
+    let client = format!("{}{}.{}", SESSION_PREFIX, expires_at, id);
+    SessionKey { secret: sign_rs::sign(&SESSION_SECRET, &client), client }
+}
+
+/// Secret of a session client id that has not expired at `now`.
+fn session_secret(client: &str, now: i64) -> Option<String> {
+    let (expires_at, _) = client.strip_prefix(SESSION_PREFIX)?.split_once('.')?;
+    if expires_at.parse::<i64>().ok()? < now {
+        return None;
+    }
+    Some(sign_rs::sign(&SESSION_SECRET, client))
+}
+
+struct SignConf {
+    enable: bool,
+    required: bool,
+    window: i64,
+    secrets: HashMap<String, String>,
+}
+
+impl<S: 'static, B> Transform<S, ServiceRequest> for Sign
+    where
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error>,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = SignMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        let conf = config::GLOBAL_CONFIG.load().sign.clone();
+        let secrets = conf.clients
+            .unwrap_or_default()
+            .into_iter()
+            .map(|c| (c.id, c.secret))
+            .collect();
+        ok(SignMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            conf: Rc::new(SignConf {
+                enable: conf.enable.unwrap_or(true),
+                required: conf.required.unwrap_or(true),
+                window: conf.window.unwrap_or(300),
+                secrets,
+            }),
+        })
+    }
+}
+
+pub struct SignMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    conf: Rc<SignConf>,
+}
+
+impl<S, B> Service<ServiceRequest> for SignMiddleware<S>
+    where
+        S: Service<ServiceRequest, Response=ServiceResponse<B>, Error=Error> + 'static,
+        S::Future: 'static,
+        B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output=Result<Self::Response, Self::Error>>>>;
+
+    actix_service::forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let conf = self.conf.clone();
+        Box::pin(async move {
+            let unsigned = !req.headers().contains_key(SIGN_CLIENT_HEADER);
+            if !conf.enable || (unsigned && !conf.required) {
+                return svc.call(req).await;
+            }
+
+            let mut body = BytesMut::new();
+            let mut stream = req.take_payload();
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk?;
+                if body.len() + chunk.len() > MAX_SIGNED_BODY {
+                    return Err(error::ErrorPayloadTooLarge("signed body too large"));
+                }
+                body.extend_from_slice(&chunk);
+            }
+
+            check_sign(&req, &conf, &body)?;
+
+            // 回写body
+            let (_, mut payload) = actix_http::h1::Payload::create(true);
+            payload.unread_data(body.into());
+            req.set_payload(payload.into());
+
+            svc.call(req).await
+        })
+    }
+}
+
+fn check_sign(req: &ServiceRequest, conf: &SignConf, body: &[u8]) -> Result<(), UserError> {
+    let invalid = |field: &str| UserError::ValidationError { field: field.to_string() };
+
+    let now = unix_now();
+    let client = get_header(req, SIGN_CLIENT_HEADER).ok_or_else(|| invalid(SIGN_CLIENT_HEADER))?;
+    let secret = match conf.secrets.get(client) {
+        Some(secret) => secret.clone(),
+        None => session_secret(client, now).ok_or_else(|| invalid(SIGN_CLIENT_HEADER))?,
+    };
+    let signature = get_header(req, SIGN_HEADER).ok_or_else(|| invalid(SIGN_HEADER))?;
+    let nonce = get_header(req, SIGN_NONCE_HEADER)
+        .filter(|n| !n.is_empty())
+        .ok_or_else(|| invalid(SIGN_NONCE_HEADER))?;
+    let timestamp: i64 = get_header(req, SIGN_TIMESTAMP_HEADER)
+        .and_then(|t| t.parse().ok())
+        .ok_or_else(|| invalid(SIGN_TIMESTAMP_HEADER))?;
+
+    if (now - timestamp).abs() > conf.window {
+        warn!("sign rejected, client: {}, timestamp {} outside window", client, timestamp);
+        return Err(invalid(SIGN_TIMESTAMP_HEADER));
+    }
+
+    let canonical = sign_rs::canonical_request(
+        req.method().as_str(),
+        req.path(),
+        req.query_string(),
+        body,
+        timestamp,
+        nonce,
+    );
+    if !sign_rs::verify(secret.as_bytes(), &canonical, signature) {
+        warn!("sign rejected, client: {}, signature mismatch", client);
+        return Err(invalid(SIGN_HEADER));
+    }
+
+    // only remember nonces of requests with a valid signature
+    let mut nonces = NONCES.lock().unwrap();
+    if nonces.len() > NONCE_EVICT_THRESHOLD {
+        nonces.retain(|_, expire| *expire >= now);
+    }
+    let key = format!("{}:{}", client, nonce);
+    if nonces.get(&key).map_or(false, |expire| *expire >= now) {
+        warn!("sign rejected, client: {}, nonce replayed", client);
+        return Err(invalid(SIGN_NONCE_HEADER));
+    }
+    nonces.insert(key, timestamp + conf.window);
+    debug!("sign check ok, client: {}", client);
+    Ok(())
+}
+
+fn get_header<'a>(req: &'a ServiceRequest, key: &str) -> Option<&'a str> {
+    req.headers().get(key)?.to_str().ok()
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
@@ -1,12 +1,18 @@
 use std::fmt::Debug;
 use chrono::{NaiveDateTime};
+use data_encoding::BASE64URL_NOPAD;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use validator::{Validate, ValidationError};
 
-#[derive(Debug, Deserialize, Serialize, Validate)]
+/// format of `courses.time` in the database
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+pub const DEFAULT_PAGE_SIZE: i64 = 20;
+
+#[derive(Debug, Clone, Deserialize, Serialize, Validate)]
 pub struct Course {
     pub id: Option<String>,
-    #[validate(range(min = 1, max = 32))]
+    /// must be an existing teacher, checked by the handlers
     pub teacher_id: i64,
     #[validate(custom(function = "validate_unique_username", message = "invalid name"))]
     pub name: Option<String>,
@@ -18,6 +24,50 @@ pub struct Course {
     pub price: Option<f64>,
     pub language: Option<String>,
     pub level: Option<String>,
+    /// seats, students beyond it are put on the waitlist, unlimited when absent
+    #[validate(range(min = 1, message = "capacity must be at least 1"))]
+    pub capacity: Option<i64>,
+    /// bumped on every update, sent as the `ETag` of the course
+    pub version: Option<i64>,
+    /// set by the database, ignored on insert
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+/// Body of `PATCH /app/courses/{id}`, only the fields present are changed.
+#[derive(Debug, Default, Deserialize, Serialize, Validate)]
+pub struct CoursePatch {
+    /// must be an existing teacher, checked by the handlers
+    pub teacher_id: Option<i64>,
+    #[validate(custom(function = "validate_unique_username", message = "invalid name"))]
+    pub name: Option<String>,
+    pub time: Option<NaiveDateTime>,
+    pub description: Option<String>,
+    pub format: Option<String>,
+    pub structure: Option<String>,
+    pub duration: Option<String>,
+    pub price: Option<f64>,
+    pub language: Option<String>,
+    pub level: Option<String>,
+    /// raising it promotes students from the waitlist, lowering it keeps those enrolled
+    #[validate(range(min = 1, message = "capacity must be at least 1"))]
+    pub capacity: Option<i64>,
+}
+
+impl CoursePatch {
+    pub fn is_empty(&self) -> bool {
+        self.teacher_id.is_none()
+            && self.name.is_none()
+            && self.time.is_none()
+            && self.description.is_none()
+            && self.format.is_none()
+            && self.structure.is_none()
+            && self.duration.is_none()
+            && self.price.is_none()
+            && self.language.is_none()
+            && self.level.is_none()
+            && self.capacity.is_none()
+    }
 }
 
 impl Course {
@@ -34,6 +84,10 @@ impl Course {
             price: None,
             language: None,
             level: None,
+            capacity: None,
+            version: None,
+            created_at: None,
+            updated_at: None,
         };
     }
 }
@@ -45,3 +99,130 @@ fn validate_unique_username(username: &str) -> Result<(), ValidationError> {
     }
     Ok(())
 }
+
+/// Query string of `GET /app/courses`.
+///
+/// `page`/`page_size` pages by offset, `cursor` continues after the last row of the
+/// previous page and takes precedence over `page`. `page` is capped so the offset
+/// `(page - 1) * page_size` cannot overflow, deeper pages are reached by cursor.
+#[derive(Debug, Default, Deserialize, Validate)]
+pub struct CourseQuery {
+    #[validate(range(min = 1, max = 100000, message = "page must be between 1 and 100000"))]
+    pub page: Option<i64>,
+    #[validate(range(min = 1, max = 100, message = "page_size must be between 1 and 100"))]
+    pub page_size: Option<i64>,
+    pub cursor: Option<String>,
+    pub teacher_id: Option<i64>,
+    pub language: Option<String>,
+    pub level: Option<String>,
+    pub min_price: Option<f64>,
+    pub max_price: Option<f64>,
+    /// substring of the course name
+    pub name: Option<String>,
+    /// comma separated fields, `-` prefix for descending, e.g. `price,-time`
+    pub sort: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CoursePage {
+    /// rows matching the filters, ignoring paging
+    pub total: i64,
+    /// `None` when the page was fetched by cursor
+    pub page: Option<i64>,
+    pub page_size: i64,
+    pub next_cursor: Option<String>,
+    pub items: Vec<Course>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortField {
+    Name,
+    Time,
+    Price,
+    TeacherId,
+}
+
+impl SortField {
+    pub fn column(&self) -> &'static str {
+        match self {
+            SortField::Name => "name",
+            SortField::Time => "time",
+            SortField::Price => "price",
+            SortField::TeacherId => "teacher_id",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<SortField> {
+        match name {
+            "name" => Some(SortField::Name),
+            "time" => Some(SortField::Time),
+            "price" => Some(SortField::Price),
+            "teacher_id" => Some(SortField::TeacherId),
+            _ => None,
+        }
+    }
+}
+
+/// Sort key and whether it is descending. `id` is always appended as the final tie breaker.
+pub type SortKey = (SortField, bool);
+
+/// Parses `sort=price,-time`.
+pub fn parse_sort(sort: Option<&str>) -> Result<Vec<SortKey>, String> {
+    let mut keys = Vec::new();
+    for part in sort.unwrap_or("").split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (name, desc) = match part.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (part, false),
+        };
+        let field = SortField::from_name(name).ok_or_else(|| format!("unknown sort field: {}", name))?;
+        if keys.iter().any(|(f, _)| *f == field) {
+            return Err(format!("duplicate sort field: {}", name));
+        }
+        keys.push((field, desc));
+    }
+    Ok(keys)
+}
+
+/// Position after the last row of a page: the sort key values and the id of that row.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CourseCursor {
+    pub sort: String,
+    pub values: Vec<Value>,
+    pub id: String,
+}
+
+impl CourseCursor {
+    pub fn after(course: &Course, sort: &str, keys: &[SortKey]) -> CourseCursor {
+        CourseCursor {
+            sort: sort.to_string(),
+            values: keys.iter().map(|(f, _)| course.sort_value(*f)).collect(),
+            id: course.id.clone().unwrap_or_default(),
+        }
+    }
+
+    pub fn encode(&self) -> String {
+        BASE64URL_NOPAD.encode(serde_json::to_string(self).unwrap_or_default().as_bytes())
+    }
+
+    /// Decodes a cursor and checks that it was issued for the same sort order.
+    pub fn decode(cursor: &str, sort: &str, keys: &[SortKey]) -> Result<CourseCursor, String> {
+        let bytes = BASE64URL_NOPAD.decode(cursor.as_bytes()).map_err(|_| "invalid cursor".to_string())?;
+        let cursor: CourseCursor = serde_json::from_slice(&bytes).map_err(|_| "invalid cursor".to_string())?;
+        if cursor.sort != sort || cursor.values.len() != keys.len() {
+            return Err("cursor does not match sort".to_string());
+        }
+        Ok(cursor)
+    }
+}
+
+impl Course {
+    /// Value of a sort column as stored in the database, `Null` where it is `NULL`.
+    pub fn sort_value(&self, field: SortField) -> Value {
+        match field {
+            SortField::Name => self.name.clone().map_or(Value::Null, Value::from),
+            SortField::Time => self.time.map_or(Value::Null, |t| Value::from(t.format(TIME_FORMAT).to_string())),
+            SortField::Price => self.price.map_or(Value::Null, Value::from),
+            SortField::TeacherId => Value::from(self.teacher_id),
+        }
+    }
+}
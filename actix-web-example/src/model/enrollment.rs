@@ -0,0 +1,48 @@
+use std::fmt;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+/// Whether a student has a seat in a course or waits for one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnrollmentStatus {
+    Enrolled,
+    /// promoted in order of enrollment when a seat becomes free
+    Waitlisted,
+}
+
+impl EnrollmentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EnrollmentStatus::Enrolled => "enrolled",
+            EnrollmentStatus::Waitlisted => "waitlisted",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<EnrollmentStatus> {
+        match name {
+            "enrolled" => Some(EnrollmentStatus::Enrolled),
+            "waitlisted" => Some(EnrollmentStatus::Waitlisted),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for EnrollmentStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Row of the `enrollments` table, one per course and user.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Enrollment {
+    /// ascending in order of enrollment, orders the waitlist
+    pub id: i64,
+    pub course_id: String,
+    /// `sub` of the student's token
+    pub user_id: String,
+    pub status: EnrollmentStatus,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
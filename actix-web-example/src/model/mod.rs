@@ -1,4 +1,9 @@
 pub mod course;
 pub mod user;
+pub mod teacher;
+pub mod enrollment;
 
 pub use self::course::*;
+pub use self::user::*;
+pub use self::teacher::*;
+pub use self::enrollment::*;
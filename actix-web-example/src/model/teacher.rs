@@ -0,0 +1,35 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+/// Row of the `teachers` table, courses reference it by `teacher_id`.
+#[derive(Debug, Clone, Deserialize, Serialize, Validate)]
+pub struct Teacher {
+    /// assigned by the database, ignored on insert
+    pub id: Option<i64>,
+    #[validate(length(min = 1, max = 64, message = "name must be 1 to 64 characters"))]
+    pub name: String,
+    #[validate(email(message = "invalid email"))]
+    pub email: Option<String>,
+    pub bio: Option<String>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+/// Body of `PATCH /app/teachers/{id}`, only the fields present are changed.
+#[derive(Debug, Default, Deserialize, Serialize, Validate)]
+pub struct TeacherPatch {
+    #[validate(length(min = 1, max = 64, message = "name must be 1 to 64 characters"))]
+    pub name: Option<String>,
+    #[validate(email(message = "invalid email"))]
+    pub email: Option<String>,
+    pub bio: Option<String>,
+}
+
+/// Query string of `DELETE /app/teachers/{id}`.
+#[derive(Debug, Default, Deserialize)]
+pub struct TeacherDelete {
+    /// delete the courses of the teacher as well, otherwise a teacher with courses is kept
+    /// and the request fails with 409
+    pub cascade: Option<bool>,
+}
@@ -1,20 +1,141 @@
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 use chrono::{NaiveDateTime};
 use serde::{Deserialize, Serialize};
 use validator::{Validate, ValidationError};
 
-#[derive(Debug, Deserialize, Serialize, Validate)]
-pub struct Course {
-    pub id: Option<String>,
-    #[validate(custom(function = "validate_username", message = "invalid name"))]
-    pub name: Option<String>,
-    pub create_at: Option<NaiveDateTime>,
-    pub update_at: Option<NaiveDateTime>,
+/// Roles of an account, carried in the `role` claim of its access tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// may change everything
+    Admin,
+    /// may change the courses of its own `teacher_id`
+    Teacher,
+    /// may only read
+    Student,
 }
 
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Teacher => "teacher",
+            Role::Student => "student",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Role> {
+        match name {
+            "admin" => Some(Role::Admin),
+            "teacher" => Some(Role::Teacher),
+            "student" => Some(Role::Student),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Row of the `users` table.
+#[derive(Debug, Clone, Serialize)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    /// argon2 PHC string, never sent to clients
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    pub role: Role,
+    /// teacher the account acts as, see [`Role::Teacher`]
+    pub teacher_id: Option<i64>,
+    /// failed logins since the last successful one or the last lockout
+    #[serde(skip_serializing)]
+    pub failed_logins: i64,
+    /// logins are refused until then
+    #[serde(skip_serializing)]
+    pub locked_until: Option<NaiveDateTime>,
+    pub created_at: Option<NaiveDateTime>,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+/// Body of `POST /auth/register`, new accounts are students.
+#[derive(Debug, Deserialize, Validate)]
+pub struct Register {
+    #[validate(
+        length(min = 3, max = 32, message = "username must be 3 to 32 characters"),
+        custom(function = "validate_username", message = "invalid name")
+    )]
+    pub username: String,
+    #[validate(length(min = 8, max = 128, message = "password must be 8 to 128 characters"))]
+    pub password: String,
+}
+
+/// Body of `POST /auth/login`.
+#[derive(Debug, Deserialize)]
+pub struct Login {
+    pub username: String,
+    pub password: String,
+}
+
+/// Body of `POST /auth/refresh` and `POST /auth/logout`.
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// Body of `PUT /app/users/{user_id}/role`. Teachers need a `teacher_id`, the other roles
+/// must not have one.
+#[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "validate_role_change", skip_on_field_errors = false))]
+pub struct RoleChange {
+    pub role: Role,
+    pub teacher_id: Option<i64>,
+}
+
+/// Tokens handed out by login and refresh.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenPair {
+    pub access_token: String,
+    /// always `Bearer`
+    pub token_type: String,
+    /// seconds the access token is valid
+    pub expires_in: u64,
+    /// single use, every refresh returns a new one
+    pub refresh_token: String,
+    /// `x-sign-client` of the key below, it expires with the access token
+    pub sign_client: String,
+    /// signs the `/app` requests of clients without a secret of their own, see `sign_rs::sign`
+    pub sign_secret: String,
+}
+
+/// Row of the `refresh_tokens` table. Only the SHA-256 of the token is stored.
+#[derive(Debug, Clone)]
+pub struct RefreshToken {
+    /// hex SHA-256 of the token
+    pub hash: String,
+    pub user_id: String,
+    /// tokens rotated from the same login, revoked together
+    pub family: String,
+    pub expires_at: NaiveDateTime,
+    /// set once the token was used or the family logged out
+    pub revoked: bool,
+}
+
+fn validate_role_change(change: &RoleChange) -> Result<(), ValidationError> {
+    match (change.role, change.teacher_id) {
+        (Role::Teacher, None) => Err(ValidationError::new("teacher_id is required for teachers")),
+        (Role::Admin | Role::Student, Some(_)) => Err(ValidationError::new("only teachers have a teacher_id")),
+        _ => Ok(()),
+    }
+}
 
+/// letters, digits, `.`, `_` and `-`
 fn validate_username(username: &str) -> Result<(), ValidationError> {
-    if username == "xXxShad0wxXx" {
+    let valid = username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'));
+    if !valid || username == "xXxShad0wxXx" {
         // the value of the username will automatically be added later
         return Err(ValidationError::new("invalid name"));
     }
@@ -0,0 +1,473 @@
+use std::cmp::Ordering;
+use std::sync::atomic::{AtomicI64, Ordering as AtomicOrdering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use chrono::{Local, NaiveDateTime};
+use serde_json::Value;
+use uuid::Uuid;
+
+use super::{CourseRepository, EnrollmentRepository, ListPlan, TeacherRepository, UserRepository};
+use crate::error::AppError;
+use crate::model::{
+    self, Course, CoursePage, CoursePatch, CourseQuery, Enrollment, EnrollmentStatus, RefreshToken, Role, Teacher,
+    TeacherPatch, User,
+};
+
+/// [`CourseRepository`] kept in memory, for tests and local runs without a database.
+#[derive(Default)]
+pub struct MemoryCourseRepository {
+    courses: Mutex<Vec<Course>>,
+}
+
+impl MemoryCourseRepository {
+    pub fn new() -> Self {
+        MemoryCourseRepository::default()
+    }
+}
+
+/// current time at the precision of [`model::TIME_FORMAT`]
+fn now() -> NaiveDateTime {
+    let now = Local::now().format(model::TIME_FORMAT).to_string();
+    NaiveDateTime::parse_from_str(&now, model::TIME_FORMAT).unwrap_or_default()
+}
+
+fn matches(c: &Course, q: &CourseQuery) -> bool {
+    q.teacher_id.is_none_or(|v| c.teacher_id == v)
+        && q.language.as_ref().is_none_or(|v| c.language.as_ref() == Some(v))
+        && q.level.as_ref().is_none_or(|v| c.level.as_ref() == Some(v))
+        && q.min_price.is_none_or(|v| c.price.is_some_and(|p| p >= v))
+        && q.max_price.is_none_or(|v| c.price.is_some_and(|p| p <= v))
+        // LIKE in SQLite ignores ASCII case
+        && q.name.as_ref().filter(|n| !n.is_empty()).is_none_or(|v| {
+            c.name.clone().unwrap_or_default().to_ascii_lowercase().contains(&v.to_ascii_lowercase())
+        })
+}
+
+/// `Null` before every value, like `NULL` in the SQL backend.
+fn cmp_value(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()).unwrap_or(Ordering::Equal),
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Null, Value::Null) => Ordering::Equal,
+        (Value::Null, _) => Ordering::Less,
+        (_, Value::Null) => Ordering::Greater,
+        _ => Ordering::Equal,
+    }
+}
+
+/// Orders the sort values and id of a row the same way the SQL backend does.
+fn cmp_key(keys: &[model::SortKey], a: (&[Value], &str), b: (&[Value], &str)) -> Ordering {
+    for (i, (_, desc)) in keys.iter().enumerate() {
+        let ord = cmp_value(&a.0[i], &b.0[i]);
+        if ord != Ordering::Equal {
+            return if *desc { ord.reverse() } else { ord };
+        }
+    }
+    a.1.cmp(b.1)
+}
+
+fn sort_values(c: &Course, keys: &[model::SortKey]) -> Vec<Value> {
+    keys.iter().map(|(f, _)| c.sort_value(*f)).collect()
+}
+
+#[async_trait]
+impl CourseRepository for MemoryCourseRepository {
+    async fn list(&self, q: &CourseQuery) -> Result<CoursePage, AppError> {
+        let plan = ListPlan::new(q)?;
+        let mut rows: Vec<(Vec<Value>, Course)> = self.courses.lock().unwrap()
+            .iter()
+            .filter(|c| matches(c, q))
+            .map(|c| (sort_values(c, &plan.keys), c.clone()))
+            .collect();
+        let total = rows.len() as i64;
+        rows.sort_by(|a, b| {
+            cmp_key(&plan.keys, (&a.0, a.1.id.as_deref().unwrap_or_default()), (&b.0, b.1.id.as_deref().unwrap_or_default()))
+        });
+
+        let (page, skip) = match plan.cursor.as_ref() {
+            Some(cursor) => {
+                let after = (cursor.values.as_slice(), cursor.id.as_str());
+                let skip = rows.iter()
+                    .take_while(|(v, c)| cmp_key(&plan.keys, (v, c.id.as_deref().unwrap_or_default()), after) != Ordering::Greater)
+                    .count();
+                (None, skip)
+            }
+            None => {
+                let page = q.page.unwrap_or(1);
+                (Some(page), ((page - 1) * plan.page_size) as usize)
+            }
+        };
+        let items = rows.into_iter()
+            .skip(skip)
+            .take(plan.page_size as usize + 1)
+            .map(|(_, c)| c)
+            .collect();
+        Ok(plan.page(total, page, items))
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Course>, AppError> {
+        Ok(self.courses.lock().unwrap()
+            .iter()
+            .find(|c| c.id.as_deref() == Some(id))
+            .cloned())
+    }
+
+    async fn create(&self, info: &Course) -> Result<Course, AppError> {
+        let now = now();
+        let course = Course {
+            id: Some(Uuid::new_v4().to_string()),
+            teacher_id: info.teacher_id,
+            name: Some(info.name.clone().unwrap_or_default()),
+            time: Some(now),
+            description: Some(info.description.clone().unwrap_or_default()),
+            format: Some(info.format.clone().unwrap_or_default()),
+            structure: Some(info.structure.clone().unwrap_or_default()),
+            duration: Some(info.duration.clone().unwrap_or_default()),
+            price: Some(info.price.unwrap_or_default()),
+            language: Some(info.language.clone().unwrap_or_default()),
+            level: Some(info.level.clone().unwrap_or_default()),
+            capacity: info.capacity,
+            version: Some(1),
+            created_at: Some(now),
+            updated_at: Some(now),
+        };
+        self.courses.lock().unwrap().push(course.clone());
+        Ok(course)
+    }
+
+    async fn update(&self, id: &str, patch: &CoursePatch, expected: Option<i64>) -> Result<Course, AppError> {
+        let mut courses = self.courses.lock().unwrap();
+        let course = courses.iter_mut()
+            .find(|c| c.id.as_deref() == Some(id))
+            .ok_or_else(|| AppError::NotFound(format!("course {}", id)))?;
+        let version = course.version.unwrap_or_default();
+        if expected.is_some_and(|v| v != version) {
+            return Err(AppError::PreconditionFailed(format!("course {} is at version {}", id, version)));
+        }
+
+        if let Some(teacher_id) = patch.teacher_id {
+            course.teacher_id = teacher_id;
+        }
+        if patch.name.is_some() {
+            course.name = patch.name.clone();
+        }
+        if patch.time.is_some() {
+            course.time = patch.time;
+        }
+        if patch.description.is_some() {
+            course.description = patch.description.clone();
+        }
+        if patch.format.is_some() {
+            course.format = patch.format.clone();
+        }
+        if patch.structure.is_some() {
+            course.structure = patch.structure.clone();
+        }
+        if patch.duration.is_some() {
+            course.duration = patch.duration.clone();
+        }
+        if patch.price.is_some() {
+            course.price = patch.price;
+        }
+        if patch.language.is_some() {
+            course.language = patch.language.clone();
+        }
+        if patch.level.is_some() {
+            course.level = patch.level.clone();
+        }
+        if patch.capacity.is_some() {
+            course.capacity = patch.capacity;
+        }
+        course.version = Some(version + 1);
+        course.updated_at = Some(now());
+        Ok(course.clone())
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), AppError> {
+        let mut courses = self.courses.lock().unwrap();
+        let len = courses.len();
+        courses.retain(|c| c.id.as_deref() != Some(id));
+        if courses.len() == len {
+            return Err(AppError::NotFound(format!("course {}", id)));
+        }
+        Ok(())
+    }
+}
+
+/// [`TeacherRepository`] kept in memory, checks and cascades to the courses of `courses`
+/// like the foreign key of the SQL backend.
+pub struct MemoryTeacherRepository {
+    teachers: Mutex<Vec<Teacher>>,
+    courses: Arc<MemoryCourseRepository>,
+}
+
+impl MemoryTeacherRepository {
+    pub fn new(courses: Arc<MemoryCourseRepository>) -> Self {
+        MemoryTeacherRepository {
+            teachers: Mutex::new(Vec::new()),
+            courses,
+        }
+    }
+}
+
+#[async_trait]
+impl TeacherRepository for MemoryTeacherRepository {
+    async fn list(&self) -> Result<Vec<Teacher>, AppError> {
+        Ok(self.teachers.lock().unwrap().clone())
+    }
+
+    async fn get(&self, id: i64) -> Result<Option<Teacher>, AppError> {
+        Ok(self.teachers.lock().unwrap().iter().find(|t| t.id == Some(id)).cloned())
+    }
+
+    async fn create(&self, info: &Teacher) -> Result<Teacher, AppError> {
+        let mut teachers = self.teachers.lock().unwrap();
+        let now = now();
+        let teacher = Teacher {
+            id: Some(teachers.iter().filter_map(|t| t.id).max().unwrap_or_default() + 1),
+            created_at: Some(now),
+            updated_at: Some(now),
+            ..info.clone()
+        };
+        teachers.push(teacher.clone());
+        Ok(teacher)
+    }
+
+    async fn update(&self, id: i64, patch: &TeacherPatch) -> Result<Teacher, AppError> {
+        let mut teachers = self.teachers.lock().unwrap();
+        let teacher = teachers.iter_mut()
+            .find(|t| t.id == Some(id))
+            .ok_or_else(|| AppError::NotFound(format!("teacher {}", id)))?;
+        if let Some(name) = patch.name.clone() {
+            teacher.name = name;
+        }
+        if patch.email.is_some() {
+            teacher.email = patch.email.clone();
+        }
+        if patch.bio.is_some() {
+            teacher.bio = patch.bio.clone();
+        }
+        teacher.updated_at = Some(now());
+        Ok(teacher.clone())
+    }
+
+    async fn delete(&self, id: i64, cascade: bool) -> Result<(), AppError> {
+        let mut teachers = self.teachers.lock().unwrap();
+        if !teachers.iter().any(|t| t.id == Some(id)) {
+            return Err(AppError::NotFound(format!("teacher {}", id)));
+        }
+        let mut courses = self.courses.courses.lock().unwrap();
+        let count = courses.iter().filter(|c| c.teacher_id == id).count();
+        if count > 0 && !cascade {
+            return Err(AppError::Conflict(format!(
+                "teacher {} still has {} courses, delete with cascade=true", id, count
+            )));
+        }
+        courses.retain(|c| c.teacher_id != id);
+        teachers.retain(|t| t.id != Some(id));
+        Ok(())
+    }
+}
+
+/// [`EnrollmentRepository`] kept in memory, on the courses of `courses`. Enrollments of
+/// deleted courses are skipped like the cascading foreign key of the SQL backend.
+pub struct MemoryEnrollmentRepository {
+    /// also serializes the enrollments, so seats are counted and taken at once
+    enrollments: Mutex<Vec<Enrollment>>,
+    last_id: AtomicI64,
+    courses: Arc<MemoryCourseRepository>,
+}
+
+impl MemoryEnrollmentRepository {
+    pub fn new(courses: Arc<MemoryCourseRepository>) -> Self {
+        MemoryEnrollmentRepository {
+            enrollments: Mutex::new(Vec::new()),
+            last_id: AtomicI64::new(0),
+            courses,
+        }
+    }
+
+    /// `None` for an unknown course, `Some(None)` for one without a limit.
+    fn capacity(&self, course_id: &str) -> Option<Option<i64>> {
+        self.courses.courses.lock().unwrap()
+            .iter()
+            .find(|c| c.id.as_deref() == Some(course_id))
+            .map(|c| c.capacity)
+    }
+}
+
+/// Moves the oldest waitlisted enrollments of `course_id` into the free seats.
+fn promote_waitlist(enrollments: &mut [Enrollment], course_id: &str, capacity: Option<i64>) -> Vec<Enrollment> {
+    let enrolled = enrollments.iter()
+        .filter(|e| e.course_id == course_id && e.status == EnrollmentStatus::Enrolled)
+        .count() as i64;
+    let free = capacity.map_or(i64::MAX, |c| c - enrolled).max(0) as usize;
+    let now = now();
+    // kept in id order
+    enrollments.iter_mut()
+        .filter(|e| e.course_id == course_id && e.status == EnrollmentStatus::Waitlisted)
+        .take(free)
+        .map(|e| {
+            e.status = EnrollmentStatus::Enrolled;
+            e.updated_at = Some(now);
+            e.clone()
+        })
+        .collect()
+}
+
+#[async_trait]
+impl EnrollmentRepository for MemoryEnrollmentRepository {
+    async fn enroll(&self, course_id: &str, user_id: &str) -> Result<Enrollment, AppError> {
+        let mut enrollments = self.enrollments.lock().unwrap();
+        let capacity = self.capacity(course_id)
+            .ok_or_else(|| AppError::NotFound(format!("course {}", course_id)))?;
+        if let Some(e) = enrollments.iter().find(|e| e.course_id == course_id && e.user_id == user_id) {
+            return Err(AppError::Conflict(format!("already {} in course {}", e.status, course_id)));
+        }
+        let enrolled = enrollments.iter()
+            .filter(|e| e.course_id == course_id && e.status == EnrollmentStatus::Enrolled)
+            .count() as i64;
+        let status = match capacity {
+            Some(capacity) if enrolled >= capacity => EnrollmentStatus::Waitlisted,
+            _ => EnrollmentStatus::Enrolled,
+        };
+        let now = now();
+        let enrollment = Enrollment {
+            id: self.last_id.fetch_add(1, AtomicOrdering::Relaxed) + 1,
+            course_id: course_id.to_string(),
+            user_id: user_id.to_string(),
+            status,
+            created_at: Some(now),
+            updated_at: Some(now),
+        };
+        enrollments.push(enrollment.clone());
+        Ok(enrollment)
+    }
+
+    async fn cancel(&self, course_id: &str, user_id: &str) -> Result<(Enrollment, Vec<Enrollment>), AppError> {
+        let mut enrollments = self.enrollments.lock().unwrap();
+        let capacity = self.capacity(course_id)
+            .ok_or_else(|| AppError::NotFound(format!("course {}", course_id)))?;
+        let index = enrollments.iter()
+            .position(|e| e.course_id == course_id && e.user_id == user_id)
+            .ok_or_else(|| AppError::NotFound(format!("enrollment of {} in course {}", user_id, course_id)))?;
+        let removed = enrollments.remove(index);
+        let promoted = promote_waitlist(&mut enrollments, course_id, capacity);
+        Ok((removed, promoted))
+    }
+
+    async fn promote(&self, course_id: &str) -> Result<Vec<Enrollment>, AppError> {
+        let mut enrollments = self.enrollments.lock().unwrap();
+        let capacity = self.capacity(course_id)
+            .ok_or_else(|| AppError::NotFound(format!("course {}", course_id)))?;
+        Ok(promote_waitlist(&mut enrollments, course_id, capacity))
+    }
+
+    async fn list_by_user(&self, user_id: &str) -> Result<Vec<Enrollment>, AppError> {
+        let enrollments = self.enrollments.lock().unwrap();
+        Ok(enrollments.iter()
+            .filter(|e| e.user_id == user_id && self.capacity(&e.course_id).is_some())
+            .cloned()
+            .collect())
+    }
+}
+
+/// [`UserRepository`] kept in memory, for tests and local runs without a database.
+#[derive(Default)]
+pub struct MemoryUserRepository {
+    users: Mutex<Vec<User>>,
+    tokens: Mutex<Vec<RefreshToken>>,
+}
+
+impl MemoryUserRepository {
+    pub fn new() -> Self {
+        MemoryUserRepository::default()
+    }
+
+    fn update<F: FnOnce(&mut User)>(&self, id: &str, f: F) -> Result<(), AppError> {
+        let mut users = self.users.lock().unwrap();
+        let user = users.iter_mut()
+            .find(|u| u.id == id)
+            .ok_or_else(|| AppError::NotFound(format!("user {}", id)))?;
+        f(user);
+        user.updated_at = Some(now());
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl UserRepository for MemoryUserRepository {
+    async fn create(&self, info: &User) -> Result<User, AppError> {
+        let mut users = self.users.lock().unwrap();
+        if users.iter().any(|u| u.username == info.username) {
+            return Err(AppError::Conflict(format!("username {} is taken", info.username)));
+        }
+        let now = now();
+        let user = User {
+            id: Uuid::new_v4().to_string(),
+            failed_logins: 0,
+            locked_until: None,
+            created_at: Some(now),
+            updated_at: Some(now),
+            ..info.clone()
+        };
+        users.push(user.clone());
+        Ok(user)
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<User>, AppError> {
+        Ok(self.users.lock().unwrap().iter().find(|u| u.id == id).cloned())
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, AppError> {
+        Ok(self.users.lock().unwrap().iter().find(|u| u.username == username).cloned())
+    }
+
+    async fn login_failed(&self, id: &str, max_failed: i64, lock_until: NaiveDateTime) -> Result<(), AppError> {
+        self.update(id, |user| {
+            user.failed_logins += 1;
+            if user.failed_logins >= max_failed {
+                user.failed_logins = 0;
+                user.locked_until = Some(lock_until);
+            }
+        })
+    }
+
+    async fn login_succeeded(&self, id: &str) -> Result<(), AppError> {
+        self.update(id, |user| {
+            user.failed_logins = 0;
+            user.locked_until = None;
+        })
+    }
+
+    async fn set_role(&self, id: &str, role: Role, teacher_id: Option<i64>) -> Result<User, AppError> {
+        self.update(id, |user| {
+            user.role = role;
+            user.teacher_id = teacher_id;
+        })?;
+        self.get(id).await?
+            .ok_or_else(|| AppError::NotFound(format!("user {}", id)))
+    }
+
+    async fn add_refresh_token(&self, token: &RefreshToken) -> Result<(), AppError> {
+        self.tokens.lock().unwrap().push(token.clone());
+        Ok(())
+    }
+
+    async fn use_refresh_token(&self, hash: &str) -> Result<Option<RefreshToken>, AppError> {
+        let mut tokens = self.tokens.lock().unwrap();
+        Ok(tokens.iter_mut().find(|t| t.hash == hash).map(|t| {
+            let before = t.clone();
+            t.revoked = true;
+            before
+        }))
+    }
+
+    async fn revoke_family(&self, family: &str) -> Result<(), AppError> {
+        for token in self.tokens.lock().unwrap().iter_mut().filter(|t| t.family == family) {
+            token.revoked = true;
+        }
+        Ok(())
+    }
+}
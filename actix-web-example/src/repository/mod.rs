@@ -0,0 +1,158 @@
+//! storage of the domain models, handlers only talk to the traits here
+use async_trait::async_trait;
+use chrono::NaiveDateTime;
+use validator::Validate;
+
+use crate::error::AppError;
+use crate::model::{
+    self, Course, CoursePage, CoursePatch, CourseQuery, Enrollment, RefreshToken, Role, Teacher, TeacherPatch,
+    User,
+};
+
+pub mod memory;
+pub mod sql;
+
+pub use self::memory::{
+    MemoryCourseRepository, MemoryEnrollmentRepository, MemoryTeacherRepository, MemoryUserRepository,
+};
+pub use self::sql::{SqlCourseRepository, SqlEnrollmentRepository, SqlTeacherRepository, SqlUserRepository};
+
+/// Course storage, registered as `web::Data<dyn CourseRepository>`.
+#[async_trait]
+pub trait CourseRepository: Send + Sync {
+    /// One page of the courses matching `query`.
+    async fn list(&self, query: &CourseQuery) -> Result<CoursePage, AppError>;
+
+    async fn get(&self, id: &str) -> Result<Option<Course>, AppError>;
+
+    /// Stores a new course at version 1, the id and time are generated.
+    async fn create(&self, course: &Course) -> Result<Course, AppError>;
+
+    /// Applies `patch` if the course is still at version `expected` (any version when `None`).
+    ///
+    /// Fails with [`AppError::NotFound`] for an unknown id and with
+    /// [`AppError::PreconditionFailed`] when the version moved on.
+    async fn update(&self, id: &str, patch: &CoursePatch, expected: Option<i64>) -> Result<Course, AppError>;
+
+    async fn delete(&self, id: &str) -> Result<(), AppError>;
+}
+
+/// Teachers, registered as `web::Data<dyn TeacherRepository>`.
+#[async_trait]
+pub trait TeacherRepository: Send + Sync {
+    /// All teachers by id.
+    async fn list(&self) -> Result<Vec<Teacher>, AppError>;
+
+    async fn get(&self, id: i64) -> Result<Option<Teacher>, AppError>;
+
+    /// Stores a new teacher, the id is generated.
+    async fn create(&self, teacher: &Teacher) -> Result<Teacher, AppError>;
+
+    /// Fails with [`AppError::NotFound`] for an unknown id.
+    async fn update(&self, id: i64, patch: &TeacherPatch) -> Result<Teacher, AppError>;
+
+    /// Deletes the teacher, together with its courses when `cascade` is set. Without it a
+    /// teacher that still has courses is kept and [`AppError::Conflict`] returned.
+    async fn delete(&self, id: i64, cascade: bool) -> Result<(), AppError>;
+}
+
+/// Enrollments of students in courses, registered as `web::Data<dyn EnrollmentRepository>`.
+///
+/// Seats are counted and taken atomically per course, so concurrent enrollments never
+/// exceed the `capacity` of a course.
+#[async_trait]
+pub trait EnrollmentRepository: Send + Sync {
+    /// Enrolls `user_id`, or puts it on the waitlist when the course is full.
+    ///
+    /// Fails with [`AppError::NotFound`] for an unknown course and with
+    /// [`AppError::Conflict`] when the user is already enrolled or waitlisted.
+    async fn enroll(&self, course_id: &str, user_id: &str) -> Result<Enrollment, AppError>;
+
+    /// Removes the enrollment of `user_id` and promotes the waitlist into the freed seat.
+    /// Returns the removed enrollment and the promoted ones.
+    async fn cancel(&self, course_id: &str, user_id: &str) -> Result<(Enrollment, Vec<Enrollment>), AppError>;
+
+    /// Promotes the waitlist into the free seats, after the capacity was raised.
+    async fn promote(&self, course_id: &str) -> Result<Vec<Enrollment>, AppError>;
+
+    /// Enrollments of `user_id` in all courses, oldest first.
+    async fn list_by_user(&self, user_id: &str) -> Result<Vec<Enrollment>, AppError>;
+}
+
+/// Accounts and refresh tokens, registered as `web::Data<dyn UserRepository>`.
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// Stores a new account, fails with [`AppError::Conflict`] when the username is taken.
+    async fn create(&self, user: &User) -> Result<User, AppError>;
+
+    async fn get(&self, id: &str) -> Result<Option<User>, AppError>;
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, AppError>;
+
+    /// Counts a failed login. The `max_failed`th one in a row locks the account until
+    /// `lock_until` and starts the count again.
+    async fn login_failed(&self, id: &str, max_failed: i64, lock_until: NaiveDateTime) -> Result<(), AppError>;
+
+    /// Clears the failed logins and the lock.
+    async fn login_succeeded(&self, id: &str) -> Result<(), AppError>;
+
+    /// Sets the role and teacher of an account, fails with [`AppError::NotFound`] when there
+    /// is no such account.
+    async fn set_role(&self, id: &str, role: Role, teacher_id: Option<i64>) -> Result<User, AppError>;
+
+    async fn add_refresh_token(&self, token: &RefreshToken) -> Result<(), AppError>;
+
+    /// Marks the token with `hash` as used and returns it as it was before, so a token
+    /// that is presented twice comes back with `revoked` set. `None` for unknown tokens.
+    async fn use_refresh_token(&self, hash: &str) -> Result<Option<RefreshToken>, AppError>;
+
+    /// Revokes every token of a login.
+    async fn revoke_family(&self, family: &str) -> Result<(), AppError>;
+}
+
+/// Checked paging parameters of a [`CourseQuery`], shared by the backends.
+pub(crate) struct ListPlan {
+    pub sort: String,
+    pub keys: Vec<model::SortKey>,
+    pub cursor: Option<model::CourseCursor>,
+    pub page_size: i64,
+}
+
+impl ListPlan {
+    pub(crate) fn new(q: &CourseQuery) -> Result<ListPlan, AppError> {
+        q.validate()?;
+        if let (Some(min), Some(max)) = (q.min_price, q.max_price) {
+            if min > max {
+                return Err(AppError::BadRequest("min_price is greater than max_price".to_string()));
+            }
+        }
+        let sort = q.sort.clone().unwrap_or_default();
+        let keys = model::parse_sort(Some(sort.as_str())).map_err(AppError::BadRequest)?;
+        let cursor = match q.cursor.as_ref() {
+            Some(c) => Some(model::CourseCursor::decode(c, &sort, &keys).map_err(AppError::BadRequest)?),
+            None => None,
+        };
+        Ok(ListPlan {
+            sort,
+            keys,
+            cursor,
+            page_size: q.page_size.unwrap_or(model::DEFAULT_PAGE_SIZE),
+        })
+    }
+
+    /// Builds the page from up to `page_size + 1` rows, the extra row only signals a next page.
+    pub(crate) fn page(&self, total: i64, page: Option<i64>, mut items: Vec<Course>) -> CoursePage {
+        let mut next_cursor = None;
+        if items.len() as i64 > self.page_size {
+            items.truncate(self.page_size as usize);
+            next_cursor = items.last().map(|c| model::CourseCursor::after(c, &self.sort, &self.keys).encode());
+        }
+        CoursePage {
+            total,
+            page,
+            page_size: self.page_size,
+            next_cursor,
+            items,
+        }
+    }
+}
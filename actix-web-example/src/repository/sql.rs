@@ -0,0 +1,805 @@
+use std::fmt::Write;
+
+use async_trait::async_trait;
+use chrono::{Local, NaiveDateTime};
+use futures::TryStreamExt;
+use serde_json::Value;
+use sqlx::any::{AnyArguments, AnyRow};
+use sqlx::{Any, AnyConnection, AnyPool, Arguments, Encode, Row, Type, TypeInfo, ValueRef};
+use uuid::Uuid;
+
+use super::{CourseRepository, EnrollmentRepository, ListPlan, TeacherRepository, UserRepository};
+use crate::error::AppError;
+use crate::model::{
+    self, Course, CoursePage, CoursePatch, CourseQuery, Enrollment, EnrollmentStatus, RefreshToken, Role, Teacher,
+    TeacherPatch, User,
+};
+
+/// [`CourseRepository`] on the `courses` table of the configured database.
+#[derive(Clone)]
+pub struct SqlCourseRepository {
+    pool: AnyPool,
+}
+
+impl SqlCourseRepository {
+    pub fn new(pool: AnyPool) -> Self {
+        SqlCourseRepository { pool }
+    }
+
+    /// `SELECT` of all columns, timestamps as text since the `Any` driver cannot decode date types.
+    fn select(&self) -> String {
+        let mut sql = "SELECT id, teacher_id, name, description, format, structure, duration, price, language, level, capacity, version".to_string();
+        for column in ["time", "created_at", "updated_at"] {
+            write!(sql, ", CAST({} AS TEXT) AS {}", column, column).unwrap_or_default();
+        }
+        sql.push_str(" FROM courses");
+        sql
+    }
+}
+
+/// [`TeacherRepository`] on the `teachers` table of the configured database.
+#[derive(Clone)]
+pub struct SqlTeacherRepository {
+    pool: AnyPool,
+}
+
+impl SqlTeacherRepository {
+    pub fn new(pool: AnyPool) -> Self {
+        SqlTeacherRepository { pool }
+    }
+
+    fn select(&self) -> String {
+        let mut sql = "SELECT id, name, email, bio".to_string();
+        for column in ["created_at", "updated_at"] {
+            write!(sql, ", CAST({} AS TEXT) AS {}", column, column).unwrap_or_default();
+        }
+        sql.push_str(" FROM teachers");
+        sql
+    }
+}
+
+/// [`EnrollmentRepository`] on the `enrollments` table of the configured database.
+///
+/// Every change runs in a transaction that first writes the row of the course, which takes
+/// the SQLite write lock and so serializes the enrollments of a course.
+#[derive(Clone)]
+pub struct SqlEnrollmentRepository {
+    pool: AnyPool,
+}
+
+impl SqlEnrollmentRepository {
+    pub fn new(pool: AnyPool) -> Self {
+        SqlEnrollmentRepository { pool }
+    }
+
+    fn select(&self) -> String {
+        let mut sql = "SELECT id, course_id, user_id, status".to_string();
+        for column in ["created_at", "updated_at"] {
+            write!(sql, ", CAST({} AS TEXT) AS {}", column, column).unwrap_or_default();
+        }
+        sql.push_str(" FROM enrollments");
+        sql
+    }
+
+    /// Locks the course until the transaction ends and returns its capacity.
+    async fn lock_course(&self, conn: &mut AnyConnection, course_id: &str) -> Result<Option<i64>, AppError> {
+        SqlBuilder::new("UPDATE courses SET capacity = capacity WHERE id = ")
+            .push_bind(course_id.to_string())
+            .build()
+            .execute(&mut *conn)
+            .await?;
+        let row = SqlBuilder::new("SELECT capacity FROM courses WHERE id = ")
+            .push_bind(course_id.to_string())
+            .build()
+            .fetch_optional(&mut *conn)
+            .await?;
+        let row = row.ok_or_else(|| AppError::NotFound(format!("course {}", course_id)))?;
+        nullable(&row, "capacity")
+    }
+
+    async fn find(&self, conn: &mut AnyConnection, course_id: &str, user_id: &str) -> Result<Option<Enrollment>, AppError> {
+        let row = SqlBuilder::new(&self.select())
+            .push(" WHERE course_id = ").push_bind(course_id.to_string())
+            .push(" AND user_id = ").push_bind(user_id.to_string())
+            .build()
+            .fetch_optional(&mut *conn)
+            .await?;
+        row.as_ref().map(enrollment_from_row).transpose()
+    }
+
+    async fn count_enrolled(&self, conn: &mut AnyConnection, course_id: &str) -> Result<i64, AppError> {
+        let count = SqlBuilder::new("SELECT COUNT(*) FROM enrollments WHERE status = 'enrolled' AND course_id = ")
+            .push_bind(course_id.to_string())
+            .build_scalar()
+            .fetch_one(&mut *conn)
+            .await?;
+        Ok(count)
+    }
+
+    /// Moves the oldest waitlisted enrollments into the free seats, the course must be locked.
+    async fn promote_waitlist(&self, conn: &mut AnyConnection, course_id: &str, capacity: Option<i64>) -> Result<Vec<Enrollment>, AppError> {
+        let mut query = SqlBuilder::new(&self.select());
+        query.push(" WHERE status = 'waitlisted' AND course_id = ").push_bind(course_id.to_string())
+            .push(" ORDER BY id");
+        if let Some(capacity) = capacity {
+            let free = capacity - self.count_enrolled(conn, course_id).await?;
+            if free <= 0 {
+                return Ok(Vec::new());
+            }
+            query.push(" LIMIT ").push_bind(free);
+        }
+        let mut waitlist = Vec::new();
+        {
+            let mut rows = query.build().fetch(&mut *conn);
+            while let Some(row) = rows.try_next().await? {
+                waitlist.push(enrollment_from_row(&row)?);
+            }
+        }
+
+        let date_time = Local::now().format(model::TIME_FORMAT).to_string();
+        for enrollment in waitlist.iter_mut() {
+            SqlBuilder::new("UPDATE enrollments SET status = 'enrolled', updated_at = ")
+                .push_bind(date_time.clone())
+                .push(" WHERE id = ").push_bind(enrollment.id)
+                .build()
+                .execute(&mut *conn)
+                .await?;
+            enrollment.status = EnrollmentStatus::Enrolled;
+            enrollment.updated_at = Some(parse_text(&date_time)?);
+        }
+        Ok(waitlist)
+    }
+}
+
+/// [`UserRepository`] on the `users` and `refresh_tokens` tables of the configured database.
+#[derive(Clone)]
+pub struct SqlUserRepository {
+    pool: AnyPool,
+}
+
+impl SqlUserRepository {
+    pub fn new(pool: AnyPool) -> Self {
+        SqlUserRepository { pool }
+    }
+
+    fn select(&self) -> String {
+        let mut sql = "SELECT id, username, password_hash, role, teacher_id, failed_logins".to_string();
+        sql.push_str(", CAST(locked_until AS TEXT) AS locked_until");
+        for column in ["created_at", "updated_at"] {
+            write!(sql, ", CAST({} AS TEXT) AS {}", column, column).unwrap_or_default();
+        }
+        sql.push_str(" FROM users");
+        sql
+    }
+
+    async fn find(&self, column: &str, value: &str) -> Result<Option<User>, AppError> {
+        let row = SqlBuilder::new(&self.select())
+            .push(" WHERE ").push(column).push(" = ")
+            .push_bind(value.to_string())
+            .build()
+            .fetch_optional(&self.pool)
+            .await?;
+        row.as_ref().map(user_from_row).transpose()
+    }
+}
+
+/// Like `sqlx::QueryBuilder<Any>`, but owns its arguments so helpers can add to a query.
+struct SqlBuilder {
+    sql: String,
+    args: AnyArguments<'static>,
+}
+
+impl SqlBuilder {
+    fn new(sql: &str) -> Self {
+        SqlBuilder {
+            sql: sql.to_string(),
+            args: AnyArguments::default(),
+        }
+    }
+
+    fn push(&mut self, sql: &str) -> &mut Self {
+        self.sql.push_str(sql);
+        self
+    }
+
+    fn push_bind<T>(&mut self, value: T) -> &mut Self
+        where T: 'static + Send + Encode<'static, Any> + Type<Any>,
+    {
+        self.sql.push('?');
+        self.args.add(value);
+        self
+    }
+
+    fn build(&mut self) -> sqlx::query::Query<'_, Any, AnyArguments<'_>> {
+        sqlx::query_with(&self.sql, std::mem::take(&mut self.args))
+    }
+
+    fn build_scalar<O>(&mut self) -> sqlx::query::QueryScalar<'_, Any, O, AnyArguments<'_>>
+        where (O,): for<'r> sqlx::FromRow<'r, AnyRow>,
+    {
+        sqlx::query_scalar_with(&self.sql, std::mem::take(&mut self.args))
+    }
+}
+
+fn course_from_row(row: &AnyRow) -> Result<Course, AppError> {
+    let id: String = row.try_get("id")?;
+    let name: String = row.try_get("name")?;
+
+    Ok(Course {
+        id: Option::from(id),
+        teacher_id: row.try_get("teacher_id").unwrap_or_default(),
+        name: Option::from(name),
+        time: Option::from(parse_time(row, "time")?),
+        description: nullable(row, "description")?,
+        format: nullable(row, "format")?,
+        structure: nullable(row, "structure")?,
+        duration: nullable(row, "duration")?,
+        price: nullable(row, "price")?,
+        language: nullable(row, "language")?,
+        level: nullable(row, "level")?,
+        capacity: nullable(row, "capacity")?,
+        version: Option::from(row.try_get::<i64, _>("version")?),
+        created_at: Option::from(parse_time(row, "created_at")?),
+        updated_at: Option::from(parse_time(row, "updated_at")?),
+    })
+}
+
+/// timestamps are stored as text in [`model::TIME_FORMAT`]
+fn parse_time(row: &AnyRow, column: &str) -> Result<NaiveDateTime, AppError> {
+    parse_text(row.try_get(column)?)
+}
+
+fn teacher_from_row(row: &AnyRow) -> Result<Teacher, AppError> {
+    Ok(Teacher {
+        id: Some(row.try_get("id")?),
+        name: row.try_get("name")?,
+        email: nullable(row, "email")?,
+        bio: nullable(row, "bio")?,
+        created_at: Option::from(parse_time(row, "created_at")?),
+        updated_at: Option::from(parse_time(row, "updated_at")?),
+    })
+}
+
+fn enrollment_from_row(row: &AnyRow) -> Result<Enrollment, AppError> {
+    let status: String = row.try_get("status")?;
+    Ok(Enrollment {
+        id: row.try_get("id")?,
+        course_id: row.try_get("course_id")?,
+        user_id: row.try_get("user_id")?,
+        status: EnrollmentStatus::from_name(&status)
+            .ok_or_else(|| AppError::Db(sqlx::Error::Decode(format!("unknown status {}", status).into())))?,
+        created_at: Option::from(parse_time(row, "created_at")?),
+        updated_at: Option::from(parse_time(row, "updated_at")?),
+    })
+}
+
+fn user_from_row(row: &AnyRow) -> Result<User, AppError> {
+    let role: String = row.try_get("role")?;
+    let locked_until: Option<&str> = nullable(row, "locked_until")?;
+    Ok(User {
+        id: row.try_get("id")?,
+        username: row.try_get("username")?,
+        password_hash: row.try_get("password_hash")?,
+        role: Role::from_name(&role)
+            .ok_or_else(|| AppError::Db(sqlx::Error::Decode(format!("unknown role {}", role).into())))?,
+        teacher_id: nullable(row, "teacher_id")?,
+        failed_logins: row.try_get("failed_logins")?,
+        locked_until: locked_until.map(parse_text).transpose()?,
+        created_at: Option::from(parse_time(row, "created_at")?),
+        updated_at: Option::from(parse_time(row, "updated_at")?),
+    })
+}
+
+/// `None` for a `NULL` column. The `Any` driver reports every value as non-null, so decoding
+/// `NULL` straight into an `Option` fails with a type mismatch, the value's type tells instead.
+fn nullable<'r, T>(row: &'r AnyRow, column: &str) -> Result<Option<T>, AppError>
+where
+    T: sqlx::Decode<'r, Any> + Type<Any>,
+{
+    if row.try_get_raw(column)?.type_info().name() == "NULL" {
+        return Ok(None);
+    }
+    Ok(Some(row.try_get(column)?))
+}
+
+fn parse_text(str_date: &str) -> Result<NaiveDateTime, AppError> {
+    NaiveDateTime::parse_from_str(str_date, model::TIME_FORMAT)
+        .map_err(|e| AppError::Db(sqlx::Error::Decode(Box::new(e))))
+}
+
+/// Appends the filters of `q` as bound parameters.
+fn push_filters(qb: &mut SqlBuilder, q: &CourseQuery) {
+    qb.push(" WHERE 1=1");
+    if let Some(teacher_id) = q.teacher_id {
+        qb.push(" AND teacher_id = ").push_bind(teacher_id);
+    }
+    if let Some(language) = q.language.clone() {
+        qb.push(" AND language = ").push_bind(language);
+    }
+    if let Some(level) = q.level.clone() {
+        qb.push(" AND level = ").push_bind(level);
+    }
+    if let Some(min_price) = q.min_price {
+        qb.push(" AND price >= ").push_bind(min_price);
+    }
+    if let Some(max_price) = q.max_price {
+        qb.push(" AND price <= ").push_bind(max_price);
+    }
+    if let Some(name) = q.name.as_ref().filter(|n| !n.is_empty()) {
+        // `!` as escape character, SQLite has no default one
+        let pattern = name.replace('!', "!!").replace('%', "!%").replace('_', "!_");
+        qb.push(" AND LOWER(name) LIKE LOWER(")
+            .push_bind(format!("%{}%", pattern))
+            .push(") ESCAPE '!'");
+    }
+}
+
+/// Keyset condition selecting the rows after `cursor` in `keys` order, id breaking ties.
+/// `NULL` sorts before every value, like the `ORDER BY` of [`push_order`].
+fn push_cursor(qb: &mut SqlBuilder, keys: &[model::SortKey], cursor: &model::CourseCursor) {
+    qb.push(" AND (");
+    for i in 0..=keys.len() {
+        if i > 0 {
+            qb.push(" OR ");
+        }
+        qb.push("(");
+        for (j, (field, _)) in keys[..i].iter().enumerate() {
+            match &cursor.values[j] {
+                Value::Null => qb.push(field.column()).push(" IS NULL"),
+                value => push_value(qb.push(field.column()).push(" = "), value),
+            };
+            qb.push(" AND ");
+        }
+        match keys.get(i) {
+            Some((field, desc)) => {
+                let column = field.column();
+                match (&cursor.values[i], *desc) {
+                    (Value::Null, false) => qb.push(column).push(" IS NOT NULL"),
+                    // nothing sorts after NULL in descending order
+                    (Value::Null, true) => qb.push("1 = 0"),
+                    (value, false) => push_value(qb.push(column).push(" > "), value),
+                    (value, true) => {
+                        push_value(qb.push("(").push(column).push(" < "), value);
+                        qb.push(" OR ").push(column).push(" IS NULL)")
+                    }
+                };
+            }
+            None => {
+                qb.push("id > ").push_bind(cursor.id.clone());
+            }
+        }
+        qb.push(")");
+    }
+    qb.push(")");
+}
+
+fn push_value<'a>(qb: &'a mut SqlBuilder, value: &Value) -> &'a mut SqlBuilder {
+    match value {
+        Value::Number(n) if n.is_i64() => qb.push_bind(n.as_i64()),
+        Value::Number(n) => qb.push_bind(n.as_f64()),
+        Value::String(s) => qb.push_bind(s.clone()),
+        _ => qb.push_bind(Option::<String>::None),
+    }
+}
+
+/// `ORDER BY` of `keys` then id, with `NULL` first in ascending and last in descending order.
+fn push_order(qb: &mut SqlBuilder, keys: &[model::SortKey]) {
+    qb.push(" ORDER BY ");
+    for (field, desc) in keys.iter() {
+        qb.push(field.column()).push(if *desc { " DESC NULLS LAST, " } else { " ASC NULLS FIRST, " });
+    }
+    qb.push("id ASC");
+}
+
+#[async_trait]
+impl CourseRepository for SqlCourseRepository {
+    async fn list(&self, q: &CourseQuery) -> Result<CoursePage, AppError> {
+        let plan = ListPlan::new(q)?;
+
+        let mut count = SqlBuilder::new("SELECT COUNT(*) FROM courses");
+        push_filters(&mut count, q);
+        let total: i64 = count.build_scalar().fetch_one(&self.pool).await?;
+
+        let mut query = SqlBuilder::new(&self.select());
+        push_filters(&mut query, q);
+        if let Some(cursor) = plan.cursor.as_ref() {
+            push_cursor(&mut query, &plan.keys, cursor);
+        }
+        push_order(&mut query, &plan.keys);
+        // one extra row tells whether there is a next page
+        query.push(" LIMIT ").push_bind(plan.page_size + 1);
+        let page = match plan.cursor {
+            Some(_) => None,
+            None => {
+                let page = q.page.unwrap_or(1);
+                query.push(" OFFSET ").push_bind((page - 1) * plan.page_size);
+                Some(page)
+            }
+        };
+
+        let mut courses = Vec::new();
+        let mut rows = query.build().fetch(&self.pool);
+        while let Some(row) = rows.try_next().await? {
+            courses.push(course_from_row(&row)?);
+        }
+        Ok(plan.page(total, page, courses))
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<Course>, AppError> {
+        let row = SqlBuilder::new(&self.select())
+            .push(" WHERE id = ")
+            .push_bind(id.to_string())
+            .build()
+            .fetch_optional(&self.pool)
+            .await?;
+        row.as_ref().map(course_from_row).transpose()
+    }
+
+    async fn create(&self, info: &Course) -> Result<Course, AppError> {
+        let date_time = Local::now().format(model::TIME_FORMAT).to_string();
+        let id = Uuid::new_v4().to_string();
+
+        let mut query = SqlBuilder::new("INSERT INTO courses (id, teacher_id, name, time, description, format, structure, duration, price, language, level, capacity, version, created_at, updated_at) VALUES (");
+        query.push_bind(id.clone())
+            .push(", ").push_bind(info.teacher_id)
+            .push(", ").push_bind(info.name.clone().unwrap_or_default())
+            .push(", ").push_bind(date_time.clone())
+            .push(", ").push_bind(info.description.clone().unwrap_or_default())
+            .push(", ").push_bind(info.format.clone().unwrap_or_default())
+            .push(", ").push_bind(info.structure.clone().unwrap_or_default())
+            .push(", ").push_bind(info.duration.clone().unwrap_or_default())
+            .push(", ").push_bind(info.price.unwrap_or_default())
+            .push(", ").push_bind(info.language.clone().unwrap_or_default())
+            .push(", ").push_bind(info.level.clone().unwrap_or_default())
+            .push(", ").push_bind(info.capacity)
+            .push(", 1, ").push_bind(date_time.clone())
+            .push(", ").push_bind(date_time)
+            .push(")");
+        query.build().execute(&self.pool).await?;
+        self.get(&id).await?
+            .ok_or_else(|| AppError::NotFound(format!("course {}", id)))
+    }
+
+    async fn update(&self, id: &str, patch: &CoursePatch, expected: Option<i64>) -> Result<Course, AppError> {
+        let mut query = SqlBuilder::new("UPDATE courses SET version = version + 1, updated_at = ");
+        query.push_bind(Local::now().format(model::TIME_FORMAT).to_string());
+        if let Some(teacher_id) = patch.teacher_id {
+            query.push(", teacher_id = ").push_bind(teacher_id);
+        }
+        if let Some(name) = patch.name.clone() {
+            query.push(", name = ").push_bind(name);
+        }
+        if let Some(time) = patch.time {
+            query.push(", time = ").push_bind(time.format(model::TIME_FORMAT).to_string());
+        }
+        if let Some(description) = patch.description.clone() {
+            query.push(", description = ").push_bind(description);
+        }
+        if let Some(format) = patch.format.clone() {
+            query.push(", format = ").push_bind(format);
+        }
+        if let Some(structure) = patch.structure.clone() {
+            query.push(", structure = ").push_bind(structure);
+        }
+        if let Some(duration) = patch.duration.clone() {
+            query.push(", duration = ").push_bind(duration);
+        }
+        if let Some(price) = patch.price {
+            query.push(", price = ").push_bind(price);
+        }
+        if let Some(language) = patch.language.clone() {
+            query.push(", language = ").push_bind(language);
+        }
+        if let Some(level) = patch.level.clone() {
+            query.push(", level = ").push_bind(level);
+        }
+        if let Some(capacity) = patch.capacity {
+            query.push(", capacity = ").push_bind(capacity);
+        }
+        query.push(" WHERE id = ").push_bind(id.to_string());
+        if let Some(version) = expected {
+            query.push(" AND version = ").push_bind(version);
+        }
+
+        let rows_affected = query.build().execute(&self.pool).await?.rows_affected();
+        let course = self.get(id).await?
+            .ok_or_else(|| AppError::NotFound(format!("course {}", id)))?;
+        if rows_affected == 0 {
+            return Err(AppError::PreconditionFailed(format!(
+                "course {} is at version {}", id, course.version.unwrap_or_default()
+            )));
+        }
+        Ok(course)
+    }
+
+    async fn delete(&self, id: &str) -> Result<(), AppError> {
+        let rows_affected = SqlBuilder::new("DELETE FROM courses WHERE id = ")
+            .push_bind(id.to_string())
+            .build()
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if rows_affected == 0 {
+            return Err(AppError::NotFound(format!("course {}", id)));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TeacherRepository for SqlTeacherRepository {
+    async fn list(&self) -> Result<Vec<Teacher>, AppError> {
+        let mut teachers = Vec::new();
+        let mut query = SqlBuilder::new(&self.select());
+        query.push(" ORDER BY id");
+        let mut rows = query.build().fetch(&self.pool);
+        while let Some(row) = rows.try_next().await? {
+            teachers.push(teacher_from_row(&row)?);
+        }
+        Ok(teachers)
+    }
+
+    async fn get(&self, id: i64) -> Result<Option<Teacher>, AppError> {
+        let row = SqlBuilder::new(&self.select())
+            .push(" WHERE id = ")
+            .push_bind(id)
+            .build()
+            .fetch_optional(&self.pool)
+            .await?;
+        row.as_ref().map(teacher_from_row).transpose()
+    }
+
+    async fn create(&self, info: &Teacher) -> Result<Teacher, AppError> {
+        let date_time = Local::now().format(model::TIME_FORMAT).to_string();
+
+        let mut query = SqlBuilder::new("INSERT INTO teachers (name, email, bio, created_at, updated_at) VALUES (");
+        query.push_bind(info.name.clone())
+            .push(", ").push_bind(info.email.clone())
+            .push(", ").push_bind(info.bio.clone())
+            .push(", ").push_bind(date_time.clone())
+            .push(", ").push_bind(date_time)
+            .push(")");
+        // read to the end, SQLite commits the insert only once the statement is done and the
+        // `get` below may run on another connection
+        let id = query.push(" RETURNING id")
+            .build_scalar()
+            .fetch_all(&self.pool)
+            .await?
+            .pop()
+            .ok_or_else(|| AppError::Internal("insert returned no id".to_string()))?;
+        self.get(id).await?
+            .ok_or_else(|| AppError::NotFound(format!("teacher {}", id)))
+    }
+
+    async fn update(&self, id: i64, patch: &TeacherPatch) -> Result<Teacher, AppError> {
+        let mut query = SqlBuilder::new("UPDATE teachers SET updated_at = ");
+        query.push_bind(Local::now().format(model::TIME_FORMAT).to_string());
+        if let Some(name) = patch.name.clone() {
+            query.push(", name = ").push_bind(name);
+        }
+        if let Some(email) = patch.email.clone() {
+            query.push(", email = ").push_bind(email);
+        }
+        if let Some(bio) = patch.bio.clone() {
+            query.push(", bio = ").push_bind(bio);
+        }
+        query.push(" WHERE id = ").push_bind(id);
+        query.build().execute(&self.pool).await?;
+        self.get(id).await?
+            .ok_or_else(|| AppError::NotFound(format!("teacher {}", id)))
+    }
+
+    async fn delete(&self, id: i64, cascade: bool) -> Result<(), AppError> {
+        let mut tx = self.pool.begin().await?;
+        if cascade {
+            SqlBuilder::new("DELETE FROM courses WHERE teacher_id = ")
+                .push_bind(id)
+                .build()
+                .execute(&mut *tx)
+                .await?;
+        } else {
+            // the foreign key rejects it as well, counting gives a better message
+            let count: i64 = SqlBuilder::new("SELECT COUNT(*) FROM courses WHERE teacher_id = ")
+                .push_bind(id)
+                .build_scalar()
+                .fetch_one(&mut *tx)
+                .await?;
+            if count > 0 {
+                return Err(AppError::Conflict(format!(
+                    "teacher {} still has {} courses, delete with cascade=true", id, count
+                )));
+            }
+        }
+        let rows_affected = SqlBuilder::new("DELETE FROM teachers WHERE id = ")
+            .push_bind(id)
+            .build()
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if rows_affected == 0 {
+            return Err(AppError::NotFound(format!("teacher {}", id)));
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl EnrollmentRepository for SqlEnrollmentRepository {
+    async fn enroll(&self, course_id: &str, user_id: &str) -> Result<Enrollment, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let capacity = self.lock_course(&mut tx, course_id).await?;
+        if let Some(e) = self.find(&mut tx, course_id, user_id).await? {
+            return Err(AppError::Conflict(format!("already {} in course {}", e.status, course_id)));
+        }
+        let status = match capacity {
+            Some(capacity) if self.count_enrolled(&mut tx, course_id).await? >= capacity => EnrollmentStatus::Waitlisted,
+            _ => EnrollmentStatus::Enrolled,
+        };
+
+        let date_time = Local::now().format(model::TIME_FORMAT).to_string();
+        let mut query = SqlBuilder::new("INSERT INTO enrollments (course_id, user_id, status, created_at, updated_at) VALUES (");
+        query.push_bind(course_id.to_string())
+            .push(", ").push_bind(user_id.to_string())
+            .push(", ").push_bind(status.to_string())
+            .push(", ").push_bind(date_time.clone())
+            .push(", ").push_bind(date_time)
+            .push(")");
+        query.build().execute(&mut *tx).await?;
+        let enrollment = self.find(&mut tx, course_id, user_id).await?
+            .ok_or_else(|| AppError::NotFound(format!("enrollment of {} in course {}", user_id, course_id)))?;
+        tx.commit().await?;
+        Ok(enrollment)
+    }
+
+    async fn cancel(&self, course_id: &str, user_id: &str) -> Result<(Enrollment, Vec<Enrollment>), AppError> {
+        let mut tx = self.pool.begin().await?;
+        let capacity = self.lock_course(&mut tx, course_id).await?;
+        let enrollment = self.find(&mut tx, course_id, user_id).await?
+            .ok_or_else(|| AppError::NotFound(format!("enrollment of {} in course {}", user_id, course_id)))?;
+        SqlBuilder::new("DELETE FROM enrollments WHERE id = ")
+            .push_bind(enrollment.id)
+            .build()
+            .execute(&mut *tx)
+            .await?;
+        let promoted = self.promote_waitlist(&mut tx, course_id, capacity).await?;
+        tx.commit().await?;
+        Ok((enrollment, promoted))
+    }
+
+    async fn promote(&self, course_id: &str) -> Result<Vec<Enrollment>, AppError> {
+        let mut tx = self.pool.begin().await?;
+        let capacity = self.lock_course(&mut tx, course_id).await?;
+        let promoted = self.promote_waitlist(&mut tx, course_id, capacity).await?;
+        tx.commit().await?;
+        Ok(promoted)
+    }
+
+    async fn list_by_user(&self, user_id: &str) -> Result<Vec<Enrollment>, AppError> {
+        let mut enrollments = Vec::new();
+        let mut query = SqlBuilder::new(&self.select());
+        query.push(" WHERE user_id = ").push_bind(user_id.to_string()).push(" ORDER BY id");
+        let mut rows = query.build().fetch(&self.pool);
+        while let Some(row) = rows.try_next().await? {
+            enrollments.push(enrollment_from_row(&row)?);
+        }
+        Ok(enrollments)
+    }
+}
+
+#[async_trait]
+impl UserRepository for SqlUserRepository {
+    async fn create(&self, info: &User) -> Result<User, AppError> {
+        let date_time = Local::now().format(model::TIME_FORMAT).to_string();
+        let id = Uuid::new_v4().to_string();
+
+        let mut query = SqlBuilder::new("INSERT INTO users (id, username, password_hash, role, teacher_id, failed_logins, created_at, updated_at) VALUES (");
+        query.push_bind(id.clone())
+            .push(", ").push_bind(info.username.clone())
+            .push(", ").push_bind(info.password_hash.clone())
+            .push(", ").push_bind(info.role.to_string())
+            .push(", ").push_bind(info.teacher_id)
+            .push(", 0, ").push_bind(date_time.clone())
+            .push(", ").push_bind(date_time)
+            .push(")");
+        query.build().execute(&self.pool).await?;
+        self.get(&id).await?
+            .ok_or_else(|| AppError::NotFound(format!("user {}", id)))
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<User>, AppError> {
+        self.find("id", id).await
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, AppError> {
+        self.find("username", username).await
+    }
+
+    async fn login_failed(&self, id: &str, max_failed: i64, lock_until: NaiveDateTime) -> Result<(), AppError> {
+        let mut query = SqlBuilder::new("UPDATE users SET locked_until = CASE WHEN failed_logins + 1 >= ");
+        query.push_bind(max_failed)
+            .push(" THEN ").push_bind(lock_until.format(model::TIME_FORMAT).to_string())
+            .push(" ELSE locked_until END, failed_logins = CASE WHEN failed_logins + 1 >= ").push_bind(max_failed)
+            .push(" THEN 0 ELSE failed_logins + 1 END, updated_at = ").push_bind(Local::now().format(model::TIME_FORMAT).to_string())
+            .push(" WHERE id = ").push_bind(id.to_string());
+        query.build().execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn login_succeeded(&self, id: &str) -> Result<(), AppError> {
+        SqlBuilder::new("UPDATE users SET failed_logins = 0, locked_until = NULL, updated_at = ")
+            .push_bind(Local::now().format(model::TIME_FORMAT).to_string())
+            .push(" WHERE id = ").push_bind(id.to_string())
+            .build()
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_role(&self, id: &str, role: Role, teacher_id: Option<i64>) -> Result<User, AppError> {
+        let rows_affected = SqlBuilder::new("UPDATE users SET role = ")
+            .push_bind(role.to_string())
+            .push(", teacher_id = ").push_bind(teacher_id)
+            .push(", updated_at = ").push_bind(Local::now().format(model::TIME_FORMAT).to_string())
+            .push(" WHERE id = ").push_bind(id.to_string())
+            .build()
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        if rows_affected == 0 {
+            return Err(AppError::NotFound(format!("user {}", id)));
+        }
+        self.get(id).await?
+            .ok_or_else(|| AppError::NotFound(format!("user {}", id)))
+    }
+
+    async fn add_refresh_token(&self, token: &RefreshToken) -> Result<(), AppError> {
+        let mut query = SqlBuilder::new("INSERT INTO refresh_tokens (hash, user_id, family, expires_at, revoked, created_at) VALUES (");
+        query.push_bind(token.hash.clone())
+            .push(", ").push_bind(token.user_id.clone())
+            .push(", ").push_bind(token.family.clone())
+            .push(", ").push_bind(token.expires_at.format(model::TIME_FORMAT).to_string())
+            .push(", ").push_bind(i64::from(token.revoked))
+            .push(", ").push_bind(Local::now().format(model::TIME_FORMAT).to_string())
+            .push(")");
+        query.build().execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn use_refresh_token(&self, hash: &str) -> Result<Option<RefreshToken>, AppError> {
+        let sql = "SELECT hash, user_id, family, CAST(expires_at AS TEXT) AS expires_at, revoked FROM refresh_tokens WHERE hash = ";
+        let row = SqlBuilder::new(sql)
+            .push_bind(hash.to_string())
+            .build()
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some(row) = row else { return Ok(None) };
+        let mut token = RefreshToken {
+            hash: row.try_get("hash")?,
+            user_id: row.try_get("user_id")?,
+            family: row.try_get("family")?,
+            expires_at: parse_time(&row, "expires_at")?,
+            revoked: row.try_get::<i64, _>("revoked")? != 0,
+        };
+        if !token.revoked {
+            // of two concurrent refreshes only one flips the flag, the other one is a reuse
+            let rows_affected = SqlBuilder::new("UPDATE refresh_tokens SET revoked = 1 WHERE revoked = 0 AND hash = ")
+                .push_bind(hash.to_string())
+                .build()
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+            token.revoked = rows_affected == 0;
+        }
+        Ok(Some(token))
+    }
+
+    async fn revoke_family(&self, family: &str) -> Result<(), AppError> {
+        SqlBuilder::new("UPDATE refresh_tokens SET revoked = 1 WHERE family = ")
+            .push_bind(family.to_string())
+            .build()
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
@@ -1,23 +1,32 @@
 //! router setting
 use actix_web::web;
 use crate::{
-    middleware,
-    handler::{basic, user, course},
+    error::AppError,
+    middleware::{self, CorsScope},
+    handler::{auth, basic, user, course, enrollment, teacher, err_handlers::ErrorHandlers},
 };
 
 pub fn routes(cfg: &mut web::ServiceConfig) {
     cfg
-        // .wrap(middleware::ReadReqBody)
         // .wrap(middleware::Jwt)
         // // .wrap(middleware::AccessLogging)
         // .wrap(middleware::AccessLogging::default().log_target("http_log"))
         // .app_data(counter.clone()) // <- register the created data
-        .service(basic::index)
-
         .service(
             // /app
             web::scope("/app")
+                // inside Jwt so clients are keyed by their subject
+                .wrap(middleware::RateLimit)
                 .wrap(middleware::Jwt)
+                .wrap(middleware::Sign)
+                .wrap(ErrorHandlers::api())
+                // outermost, preflight requests carry no credentials
+                .wrap(middleware::cors(CorsScope::App))
+                // malformed json bodies and query strings get the same error shape as the handlers
+                .app_data(web::JsonConfig::default()
+                    .error_handler(|err, _req| AppError::BadRequest(err.to_string()).into()))
+                .app_data(web::QueryConfig::default()
+                    .error_handler(|err, _req| AppError::BadRequest(err.to_string()).into()))
                 // .route("/user", web::post().to(user::user_handler))
                 .route("/greet", web::get().to(basic::greet))
                 .route("/state", web::post().to(basic::state))
@@ -26,8 +35,38 @@ pub fn routes(cfg: &mut web::ServiceConfig) {
                 .service(user::json_handler)
                 .service(user::payload_handler)
                 .service(course::get_courses)
+                .service(course::get_course)
                 .service(course::add_courses)
                 .service(course::del_courses)
-                .service(course::update_courses),
+                .service(course::update_courses)
+                .service(teacher::get_teachers)
+                .service(teacher::get_teacher)
+                .service(teacher::get_teacher_courses)
+                .service(teacher::add_teacher)
+                .service(teacher::del_teacher)
+                .service(teacher::update_teacher)
+                .service(enrollment::enroll)
+                .service(enrollment::unenroll)
+                .service(enrollment::my_enrollments)
+                .service(auth::set_role),
+        )
+        .service(
+            // /auth, no token needed, login attempts are limited per ip
+            web::scope("/auth")
+                .wrap(middleware::RateLimit)
+                .wrap(ErrorHandlers::api())
+                .wrap(middleware::cors(CorsScope::App))
+                .app_data(web::JsonConfig::default()
+                    .error_handler(|err, _req| AppError::BadRequest(err.to_string()).into()))
+                .service(auth::register)
+                .service(auth::login)
+                .service(auth::refresh)
+                .service(auth::logout),
+        )
+        .service(
+            // everything else, registered last as the empty prefix matches every path
+            web::scope("")
+                .wrap(middleware::cors(CorsScope::Public))
+                .service(basic::index),
         );
 }
@@ -1,47 +1,285 @@
-use std::env;
-use std::path::Path;
-use std::{fs::File, io::BufReader};
-
-use rustls::{Certificate, PrivateKey, ServerConfig};
-// use rustls_pemfile::{certs, pkcs8_private_keys, rsa_private_keys, ec_private_keys};
-
-pub fn load_tls_config() -> ServerConfig {
-    // init server config builder with safe defaults
-    let mut cwd = env::current_dir().unwrap();
-    cwd.push(Path::new("conf"));
-    cwd.push(Path::new("server.pem"));
-    let cert_path = cwd.to_str().unwrap();
-
-    let mut cwd = env::current_dir().unwrap();
-    cwd.push(Path::new("conf"));
-    cwd.push(Path::new("server-key.pem"));
-    let key_path = cwd.to_str().unwrap();
-
-    let config = ServerConfig::builder()
-        .with_safe_defaults()
-        .with_no_client_auth();
-
-    // load TLS key/cert files
-    let cert_file = &mut BufReader::new(File::open(cert_path.clone()).unwrap());
-    let key_file = &mut BufReader::new(File::open(key_path.clone()).unwrap());
-
-    // convert files to key/cert objects
-    let cert_chain = rustls_pemfile::certs(cert_file)
-        .unwrap()
-        .into_iter()
-        .map(Certificate)
-        .collect();
-    let mut keys: Vec<PrivateKey> = rustls_pemfile::ec_private_keys(key_file)
-        .unwrap()
-        .into_iter()
-        .map(PrivateKey)
-        .collect();
-
-    // exit if no keys could be parsed
-    if keys.is_empty() {
-        eprintln!("Could not locate EC private keys.");
-        std::process::exit(1);
-    }
-
-    config.with_single_cert(cert_chain, keys.remove(0)).unwrap()
+//! Client address behind reverse proxies.
+//!
+//! Forwarding headers are only believed when the peer is one of the proxies listed in
+//! `[proxy] trusted`. The hops are then read from right to left, the nearest one first,
+//! and the first address that is not a trusted proxy is the client. A hop that cannot be
+//! parsed ends the walk, nothing to its left can be trusted.
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use actix_web::dev::Payload;
+use actix_web::http::header::HeaderMap;
+use actix_web::{FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+use serde::Deserialize;
+
+use crate::conf::config;
+use crate::error::AppError;
+
+pub const FORWARDED: &str = "forwarded";
+pub const X_FORWARDED_FOR: &str = "x-forwarded-for";
+pub const X_REAL_IP: &str = "x-real-ip";
+
+/// Network like `10.0.0.0/8` or `fd00::/8`, a plain address is a single host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual-stack listener show up as ::ffff:a.b.c.d
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            ip => ip,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| format!("invalid address in {:?}", s))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.trim().parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(|| format!("invalid prefix in {:?}", s))?,
+            None => max,
+        };
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Address of a `Forwarded` node or `X-Forwarded-For` entry, ports and quotes are dropped.
+/// `None` for `unknown`, obfuscated identifiers and garbage.
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Some(rest) = node.strip_prefix('[') {
+        let (v6, port) = rest.split_once(']')?;
+        if !(port.is_empty() || port.starts_with(':')) {
+            return None;
+        }
+        return v6.parse::<std::net::Ipv6Addr>().ok().map(IpAddr::V6);
+    }
+    node.parse::<IpAddr>()
+        .ok()
+        .or_else(|| node.parse::<SocketAddr>().ok().map(|a| a.ip()))
+}
+
+/// `for=` values of all `Forwarded` headers, nearest hop last.
+fn forwarded_hops(headers: &HeaderMap) -> Option<Vec<String>> {
+    let mut hops = Vec::new();
+    for value in headers.get_all(FORWARDED) {
+        // a header that is not text cannot be read, so none of its hops can be trusted
+        let Ok(value) = value.to_str() else {
+            hops.push(String::new());
+            continue;
+        };
+        for element in value.split(',') {
+            let node = element
+                .split(';')
+                .filter_map(|pair| pair.split_once('='))
+                .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                .map(|(_, node)| node.to_string());
+            hops.push(node.unwrap_or_default());
+        }
+    }
+    (!hops.is_empty()).then_some(hops)
+}
+
+fn forwarded_for_hops(headers: &HeaderMap) -> Option<Vec<String>> {
+    let mut hops = Vec::new();
+    for value in headers.get_all(X_FORWARDED_FOR) {
+        match value.to_str() {
+            Ok(value) => hops.extend(value.split(',').map(str::to_string)),
+            Err(_) => hops.push(String::new()),
+        }
+    }
+    (!hops.is_empty()).then_some(hops)
+}
+
+/// Client address of a request from `peer`, see the module docs.
+///
+/// `Forwarded` wins over `X-Forwarded-For`, `X-Real-IP` is used when neither is sent.
+pub fn resolve(peer: IpAddr, headers: &HeaderMap, trusted: &[Cidr]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|net| net.contains(ip));
+    if !is_trusted(peer) {
+        return peer;
+    }
+    let hops = match forwarded_hops(headers).or_else(|| forwarded_for_hops(headers)) {
+        Some(hops) => hops,
+        None => {
+            let real_ip = headers.get(X_REAL_IP).and_then(|v| v.to_str().ok());
+            return real_ip.and_then(parse_node).unwrap_or(peer);
+        }
+    };
+
+    let mut client = peer;
+    for hop in hops.iter().rev() {
+        match parse_node(hop) {
+            Some(ip) => {
+                client = ip;
+                if !is_trusted(ip) {
+                    break;
+                }
+            }
+            None => {
+                debug!("unparsable forwarded hop {:?}, client is {}", hop, client);
+                break;
+            }
+        }
+    }
+    client
+}
+
+/// Client address of `req` with the trusted proxies of the current config.
+pub fn client_ip(req: &HttpRequest) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    let conf = config::GLOBAL_CONFIG.load();
+    Some(resolve(peer, req.headers(), conf.proxy.trusted.as_deref().unwrap_or_default()))
+}
+
+/// Client address, see [`client_ip`]. Rejected with 400 when the peer address is unknown,
+/// which only happens for requests that did not come over a socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+impl fmt::Display for ClientIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromRequest for ClientIp {
+    type Error = AppError;
+    type Future = Ready<Result<ClientIp, AppError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(client_ip(req).map(ClientIp).ok_or_else(|| AppError::BadRequest("unknown client address".to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    fn headers(pairs: &[(&'static str, &[u8])]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(HeaderName::from_static(name), HeaderValue::from_bytes(value).unwrap());
+        }
+        map
+    }
+
+    fn trusted() -> Vec<Cidr> {
+        ["10.0.0.0/8", "127.0.0.1", "fd00::/8"].iter().map(|c| c.parse().unwrap()).collect()
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn cidr() {
+        let net: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains(ip("10.1.255.3")));
+        assert!(!net.contains(ip("10.2.0.1")));
+        assert!(net.contains(ip("::ffff:10.1.0.9")));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("8.8.8.8")));
+        assert!("2001:db8::/32".parse::<Cidr>().unwrap().contains(ip("2001:db8:1::5")));
+        assert!(!"2001:db8::/32".parse::<Cidr>().unwrap().contains(ip("10.1.0.1")));
+        assert_eq!("::1".parse::<Cidr>().unwrap().to_string(), "::1/128");
+        for bad in ["10.0.0.0/33", "::/129", "10.0.0/8", "host/8", "10.0.0.0/x", ""] {
+            assert!(bad.parse::<Cidr>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn untrusted_peer_ignores_headers() {
+        let h = headers(&[(X_FORWARDED_FOR, b"1.2.3.4"), (X_REAL_IP, b"1.2.3.4")]);
+        assert_eq!(resolve(ip("203.0.113.9"), &h, &trusted()), ip("203.0.113.9"));
+    }
+
+    #[test]
+    fn forwarded_for_right_to_left() {
+        // the client spoofed 6.6.6.6, the proxies appended the rest
+        let h = headers(&[(X_FORWARDED_FOR, b"6.6.6.6, 198.51.100.7, 10.0.0.2")]);
+        assert_eq!(resolve(ip("10.0.0.1"), &h, &trusted()), ip("198.51.100.7"));
+        // split over several headers
+        let h = headers(&[(X_FORWARDED_FOR, b"6.6.6.6"), (X_FORWARDED_FOR, b"198.51.100.7:5000,10.0.0.2")]);
+        assert_eq!(resolve(ip("10.0.0.1"), &h, &trusted()), ip("198.51.100.7"));
+        // only proxies: the leftmost hop
+        let h = headers(&[(X_FORWARDED_FOR, b"10.9.9.9, 10.0.0.2")]);
+        assert_eq!(resolve(ip("127.0.0.1"), &h, &trusted()), ip("10.9.9.9"));
+    }
+
+    #[test]
+    fn forwarded_header() {
+        let h = headers(&[(FORWARDED, b"for=6.6.6.6, for=\"[2001:db8:cafe::17]:4711\";proto=https, For=10.0.0.2;by=10.0.0.1")]);
+        assert_eq!(resolve(ip("10.0.0.1"), &h, &trusted()), ip("2001:db8:cafe::17"));
+        // Forwarded wins over X-Forwarded-For
+        let h = headers(&[(FORWARDED, b"for=192.0.2.60"), (X_FORWARDED_FOR, b"198.51.100.7")]);
+        assert_eq!(resolve(ip("10.0.0.1"), &h, &trusted()), ip("192.0.2.60"));
+        // element without for=
+        let h = headers(&[(FORWARDED, b"for=192.0.2.60, proto=https;by=10.0.0.5")]);
+        assert_eq!(resolve(ip("10.0.0.1"), &h, &trusted()), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn ipv6() {
+        let h = headers(&[(X_FORWARDED_FOR, b"2001:db8::1, fd00::2")]);
+        assert_eq!(resolve(ip("fd00::1"), &h, &trusted()), ip("2001:db8::1"));
+        let h = headers(&[(X_FORWARDED_FOR, b"[2001:db8::1]:443")]);
+        assert_eq!(resolve(ip("::ffff:127.0.0.1"), &h, &trusted()), ip("2001:db8::1"));
+        let h = headers(&[(X_REAL_IP, b"2001:db8::9")]);
+        assert_eq!(resolve(ip("fd12::1"), &h, &trusted()), ip("2001:db8::9"));
+    }
+
+    #[test]
+    fn malformed_hops_stop_the_walk() {
+        for value in [&b"1.2.3.4, garbage, 10.0.0.2"[..], b"1.2.3.4,,10.0.0.2", b"1.2.3.4, 10.0.0.256, 10.0.0.2", b"1.2.3.4, [2001:db8::1, 10.0.0.2"] {
+            let h = headers(&[(X_FORWARDED_FOR, value)]);
+            assert_eq!(resolve(ip("10.0.0.1"), &h, &trusted()), ip("10.0.0.2"), "{:?}", value);
+        }
+        let h = headers(&[(FORWARDED, b"for=1.2.3.4, for=unknown, for=10.0.0.2")]);
+        assert_eq!(resolve(ip("10.0.0.1"), &h, &trusted()), ip("10.0.0.2"));
+        let h = headers(&[(FORWARDED, b"for=1.2.3.4, for=_hidden")]);
+        assert_eq!(resolve(ip("10.0.0.1"), &h, &trusted()), ip("10.0.0.1"));
+        let h = headers(&[(X_FORWARDED_FOR, b"1.2.3.4, \xff\xfe")]);
+        assert_eq!(resolve(ip("10.0.0.1"), &h, &trusted()), ip("10.0.0.1"));
+        let h = headers(&[(X_REAL_IP, b"not an ip")]);
+        assert_eq!(resolve(ip("10.0.0.1"), &h, &trusted()), ip("10.0.0.1"));
+        let h = headers(&[(X_FORWARDED_FOR, b"[2001:db8::1]x")]);
+        assert_eq!(resolve(ip("10.0.0.1"), &h, &trusted()), ip("10.0.0.1"));
+    }
 }
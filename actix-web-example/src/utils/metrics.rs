@@ -0,0 +1,143 @@
+//! Prometheus metrics, rendered by `GET /sys/metrics`.
+//!
+//! Request metrics are recorded by [`crate::middleware::AccessLogging`], labeled by the
+//! matched route pattern so path parameters do not create new series.
+use std::time::Instant;
+
+use actix_web::http::StatusCode;
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+use crate::conf::config;
+
+/// Route label of requests that matched no resource.
+pub const UNMATCHED: &str = "unmatched";
+
+pub static REGISTRY: Lazy<Registry> = Lazy::new(Registry::new);
+
+pub static HTTP_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+    Opts::new("http_requests_total", "HTTP requests served"),
+    &["method", "route", "status"],
+)));
+
+pub static HTTP_DURATION: Lazy<HistogramVec> = Lazy::new(|| register(HistogramVec::new(
+    HistogramOpts::new("http_request_duration_seconds", "Time until the response head is ready"),
+    &["method", "route", "status"],
+)));
+
+pub static HTTP_IN_FLIGHT: Lazy<IntGauge> = Lazy::new(|| register(IntGauge::new(
+    "http_requests_in_flight",
+    "HTTP requests currently being handled",
+)));
+
+pub static DB_POOL_CONNECTIONS: Lazy<IntGaugeVec> = Lazy::new(|| register(IntGaugeVec::new(
+    Opts::new("db_pool_connections", "Connections of the sqlx pool by state"),
+    &["state"],
+)));
+
+pub static DB_POOL_MAX: Lazy<IntGauge> = Lazy::new(|| register(IntGauge::new(
+    "db_pool_max_connections",
+    "Upper bound of the sqlx pool",
+)));
+
+pub static SCHEDULER_RUNS: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+    Opts::new("scheduler_job_runs_total", "Runs of the cron jobs"),
+    &["job"],
+)));
+
+pub static SCHEDULER_FAILURES: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+    Opts::new("scheduler_job_failures_total", "Failed runs of the cron jobs"),
+    &["job"],
+)));
+
+pub static RATE_LIMITED: Lazy<IntCounterVec> = Lazy::new(|| register(IntCounterVec::new(
+    Opts::new("http_rate_limited_total", "Requests rejected by the rate limiter"),
+    &["group"],
+)));
+
+/// `POST /app/state` calls, formerly the `AppStateWithCounter` demo.
+pub static STATE_REQUESTS: Lazy<IntCounter> = Lazy::new(|| register(IntCounter::new(
+    "state_requests_total",
+    "Calls of POST /app/state",
+)));
+
+/// One request seen by the middleware, counted as in flight until dropped.
+pub struct RequestTimer {
+    method: &'static str,
+    route: String,
+    start: Instant,
+}
+
+impl RequestTimer {
+    /// `route` is the matched pattern like `/app/courses/{id}`.
+    pub fn start(method: &str, route: Option<String>) -> RequestTimer {
+        HTTP_IN_FLIGHT.inc();
+        RequestTimer {
+            method: method_label(method),
+            route: route.unwrap_or_else(|| UNMATCHED.to_string()),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn finish(self, status: StatusCode) {
+        let labels = [self.method, self.route.as_str(), status.as_str()];
+        HTTP_REQUESTS.with_label_values(&labels).inc();
+        HTTP_DURATION.with_label_values(&labels).observe(self.start.elapsed().as_secs_f64());
+    }
+}
+
+/// Label of `method`, extension methods share `other` so clients cannot add series.
+fn method_label(method: &str) -> &'static str {
+    match method {
+        "GET" => "GET",
+        "HEAD" => "HEAD",
+        "POST" => "POST",
+        "PUT" => "PUT",
+        "PATCH" => "PATCH",
+        "DELETE" => "DELETE",
+        "OPTIONS" => "OPTIONS",
+        "CONNECT" => "CONNECT",
+        "TRACE" => "TRACE",
+        _ => "other",
+    }
+}
+
+impl Drop for RequestTimer {
+    fn drop(&mut self) {
+        HTTP_IN_FLIGHT.dec();
+    }
+}
+
+fn register<M: prometheus::core::Collector + Clone + 'static>(metric: prometheus::Result<M>) -> M {
+    let metric = metric.expect("valid metric");
+    REGISTRY.register(Box::new(metric.clone())).expect("metric registered once");
+    metric
+}
+
+/// All metrics in the Prometheus text format.
+pub fn render() -> String {
+    // pool gauges are sampled at scrape time, an untouched pool is not created for it
+    if let Some(pool) = Lazy::get(&config::DB_POOL) {
+        let idle = pool.num_idle() as i64;
+        DB_POOL_CONNECTIONS.with_label_values(&["idle"]).set(idle);
+        DB_POOL_CONNECTIONS.with_label_values(&["in_use"]).set(pool.size() as i64 - idle);
+        DB_POOL_MAX.set(pool.options().get_max_connections() as i64);
+    }
+    // register the metrics nothing touched yet
+    Lazy::force(&HTTP_REQUESTS);
+    Lazy::force(&HTTP_DURATION);
+    Lazy::force(&HTTP_IN_FLIGHT);
+    Lazy::force(&SCHEDULER_RUNS);
+    Lazy::force(&SCHEDULER_FAILURES);
+    Lazy::force(&RATE_LIMITED);
+    Lazy::force(&STATE_REQUESTS);
+
+    let mut buf = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buf) {
+        error!("metrics encode error: {}", e);
+    }
+    String::from_utf8(buf).unwrap_or_default()
+}
@@ -1,6 +1,10 @@
 pub mod signal;
+pub mod shutdown;
+pub mod metrics;
+pub mod request_id;
 pub mod scheduler;
 pub mod log;
 pub mod counter;
 pub mod hmac;
 pub mod ip;
+pub mod tls;
@@ -0,0 +1,100 @@
+//! Request correlation id, set by [`crate::middleware::RequestId`].
+//!
+//! While a request is handled its id is available from [`current`] and in the log4rs MDC
+//! under [`MDC_KEY`], so every log line of the request can print it with
+//! `{X(request_id)(-)}`. Tasks spawned by a handler do not inherit it, wrap them in
+//! [`scope`] to keep the id.
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_web::{dev::Payload, FromRequest, HttpMessage, HttpRequest};
+use futures::future::{ready, Ready};
+use pin_project_lite::pin_project;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const MDC_KEY: &str = "request_id";
+/// longer incoming ids are replaced
+const MAX_LEN: usize = 64;
+
+tokio::task_local! {
+    static CURRENT: ReqId;
+}
+
+/// Id of the request being handled, also an extractor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReqId(String);
+
+impl ReqId {
+    pub fn generate() -> ReqId {
+        ReqId(Uuid::new_v4().to_string())
+    }
+
+    /// Accepts an id sent by a client, `None` if it is empty, too long or has characters
+    /// other than ASCII letters, digits, `-`, `_`, `.` and `:`.
+    pub fn parse(id: &str) -> Option<ReqId> {
+        let valid = !id.is_empty()
+            && id.len() <= MAX_LEN
+            && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b));
+        valid.then(|| ReqId(id.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ReqId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromRequest for ReqId {
+    type Error = Infallible;
+    type Future = Ready<Result<ReqId, Infallible>>;
+
+    /// The id set by the middleware, a fresh one when it is not installed.
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(Ok(req.extensions().get::<ReqId>().cloned().unwrap_or_else(ReqId::generate)))
+    }
+}
+
+/// Id of the request the current task is handling.
+pub fn current() -> Option<ReqId> {
+    CURRENT.try_with(ReqId::clone).ok()
+}
+
+/// Runs `fut` with `id` as [`current`] request id and in the log MDC.
+pub fn scope<F: Future>(id: ReqId, fut: F) -> impl Future<Output = F::Output> {
+    let mdc = id.0.clone();
+    CURRENT.scope(id, WithMdc { id: mdc, fut })
+}
+
+pin_project! {
+    /// The MDC is thread-local, so it is set around every poll of the request future.
+    struct WithMdc<F> {
+        id: String,
+        #[pin]
+        fut: F,
+    }
+}
+
+impl<F: Future> Future for WithMdc<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let previous = log_mdc::get(MDC_KEY, |v| v.map(str::to_string));
+        log_mdc::insert(MDC_KEY, this.id.as_str());
+        let poll = this.fut.poll(cx);
+        match previous {
+            Some(previous) => log_mdc::insert(MDC_KEY, previous),
+            None => log_mdc::remove(MDC_KEY),
+        };
+        poll
+    }
+}
@@ -1,17 +1,31 @@
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use async_trait::async_trait;
 use tokio_cron_scheduler::{JobScheduler, Job, JobSchedulerError};
 use log::*;
 
+use super::metrics;
+
 #[async_trait]
 pub trait JobTrait {
-    /// run job
-    async fn run(&self, expression: &str) -> Result<(), JobSchedulerError>;
+    /// run job, the returned scheduler keeps running until it is shut down
+    async fn run(&self, expression: &str) -> Result<JobScheduler, JobSchedulerError>;
 }
 
 pub struct SchedulerJob;
 
+/// unix seconds of the latest tick, 0 before the first one
+static LAST_TICK: AtomicU64 = AtomicU64::new(0);
+
+/// Unix seconds of the latest tick of the ticker job, read by the readiness check.
+pub fn last_tick() -> Option<u64> {
+    match LAST_TICK.load(Ordering::Relaxed) {
+        0 => None,
+        tick => Some(tick),
+    }
+}
+
 pub fn build() -> Result<SchedulerJob, &'static str> {
     Ok(SchedulerJob {})
 }
@@ -24,7 +38,7 @@ impl SchedulerJob {
 
 #[async_trait]
 impl JobTrait for SchedulerJob {
-    async fn run(&self, expr: &str) -> Result<(), JobSchedulerError> {
+    async fn run(&self, expr: &str) -> Result<JobScheduler, JobSchedulerError> {
         // let mut sched = JobScheduler::new().await?;
         let sched = JobScheduler::new().await?;
 
@@ -38,8 +52,15 @@ impl JobTrait for SchedulerJob {
         let job_async = Job::new_async(expr, |_uuid, _l| {
             Box::pin(async move {
                 match SystemTime::now().duration_since(UNIX_EPOCH) {
-                    Ok(n) => info!("ticker:{:?}ms", n.as_micros()),
-                    Err(_) => error!("system time error")
+                    Ok(n) => {
+                        LAST_TICK.store(n.as_secs(), Ordering::Relaxed);
+                        metrics::SCHEDULER_RUNS.with_label_values(&["ticker"]).inc();
+                        info!("ticker:{:?}ms", n.as_micros())
+                    }
+                    Err(_) => {
+                        metrics::SCHEDULER_FAILURES.with_label_values(&["ticker"]).inc();
+                        error!("system time error")
+                    }
                 }
             })
         })?;
@@ -47,6 +68,6 @@ impl JobTrait for SchedulerJob {
         sched.add(job_async).await?;
         sched.start().await?;
 
-        Ok(())
+        Ok(sched)
     }
 }
@@ -0,0 +1,174 @@
+//! Stops the server, the cron scheduler and the database pool in order.
+use actix_web::dev::ServerHandle;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tokio::sync::watch;
+use tokio_cron_scheduler::JobScheduler;
+
+use crate::conf::config;
+use super::signal;
+
+/// Where a shutdown currently is, reported by `/sys/stop`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Running,
+    /// a stop was requested, the coordinator picks it up next
+    Requested,
+    /// workers finish in-flight requests for up to `shutdown.drain_timeout` seconds
+    Draining,
+    StoppingScheduler,
+    ClosingDb,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Progress {
+    pub phase: Phase,
+    /// `None` until a stop is requested
+    pub graceful: Option<bool>,
+}
+
+/// Single place that shuts the process down, shared as `web::Data<Shutdown>`.
+///
+/// [`Shutdown::run`] waits for SIGTERM/SIGINT or [`Shutdown::stop`], then stops the
+/// registered server and scheduler and closes [`config::DB_POOL`]. Parts that were never
+/// registered are skipped.
+pub struct Shutdown {
+    server: Mutex<Option<ServerHandle>>,
+    scheduler: Mutex<Option<JobScheduler>>,
+    phase: Mutex<Phase>,
+    requested: watch::Sender<Option<bool>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Shutdown {
+            server: Mutex::new(None),
+            scheduler: Mutex::new(None),
+            phase: Mutex::new(Phase::Running),
+            requested: watch::channel(None).0,
+        }
+    }
+}
+
+impl Shutdown {
+    /// Sets the server to stop, it should be built with `disable_signals()`.
+    pub fn register(&self, handle: ServerHandle) {
+        *self.server.lock() = Some(handle);
+    }
+
+    pub fn register_scheduler(&self, scheduler: JobScheduler) {
+        *self.scheduler.lock() = Some(scheduler);
+    }
+
+    /// Requests a stop, only the first request counts.
+    pub fn stop(&self, graceful: bool) -> Progress {
+        let first = self.requested.send_if_modified(|requested| {
+            if requested.is_some() {
+                return false;
+            }
+            *requested = Some(graceful);
+            true
+        });
+        if first {
+            self.set_phase(Phase::Requested);
+        }
+        self.progress()
+    }
+
+    pub fn progress(&self) -> Progress {
+        Progress {
+            phase: *self.phase.lock(),
+            graceful: *self.requested.borrow(),
+        }
+    }
+
+    fn set_phase(&self, phase: Phase) {
+        let mut current = self.phase.lock();
+        // a late stop request must not move a finished shutdown back
+        if phase > Phase::Requested || *current == Phase::Running {
+            info!("shutdown: {:?}", phase);
+            *current = phase;
+        }
+    }
+
+    /// Waits for a stop request or signal and carries it out.
+    pub async fn run(&self) {
+        let mut requested = self.requested.subscribe();
+        let graceful = tokio::select! {
+            () = signal::shutdown() => {
+                self.stop(true);
+                true
+            }
+            Ok(graceful) = requested.wait_for(Option::is_some) => graceful.unwrap_or(true),
+        };
+
+        self.set_phase(Phase::Draining);
+        let server = self.server.lock().take();
+        if let Some(server) = server {
+            server.stop(graceful).await;
+        }
+
+        self.set_phase(Phase::StoppingScheduler);
+        let scheduler = self.scheduler.lock().take();
+        if let Some(mut scheduler) = scheduler {
+            if let Err(e) = scheduler.shutdown().await {
+                error!("scheduler shutdown error: {:?}", e);
+            }
+        }
+
+        self.set_phase(Phase::ClosingDb);
+        if let Some(pool) = Lazy::get(&config::DB_POOL) {
+            pool.close().await;
+        }
+        self.set_phase(Phase::Stopped);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[actix_web::test]
+    async fn run_carries_out_a_stop_request() {
+        let shutdown = Arc::new(Shutdown::default());
+        assert_eq!(shutdown.progress().phase, Phase::Running);
+        assert_eq!(shutdown.progress().graceful, None);
+        let run = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.run().await }
+        });
+
+        let progress = shutdown.stop(false);
+        assert!(progress.phase >= Phase::Requested, "{:?}", progress);
+        assert_eq!(progress.graceful, Some(false));
+        // nothing registered, every phase is passed through
+        tokio::time::timeout(Duration::from_secs(5), run).await.expect("shutdown finished").unwrap();
+        assert_eq!(shutdown.progress().phase, Phase::Stopped);
+    }
+
+    #[actix_web::test]
+    async fn only_the_first_stop_counts() {
+        let shutdown = Shutdown::default();
+        assert_eq!(shutdown.stop(true).phase, Phase::Requested);
+        let second = shutdown.stop(false);
+        assert_eq!((second.phase, second.graceful), (Phase::Requested, Some(true)));
+
+        shutdown.run().await;
+        let late = shutdown.stop(false);
+        assert_eq!((late.phase, late.graceful), (Phase::Stopped, Some(true)));
+    }
+
+    #[test]
+    fn phases_only_move_forward() {
+        let shutdown = Shutdown::default();
+        shutdown.set_phase(Phase::Draining);
+        shutdown.set_phase(Phase::Requested);
+        assert_eq!(shutdown.progress().phase, Phase::Draining);
+    }
+}
@@ -8,9 +8,24 @@ pub async fn shutdown() {
     imp::shutdown().await
 }
 
+/// SIGHUP, the conventional request to reload the config.
+pub struct Hangup(imp::Hangup);
+
+/// Starts listening for SIGHUP, signals received before the next [`Hangup::recv`] are not lost.
+pub fn hangup() -> Hangup {
+    Hangup(imp::Hangup::new())
+}
+
+impl Hangup {
+    /// Completes on the next SIGHUP. Never completes where there is no SIGHUP.
+    pub async fn recv(&mut self) {
+        self.0.recv().await
+    }
+}
+
 #[cfg(unix)]
 mod imp {
-    use tokio::signal::unix::{signal, SignalKind};
+    use tokio::signal::unix::{signal, Signal, SignalKind};
     use log::*;
 
     pub(super) async fn shutdown() {
@@ -36,6 +51,19 @@ mod imp {
             name,
         );
     }
+
+    pub(super) struct Hangup(Signal);
+
+    impl Hangup {
+        pub(super) fn new() -> Hangup {
+            Hangup(signal(SignalKind::hangup()).expect("Failed to register signal handler"))
+        }
+
+        pub(super) async fn recv(&mut self) {
+            self.0.recv().await;
+            info!(target: "linkerd_proxy::signal", "received SIGHUP, reloading config");
+        }
+    }
 }
 
 #[cfg(not(unix))]
@@ -57,4 +85,16 @@ mod imp {
             "received Ctrl-C, starting shutdown",
         );
     }
+
+    pub(super) struct Hangup;
+
+    impl Hangup {
+        pub(super) fn new() -> Hangup {
+            Hangup
+        }
+
+        pub(super) async fn recv(&mut self) {
+            std::future::pending::<()>().await
+        }
+    }
 }
@@ -1,47 +1,296 @@
+//! TLS listeners, configured in the `[tls]` section.
+//!
+//! The certificate is served through a resolver, so [`reload`] swaps it for new handshakes
+//! while open connections keep the one they started with. [`spawn_reload`] reloads it when
+//! the files change. With `client_ca` set, client certificates are verified and the
+//! identity of the peer is available as [`ClientIdentity`].
+use std::any::Any;
 use std::env;
-use std::path::Path;
-use std::{fs::File, io::BufReader};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use rustls::{Certificate, PrivateKey, ServerConfig};
-// use rustls_pemfile::{certs, pkcs8_private_keys, rsa_private_keys, ec_private_keys};
+use actix_tls::accept::rustls::TlsStream;
+use actix_web::dev::{Extensions, Payload};
+use actix_web::rt::net::TcpStream;
+use actix_web::{FromRequest, HttpRequest};
+use arc_swap::ArcSwapOption;
+use chrono::{DateTime, TimeZone, Utc};
+use futures::future::{ready, Ready};
+use rustls::server::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, ClientHello, NoClientAuth,
+    ResolvesServerCert,
+};
+use rustls::sign::{self, CertifiedKey};
+use ring::signature;
+use rustls::{Certificate, PrivateKey, RootCertStore, ServerConfig, SignatureScheme};
+use rustls_pemfile::Item;
+use tokio::sync::mpsc;
+use x509_parser::pem::parse_x509_pem;
+use x509_parser::prelude::{FromDer, X509Certificate};
 
-pub fn load_tls_config() -> ServerConfig {
-    // init server config builder with safe defaults
-    let mut cwd = env::current_dir().unwrap();
-    cwd.push(Path::new("conf"));
-    cwd.push(Path::new("server.pem"));
-    let cert_path = cwd.to_str().unwrap();
+use crate::conf::{config, watch};
+use crate::error::AppError;
 
-    let mut cwd = env::current_dir().unwrap();
-    cwd.push(Path::new("conf"));
-    cwd.push(Path::new("server-key.pem"));
-    let key_path = cwd.to_str().unwrap();
+/// certificate handed out to new handshakes, empty until the first [`reload`]
+static CURRENT: ArcSwapOption<CertifiedKey> = ArcSwapOption::const_empty();
 
-    let config = ServerConfig::builder()
+fn path(configured: Option<&str>, default: &str) -> PathBuf {
+    let mut cwd = env::current_dir().unwrap_or_default();
+    cwd.push(Path::new(configured.filter(|p| !p.is_empty()).unwrap_or(default)));
+    cwd
+}
+
+/// Certificate chain served on the TLS listeners.
+pub fn cert_path() -> PathBuf {
+    path(config::GLOBAL_CONFIG.load().tls.cert.as_deref(), "conf/server.pem")
+}
+
+fn key_path() -> PathBuf {
+    path(config::GLOBAL_CONFIG.load().tls.key.as_deref(), "conf/server-key.pem")
+}
+
+/// `notAfter` of the leaf certificate in [`cert_path`].
+pub fn cert_not_after() -> Result<DateTime<Utc>, String> {
+    let path = cert_path();
+    let pem = std::fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let (_, pem) = parse_x509_pem(&pem).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let cert = pem.parse_x509().map_err(|e| format!("{}: {}", path.display(), e))?;
+    Utc.timestamp_opt(cert.validity().not_after.timestamp(), 0)
+        .single()
+        .ok_or_else(|| format!("{}: invalid notAfter", path.display()))
+}
+
+/// Server config of the TLS listeners, loads the certificate and the client CAs.
+pub fn load_tls_config() -> Result<ServerConfig, String> {
+    reload()?;
+    let tls = config::GLOBAL_CONFIG.load().tls.clone();
+    let verifier = match tls.client_ca.as_deref().filter(|ca| !ca.is_empty()) {
+        None => NoClientAuth::new(),
+        Some(ca) => {
+            let roots = client_roots(&path(Some(ca), ca))?;
+            if tls.client_auth_required.unwrap_or(false) {
+                AllowAnyAuthenticatedClient::new(roots)
+            } else {
+                AllowAnyAnonymousOrAuthenticatedClient::new(roots)
+            }
+        }
+    };
+    Ok(ServerConfig::builder()
         .with_safe_defaults()
-        .with_no_client_auth();
-
-    // load TLS key/cert files
-    let cert_file = &mut BufReader::new(File::open(cert_path.clone()).unwrap());
-    let key_file = &mut BufReader::new(File::open(key_path.clone()).unwrap());
-
-    // convert files to key/cert objects
-    let cert_chain = rustls_pemfile::certs(cert_file)
-        .unwrap()
-        .into_iter()
-        .map(Certificate)
-        .collect();
-    let mut keys: Vec<PrivateKey> = rustls_pemfile::ec_private_keys(key_file)
-        .unwrap()
-        .into_iter()
-        .map(PrivateKey)
-        .collect();
-
-    // exit if no keys could be parsed
-    if keys.is_empty() {
-        eprintln!("Could not locate EC private keys.");
-        std::process::exit(1);
-    }
-
-    config.with_single_cert(cert_chain, keys.remove(0)).unwrap()
+        .with_client_cert_verifier(verifier)
+        .with_cert_resolver(Arc::new(Resolver)))
+}
+
+/// Reads the certificate and key again, the current ones stay in use on error.
+pub fn reload() -> Result<(), String> {
+    reload_from(&cert_path(), &key_path())
+}
+
+fn reload_from(cert_path: &Path, key_path: &Path) -> Result<(), String> {
+    let certs = read_certs(cert_path)?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificate found", cert_path.display()));
+    }
+    let key = read_key(key_path)?;
+    let key = sign::any_supported_type(&key).map_err(|e| format!("{}: {}", key_path.display(), e))?;
+    if !key_matches(&certs[0], key.as_ref()) {
+        return Err(format!("{}: does not match the certificate in {}", key_path.display(), cert_path.display()));
+    }
+    CURRENT.store(Some(Arc::new(CertifiedKey::new(certs, key))));
+    Ok(())
+}
+
+/// Whether a signature of `key` verifies with the public key of the leaf certificate, a
+/// mismatched pair would fail every handshake.
+fn key_matches(leaf: &Certificate, key: &dyn sign::SigningKey) -> bool {
+    let offered = [
+        SignatureScheme::ED25519,
+        SignatureScheme::ECDSA_NISTP256_SHA256,
+        SignatureScheme::ECDSA_NISTP384_SHA384,
+        SignatureScheme::RSA_PSS_SHA256,
+    ];
+    let Ok((_, cert)) = X509Certificate::from_der(&leaf.0) else { return false };
+    let Some(signer) = key.choose_scheme(&offered) else { return false };
+    let algorithm: &dyn signature::VerificationAlgorithm = match signer.scheme() {
+        SignatureScheme::ED25519 => &signature::ED25519,
+        SignatureScheme::ECDSA_NISTP256_SHA256 => &signature::ECDSA_P256_SHA256_ASN1,
+        SignatureScheme::ECDSA_NISTP384_SHA384 => &signature::ECDSA_P384_SHA384_ASN1,
+        SignatureScheme::RSA_PSS_SHA256 => &signature::RSA_PSS_2048_8192_SHA256,
+        _ => return false,
+    };
+    let message = b"tls key check";
+    let Ok(sig) = signer.sign(message) else { return false };
+    signature::UnparsedPublicKey::new(algorithm, &cert.public_key().subject_public_key.data)
+        .verify(message, &sig)
+        .is_ok()
+}
+
+/// Reloads the certificate whenever its files change. Must be called inside the tokio runtime.
+///
+/// The paths are the ones configured at startup, a config reload that changes `tls.cert` or
+/// `tls.key` reports them as needing a restart.
+pub fn spawn_reload() {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let (cert, key) = (cert_path(), key_path());
+    let watcher = match watch::watch_files(&[cert.as_path(), key.as_path()], tx) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            warn!("cannot watch {}, certificate changes need a restart: {}", cert.display(), e);
+            None
+        }
+    };
+    tokio::spawn(async move {
+        // dropping the watcher stops the events
+        let _watcher = watcher;
+        while rx.recv().await.is_some() {
+            // the certificate and the key are usually replaced one after the other
+            tokio::time::sleep(watch::SETTLE).await;
+            while rx.try_recv().is_ok() {}
+            match reload_from(&cert, &key) {
+                Ok(()) => info!("tls certificate reloaded from {}", cert.display()),
+                Err(e) => error!("tls certificate not reloaded, keeping the current one: {}", e),
+            }
+        }
+    });
+}
+
+struct Resolver;
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, _: ClientHello) -> Option<Arc<CertifiedKey>> {
+        CURRENT.load_full()
+    }
+}
+
+fn open(path: &Path) -> Result<BufReader<File>, String> {
+    File::open(path).map(BufReader::new).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn read_certs(path: &Path) -> Result<Vec<Certificate>, String> {
+    let certs = rustls_pemfile::certs(&mut open(path)?).map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+/// First PKCS8, RSA or EC key of the file.
+fn read_key(path: &Path) -> Result<PrivateKey, String> {
+    let mut reader = open(path)?;
+    loop {
+        match rustls_pemfile::read_one(&mut reader).map_err(|e| format!("{}: {}", path.display(), e))? {
+            Some(Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key)) => return Ok(PrivateKey(key)),
+            Some(_) => continue,
+            None => return Err(format!("{}: no PKCS8, RSA or EC private key found", path.display())),
+        }
+    }
+}
+
+fn client_roots(path: &Path) -> Result<RootCertStore, String> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(path)? {
+        roots.add(&cert).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    if roots.is_empty() {
+        return Err(format!("{}: no CA certificate found", path.display()));
+    }
+    Ok(roots)
+}
+
+/// Verified client certificate of the connection.
+///
+/// Extract it in a handler, or as `Option<ClientIdentity>` where mTLS is optional;
+/// a missing certificate is answered with 401.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// distinguished name like `CN=client,O=example`
+    pub subject: String,
+    pub common_name: Option<String>,
+    /// hex serial number
+    pub serial: String,
+}
+
+impl ClientIdentity {
+    pub fn from_der(der: &[u8]) -> Result<ClientIdentity, String> {
+        let (_, cert) = X509Certificate::from_der(der).map_err(|e| e.to_string())?;
+        let common_name = cert.subject().iter_common_name().next().and_then(|cn| cn.as_str().ok());
+        Ok(ClientIdentity {
+            subject: cert.subject().to_string(),
+            common_name: common_name.map(str::to_string),
+            serial: cert.raw_serial_as_string(),
+        })
+    }
+}
+
+impl FromRequest for ClientIdentity {
+    type Error = AppError;
+    type Future = Ready<Result<ClientIdentity, AppError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let identity = req.conn_data::<ClientIdentity>().cloned();
+        ready(identity.ok_or_else(|| AppError::Unauthorized("client certificate required".to_string())))
+    }
+}
+
+/// `HttpServer::on_connect` hook, stores the [`ClientIdentity`] of TLS connections that
+/// presented a certificate in the connection data.
+pub fn on_connect(conn: &dyn Any, data: &mut Extensions) {
+    let Some(stream) = conn.downcast_ref::<TlsStream<TcpStream>>() else { return };
+    let (_, session) = stream.get_ref();
+    let Some(cert) = session.peer_certificates().and_then(|certs| certs.first()) else { return };
+    match ClientIdentity::from_der(&cert.0) {
+        Ok(identity) => {
+            debug!("client certificate {}", identity.subject);
+            data.insert(identity);
+        }
+        Err(e) => warn!("unreadable client certificate: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls").join(name)
+    }
+
+    #[test]
+    fn reads_pkcs8_rsa_and_ec_keys() {
+        for kind in ["pkcs8", "rsa", "ec"] {
+            let key = read_key(&fixture(&format!("{}-key.pem", kind))).unwrap();
+            assert!(sign::any_supported_type(&key).is_ok(), "{}", kind);
+        }
+    }
+
+    #[test]
+    fn rejects_files_without_a_usable_key() {
+        let err = read_key(&fixture("rsa.pem")).unwrap_err();
+        assert!(err.contains("no PKCS8, RSA or EC private key found"), "{}", err);
+        assert!(read_key(&fixture("missing-key.pem")).is_err());
+        let invalid = read_key(&fixture("invalid-key.pem")).and_then(|key| {
+            sign::any_supported_type(&key).map(|_| ()).map_err(|e| e.to_string())
+        });
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn reload_keeps_the_current_certificate_on_error() {
+        let served = || CURRENT.load_full().map(|key| key.cert[0].clone());
+        reload_from(&fixture("rsa.pem"), &fixture("rsa-key.pem")).unwrap();
+        let rsa = served().unwrap();
+        assert_eq!(rsa, read_certs(&fixture("rsa.pem")).unwrap()[0]);
+
+        assert!(reload_from(&fixture("ec.pem"), &fixture("invalid-key.pem")).is_err());
+        assert!(reload_from(&fixture("ec.pem"), &fixture("missing-key.pem")).is_err());
+        assert!(reload_from(&fixture("ec-key.pem"), &fixture("ec-key.pem")).is_err());
+        // valid files, but the key belongs to another certificate
+        assert!(reload_from(&fixture("rsa.pem"), &fixture("pkcs8-key.pem")).is_err());
+        let err = reload_from(&fixture("pkcs8.pem"), &fixture("ec-key.pem")).unwrap_err();
+        assert!(err.contains("does not match the certificate"), "{}", err);
+        assert_eq!(served(), Some(rsa));
+
+        reload_from(&fixture("ec.pem"), &fixture("ec-key.pem")).unwrap();
+        assert_eq!(served(), Some(read_certs(&fixture("ec.pem")).unwrap()[0].clone()));
+        reload_from(&fixture("pkcs8.pem"), &fixture("pkcs8-key.pem")).unwrap();
+    }
 }
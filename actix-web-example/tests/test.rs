@@ -1 +1,1259 @@
-ssss
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::{test, web, App, HttpMessage, HttpResponse};
+use actix_web_example::conf::config;
+use actix_web_example::conf::migrate;
+use actix_web_example::error::AppError;
+use actix_web_example::events::{EnrollmentEvent, EventPublisher, LogPublisher};
+use actix_web_example::handler::{self, auth, course, enrollment, teacher};
+use actix_web_example::middleware::{self, session_key, Claims, CorsScope, Jwt, JwtIssuer};
+use actix_web_example::model::{self, EnrollmentStatus, Role, Teacher};
+use actix_web_example::repository::{
+    CourseRepository, EnrollmentRepository, MemoryCourseRepository, MemoryEnrollmentRepository,
+    MemoryTeacherRepository, MemoryUserRepository, SqlCourseRepository, SqlEnrollmentRepository,
+    SqlTeacherRepository, TeacherRepository, UserRepository,
+};
+use actix_web_example::utils::metrics;
+use actix_web_example::utils::shutdown::{Phase, Shutdown};
+use serde_json::{json, Value};
+
+/// The shipped `conf/app.toml` has no secrets, give the tests some before the config is
+/// first read.
+fn init_conf() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        std::env::set_var("APP__JWT__SECRET", "test-secret-0123456789abcdef0123456789");
+        std::env::set_var("APP__SIGN__CLIENTS__0__SECRET", SIGN_SECRET);
+        std::env::set_var("APP__SHUTDOWN__ADMIN_TOKEN", ADMIN_TOKEN);
+    });
+}
+
+/// `shutdown.admin_token` of the `/sys` admin routes
+const ADMIN_TOKEN: &str = "test-admin-token";
+
+/// Held by the tests that swap [`config::GLOBAL_CONFIG`], they put the old config back before
+/// letting go.
+static CONFIG_SWAP: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+/// secret of the `reqwest` sign client
+const SIGN_SECRET: &str = "test-sign-secret-0123";
+
+/// Course, teacher and enrollment routes, with the teachers 1 and 2 already stored.
+async fn app() -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+    app_with(Arc::new(LogPublisher)).await
+}
+
+async fn app_with(events: Arc<dyn EventPublisher>) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+    init_conf();
+    let courses = Arc::new(MemoryCourseRepository::new());
+    let enrollments: web::Data<dyn EnrollmentRepository> =
+        web::Data::from(Arc::new(MemoryEnrollmentRepository::new(courses.clone())) as Arc<dyn EnrollmentRepository>);
+    let teachers = MemoryTeacherRepository::new(courses.clone());
+    for name in ["ada", "grace"] {
+        let info = Teacher { id: None, name: name.to_string(), email: None, bio: None, created_at: None, updated_at: None };
+        teachers.create(&info).await.unwrap();
+    }
+    let repo: web::Data<dyn CourseRepository> = web::Data::from(courses as Arc<dyn CourseRepository>);
+    let teachers: web::Data<dyn TeacherRepository> =
+        web::Data::from(Arc::new(teachers) as Arc<dyn TeacherRepository>);
+    test::init_service(
+        App::new()
+            .app_data(repo)
+            .app_data(teachers)
+            .app_data(enrollments)
+            .app_data(web::Data::from(events))
+            .service(
+            web::scope("/app")
+                .service(course::get_courses)
+                .service(course::get_course)
+                .service(course::add_courses)
+                .service(course::del_courses)
+                .service(course::update_courses)
+                .service(teacher::get_teachers)
+                .service(teacher::get_teacher)
+                .service(teacher::get_teacher_courses)
+                .service(teacher::add_teacher)
+                .service(teacher::del_teacher)
+                .service(teacher::update_teacher)
+                .service(enrollment::enroll)
+                .service(enrollment::unenroll)
+                .service(enrollment::my_enrollments),
+        ),
+    )
+    .await
+}
+
+/// Keeps the published events for the assertions.
+#[derive(Default)]
+struct Recorder(Mutex<Vec<EnrollmentEvent>>);
+
+#[async_trait]
+impl EventPublisher for Recorder {
+    async fn publish(&self, event: &EnrollmentEvent) -> Result<(), String> {
+        self.0.lock().unwrap().push(event.clone());
+        Ok(())
+    }
+}
+
+/// Request carrying the claims `Jwt` would have stored.
+fn as_user(req: test::TestRequest, role: Role, teacher_id: Option<i64>) -> actix_http::Request {
+    let req = req.to_request();
+    req.extensions_mut().insert(Claims {
+        sub: format!("{}-{}", role, teacher_id.unwrap_or_default()),
+        exp: u64::MAX,
+        nbf: None,
+        iat: None,
+        iss: None,
+        aud: None,
+        role: Some(role),
+        teacher_id,
+    });
+    req
+}
+
+fn as_admin(req: test::TestRequest) -> actix_http::Request {
+    as_user(req, Role::Admin, None)
+}
+
+async fn add(app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>, body: Value) -> Value {
+    let req = as_admin(test::TestRequest::post().uri("/app/courses").set_json(body));
+    test::call_and_read_body_json(app, req).await
+}
+
+async fn get(app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>, uri: &str) -> (StatusCode, Value) {
+    let resp = test::call_service(app, test::TestRequest::get().uri(uri).to_request()).await;
+    let status = resp.status();
+    (status, test::read_body_json(resp).await)
+}
+
+#[actix_web::test]
+async fn add_and_list_courses() {
+    let app = app().await;
+    let page = add(&app, json!({"teacher_id": 1, "name": "rust", "price": 10.0})).await;
+    assert_eq!(page["total"], 1);
+    assert_eq!(page["items"][0]["name"], "rust");
+    assert_eq!(page["items"][0]["version"], 1);
+
+    let req = as_admin(test::TestRequest::post().uri("/app/courses").set_json(json!({"teacher_id": 100})));
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "ACTIX_000001");
+    assert!(body["fields"]["teacher_id"].is_array());
+}
+
+#[actix_web::test]
+async fn list_courses_filters_sorts_and_pages() {
+    let app = app().await;
+    for (teacher_id, name, price) in [(1, "rust", 30.0), (2, "go", 10.0), (1, "Rust web", 20.0)] {
+        add(&app, json!({"teacher_id": teacher_id, "name": name, "price": price})).await;
+    }
+
+    let (status, page) = get(&app, "/app/courses?teacher_id=1&sort=-price").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["total"], 2);
+    assert_eq!(page["items"][0]["price"], 30.0);
+
+    let (_, page) = get(&app, "/app/courses?name=rust").await;
+    assert_eq!(page["total"], 2);
+
+    let (_, page) = get(&app, "/app/courses?sort=price&page_size=2").await;
+    assert_eq!(page["page"], 1);
+    assert_eq!(page["items"].as_array().unwrap().len(), 2);
+    let cursor = page["next_cursor"].as_str().unwrap().to_string();
+    let (_, next) = get(&app, &format!("/app/courses?sort=price&page_size=2&cursor={}", cursor)).await;
+    assert_eq!(next["page"], Value::Null);
+    assert_eq!(next["items"][0]["price"], 30.0);
+    assert_eq!(next["next_cursor"], Value::Null);
+
+    let (status, body) = get(&app, "/app/courses?sort=nope").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "ACTIX_000006");
+    let (status, _) = get(&app, "/app/courses?page_size=0").await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    // the offset of such a page would overflow
+    let (status, body) = get(&app, &format!("/app/courses?page={}&page_size=100", i64::MAX)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["fields"]["page"].is_array());
+}
+
+#[actix_web::test]
+async fn get_course_by_id() {
+    let app = app().await;
+    let page = add(&app, json!({"teacher_id": 1, "name": "rust"})).await;
+    let id = page["items"][0]["id"].as_str().unwrap();
+
+    let resp = test::call_service(&app, test::TestRequest::get().uri(&format!("/app/courses/{}", id)).to_request()).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("etag").unwrap(), "\"1\"");
+
+    let (status, body) = get(&app, "/app/courses/missing").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "ACTIX_000003");
+}
+
+#[actix_web::test]
+async fn update_course_checks_version() {
+    let app = app().await;
+    let page = add(&app, json!({"teacher_id": 1, "name": "rust", "price": 1.0})).await;
+    let uri = format!("/app/courses/{}", page["items"][0]["id"].as_str().unwrap());
+
+    let req = as_admin(test::TestRequest::patch().uri(&uri).set_json(json!({"price": 2.0})));
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::PRECONDITION_REQUIRED);
+
+    let req = as_admin(test::TestRequest::patch().uri(&uri)
+        .insert_header(("If-Match", "\"1\""))
+        .set_json(json!({"price": 2.0})));
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(resp.headers().get("etag").unwrap(), "\"2\"");
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["name"], "rust");
+    assert_eq!(body["price"], 2.0);
+
+    let req = as_admin(test::TestRequest::patch().uri(&uri)
+        .insert_header(("If-Match", "\"1\""))
+        .set_json(json!({"price": 3.0})));
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::PRECONDITION_FAILED);
+
+    // nothing to change, the version stays
+    let req = as_admin(test::TestRequest::patch().uri(&uri)
+        .insert_header(("If-Match", "\"2\""))
+        .set_json(json!({})));
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(resp.headers().get("etag").unwrap(), "\"2\"");
+
+    // PATCH is the only update verb, no route takes a PUT
+    let req = as_admin(test::TestRequest::put().uri(&uri)
+        .insert_header(("If-Match", "\"2\""))
+        .set_json(json!({"price": 3.0})));
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+    let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+    assert_eq!(resp.headers().get("etag").unwrap(), "\"2\"");
+
+    let req = as_admin(test::TestRequest::patch().uri("/app/courses/missing")
+        .insert_header(("If-Match", "*"))
+        .set_json(json!({"price": 3.0})));
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn delete_course() {
+    let app = app().await;
+    let page = add(&app, json!({"teacher_id": 1, "name": "rust"})).await;
+    let uri = format!("/app/courses/{}", page["items"][0]["id"].as_str().unwrap());
+
+    let resp = test::call_service(&app, as_admin(test::TestRequest::delete().uri(&uri))).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["total"], 0);
+
+    let resp = test::call_service(&app, as_admin(test::TestRequest::delete().uri(&uri))).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn course_mutations_check_role_and_owner() {
+    let app = app().await;
+    let page = add(&app, json!({"teacher_id": 1, "name": "rust"})).await;
+    let uri = format!("/app/courses/{}", page["items"][0]["id"].as_str().unwrap());
+
+    // students and tokens without claims are stopped by RequireRole
+    let req = as_user(test::TestRequest::delete().uri(&uri), Role::Student, None);
+    let err = test::try_call_service(&app, req).await.err().unwrap();
+    assert_eq!(err.error_response().status(), StatusCode::FORBIDDEN);
+    let req = test::TestRequest::delete().uri(&uri).to_request();
+    let err = test::try_call_service(&app, req).await.err().unwrap();
+    assert_eq!(err.error_response().status(), StatusCode::UNAUTHORIZED);
+
+    // teachers only touch their own courses
+    let req = as_user(test::TestRequest::post().uri("/app/courses").set_json(json!({"teacher_id": 1})), Role::Teacher, Some(2));
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "ACTIX_000011");
+    assert!(body["msg"].as_str().unwrap().contains("teacher 2"));
+
+    let req = as_user(test::TestRequest::delete().uri(&uri), Role::Teacher, Some(2));
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+    // nor hand them over
+    let req = as_user(
+        test::TestRequest::patch().uri(&uri).insert_header(("If-Match", "*")).set_json(json!({"teacher_id": 2})),
+        Role::Teacher,
+        Some(1),
+    );
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+    let req = as_user(
+        test::TestRequest::patch().uri(&uri).insert_header(("If-Match", "*")).set_json(json!({"price": 5.0})),
+        Role::Teacher,
+        Some(1),
+    );
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let req = as_user(test::TestRequest::delete().uri(&uri), Role::Teacher, Some(1));
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn teachers_crud_and_courses() {
+    let app = app().await;
+    let req = as_admin(test::TestRequest::post().uri("/app/teachers").set_json(json!({"name": "linus", "email": "linus@example.com"})));
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["id"], 3);
+
+    let req = as_admin(test::TestRequest::post().uri("/app/teachers").set_json(json!({"name": "", "email": "nope"})));
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(resp).await;
+    assert!(body["fields"]["name"].is_array());
+    assert!(body["fields"]["email"].is_array());
+
+    let req = as_user(test::TestRequest::post().uri("/app/teachers").set_json(json!({"name": "x"})), Role::Teacher, Some(1));
+    let err = test::try_call_service(&app, req).await.err().unwrap();
+    assert_eq!(err.error_response().status(), StatusCode::FORBIDDEN);
+
+    let req = as_admin(test::TestRequest::patch().uri("/app/teachers/3").set_json(json!({"bio": "kernel"})));
+    let body: Value = test::call_and_read_body_json(&app, req).await;
+    assert_eq!(body["name"], "linus");
+    assert_eq!(body["bio"], "kernel");
+
+    let (_, body) = get(&app, "/app/teachers").await;
+    assert_eq!(body.as_array().unwrap().len(), 3);
+    let (status, _) = get(&app, "/app/teachers/9").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    add(&app, json!({"teacher_id": 1, "name": "rust"})).await;
+    add(&app, json!({"teacher_id": 2, "name": "go"})).await;
+    let (status, page) = get(&app, "/app/teachers/1/courses?teacher_id=2").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["total"], 1);
+    assert_eq!(page["items"][0]["name"], "rust");
+    let (status, _) = get(&app, "/app/teachers/9/courses").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn delete_teacher_with_courses_needs_cascade() {
+    let app = app().await;
+    let page = add(&app, json!({"teacher_id": 1, "name": "rust"})).await;
+    let uri = format!("/app/courses/{}", page["items"][0]["id"].as_str().unwrap());
+
+    // courses can only move to existing teachers
+    let req = as_admin(test::TestRequest::patch().uri(&uri).insert_header(("If-Match", "*")).set_json(json!({"teacher_id": 9})));
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    let body: Value = test::read_body_json(resp).await;
+    assert!(body["fields"]["teacher_id"].is_array());
+
+    let resp = test::call_service(&app, as_admin(test::TestRequest::delete().uri("/app/teachers/1"))).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "ACTIX_000004");
+
+    let resp = test::call_service(&app, as_admin(test::TestRequest::delete().uri("/app/teachers/2"))).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+
+    let resp = test::call_service(&app, as_admin(test::TestRequest::delete().uri("/app/teachers/1?cascade=true"))).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let (status, _) = get(&app, &uri).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, body) = get(&app, "/app/teachers").await;
+    assert_eq!(body, json!([]));
+}
+
+#[actix_web::test]
+async fn enrollments_fill_the_capacity_then_the_waitlist() {
+    let recorder = Arc::new(Recorder::default());
+    let app = app_with(recorder.clone()).await;
+    let page = add(&app, json!({"teacher_id": 1, "name": "rust", "capacity": 1})).await;
+    let course_id = page["items"][0]["id"].as_str().unwrap().to_string();
+    let uri = format!("/app/courses/{}/enrollments", course_id);
+    let student = |req: test::TestRequest, n: i64| as_user(req, Role::Student, Some(n));
+
+    let resp = test::call_service(&app, student(test::TestRequest::post().uri(&uri), 1)).await;
+    assert_eq!(resp.status(), StatusCode::CREATED);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["status"], "enrolled");
+    let body: Value = test::call_and_read_body_json(&app, student(test::TestRequest::post().uri(&uri), 2)).await;
+    assert_eq!(body["status"], "waitlisted");
+    let body: Value = test::call_and_read_body_json(&app, student(test::TestRequest::post().uri(&uri), 3)).await;
+    assert_eq!(body["status"], "waitlisted");
+    let resp = test::call_service(&app, student(test::TestRequest::post().uri(&uri), 1)).await;
+    assert_eq!(resp.status(), StatusCode::CONFLICT);
+    let resp = test::call_service(&app, student(test::TestRequest::post().uri("/app/courses/missing/enrollments"), 1)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // the freed seat goes to the first on the waitlist
+    let resp = test::call_service(&app, student(test::TestRequest::delete().uri(&uri), 1)).await;
+    assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+    let body: Value = test::call_and_read_body_json(&app, student(test::TestRequest::get().uri("/app/me/enrollments"), 2)).await;
+    assert_eq!(body[0]["course_id"], course_id.as_str());
+    assert_eq!(body[0]["status"], "enrolled");
+    let resp = test::call_service(&app, student(test::TestRequest::delete().uri(&uri), 1)).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+    // so does a raised capacity
+    let req = as_admin(test::TestRequest::patch()
+        .uri(&format!("/app/courses/{}", course_id))
+        .insert_header(("If-Match", "*"))
+        .set_json(json!({"capacity": 2})));
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    let body: Value = test::call_and_read_body_json(&app, student(test::TestRequest::get().uri("/app/me/enrollments"), 3)).await;
+    assert_eq!(body[0]["status"], "enrolled");
+
+    // published on tasks of their own
+    tokio::task::yield_now().await;
+    let kinds: Vec<Value> = recorder.0.lock().unwrap().iter().map(|e| json!(e.kind)).collect();
+    assert_eq!(kinds, vec!["enrolled", "waitlisted", "waitlisted", "cancelled", "promoted", "promoted"]);
+}
+
+/// A sink that never answers, like Kafka with the brokers down.
+struct Stalled;
+
+#[async_trait]
+impl EventPublisher for Stalled {
+    async fn publish(&self, _event: &EnrollmentEvent) -> Result<(), String> {
+        futures::future::pending().await
+    }
+}
+
+#[actix_web::test]
+async fn a_stalled_event_sink_does_not_hold_up_enrollments() {
+    let app = app_with(Arc::new(Stalled)).await;
+    let page = add(&app, json!({"teacher_id": 1, "name": "rust", "capacity": 1})).await;
+    let uri = format!("/app/courses/{}/enrollments", page["items"][0]["id"].as_str().unwrap());
+    for n in 1..=2 {
+        let req = as_user(test::TestRequest::post().uri(&uri), Role::Student, Some(n));
+        let resp = tokio::time::timeout(Duration::from_secs(1), test::call_service(&app, req))
+            .await
+            .expect("answered without the sink");
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+}
+
+/// `/auth` plus the course routes behind [`Jwt`], with the teachers 1 and 2 and the admin
+/// `root` already stored.
+async fn auth_app() -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+    init_conf();
+    let users = Arc::new(MemoryUserRepository::new());
+    let admin = model::Register { username: "root".to_string(), password: ROOT_PASSWORD.to_string() };
+    auth::create_user(users.as_ref(), admin, Role::Admin).await.unwrap();
+    let users: web::Data<dyn UserRepository> = web::Data::from(users as Arc<dyn UserRepository>);
+    let courses = Arc::new(MemoryCourseRepository::new());
+    let teachers = MemoryTeacherRepository::new(courses.clone());
+    for name in ["ada", "grace"] {
+        let info = Teacher { id: None, name: name.to_string(), email: None, bio: None, created_at: None, updated_at: None };
+        teachers.create(&info).await.unwrap();
+    }
+    let teachers: web::Data<dyn TeacherRepository> =
+        web::Data::from(Arc::new(teachers) as Arc<dyn TeacherRepository>);
+    let enrollments: web::Data<dyn EnrollmentRepository> =
+        web::Data::from(Arc::new(MemoryEnrollmentRepository::new(courses.clone())) as Arc<dyn EnrollmentRepository>);
+    let courses: web::Data<dyn CourseRepository> = web::Data::from(courses as Arc<dyn CourseRepository>);
+    let issuer = JwtIssuer::from_conf(&config::GLOBAL_CONFIG.load().jwt).unwrap();
+    test::init_service(
+        App::new()
+            .app_data(users)
+            .app_data(courses)
+            .app_data(teachers)
+            .app_data(enrollments)
+            .app_data(web::Data::from(Arc::new(LogPublisher) as Arc<dyn EventPublisher>))
+            .app_data(web::Data::new(issuer))
+            .service(
+                web::scope("/auth")
+                    .service(auth::register)
+                    .service(auth::login)
+                    .service(auth::refresh)
+                    .service(auth::logout),
+            )
+            .service(
+                web::scope("/app")
+                    .wrap(Jwt)
+                    .service(course::get_courses)
+                    .service(course::add_courses)
+                    .service(course::update_courses)
+                    .service(auth::set_role),
+            ),
+    )
+    .await
+}
+
+const ROOT_PASSWORD: &str = "root password";
+
+/// Logs in and returns the `Authorization` header of the access token.
+async fn bearer(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+    username: &str,
+    password: &str,
+) -> (&'static str, String) {
+    let (status, tokens) = post(app, "/auth/login", json!({"username": username, "password": password})).await;
+    assert_eq!(status, StatusCode::OK, "login {}", username);
+    ("Authorization", format!("Bearer {}", tokens["access_token"].as_str().unwrap()))
+}
+
+/// Status of the response, or of the error a middleware answered with.
+async fn status(app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>, req: actix_http::Request) -> StatusCode {
+    match test::try_call_service(app, req).await {
+        Ok(resp) => resp.status(),
+        Err(e) => e.as_response_error().status_code(),
+    }
+}
+
+async fn post(app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>, uri: &str, body: Value) -> (StatusCode, Value) {
+    let resp = test::call_service(app, test::TestRequest::post().uri(uri).set_json(body).to_request()).await;
+    let status = resp.status();
+    let body = test::read_body(resp).await;
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+#[actix_web::test]
+async fn register_login_and_use_the_token() {
+    let app = auth_app().await;
+    let (status, user) = post(&app, "/auth/register", json!({"username": "alice", "password": "correct horse"})).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(user["role"], "student");
+    assert!(user.get("password_hash").is_none());
+
+    let (status, _) = post(&app, "/auth/register", json!({"username": "alice", "password": "another one"})).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, body) = post(&app, "/auth/register", json!({"username": "bob", "password": "short"})).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["fields"]["password"].is_array());
+
+    let (status, tokens) = post(&app, "/auth/login", json!({"username": "alice", "password": "correct horse"})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(tokens["token_type"], "Bearer");
+    // the browser signs with a key of the login instead of a secret of its own
+    assert!(tokens["sign_client"].as_str().unwrap().starts_with("session."), "{}", tokens);
+    assert_eq!(tokens["sign_secret"].as_str().unwrap().len(), 64);
+    let req = test::TestRequest::get()
+        .uri("/app/courses")
+        .insert_header(("Authorization", format!("Bearer {}", tokens["access_token"].as_str().unwrap())))
+        .to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+    let (status, _) = post(&app, "/auth/login", json!({"username": "alice", "password": "wrong horse"})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = post(&app, "/auth/login", json!({"username": "nobody", "password": "correct horse"})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn jwt_rejects_invalid_tokens_with_401() {
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+
+    let app = auth_app().await;
+    let conf = config::GLOBAL_CONFIG.load().jwt.clone();
+    let secret = conf.secret.clone().unwrap();
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    // beyond the configured leeway
+    let skew = conf.leeway.unwrap_or(0) + 60;
+    let claims = || Claims {
+        sub: "alice".to_string(),
+        exp: now + 600,
+        nbf: None,
+        iat: Some(now),
+        iss: conf.issuer.clone(),
+        aud: conf.audience.clone(),
+        role: Some(Role::Student),
+        teacher_id: None,
+    };
+    let sign = |algorithm: Algorithm, key: &str, claims: Claims| {
+        let token = encode(&Header::new(algorithm), &claims, &EncodingKey::from_secret(key.as_bytes())).unwrap();
+        format!("Bearer {}", token)
+    };
+    let get = |authorization: Option<String>| {
+        let mut req = test::TestRequest::get().uri("/app/courses");
+        if let Some(value) = authorization {
+            req = req.insert_header(("Authorization", value));
+        }
+        req.to_request()
+    };
+
+    assert_eq!(status(&app, get(Some(sign(Algorithm::HS256, &secret, claims())))).await, StatusCode::OK);
+
+    let someone = Some("someone".to_string());
+    let rejected = [
+        ("expired", Some(sign(Algorithm::HS256, &secret, Claims { exp: now - skew, ..claims() }))),
+        ("not yet valid", Some(sign(Algorithm::HS256, &secret, Claims { nbf: Some(now + skew), ..claims() }))),
+        ("wrong issuer", Some(sign(Algorithm::HS256, &secret, Claims { iss: someone.clone(), ..claims() }))),
+        ("wrong audience", Some(sign(Algorithm::HS256, &secret, Claims { aud: someone.clone(), ..claims() }))),
+        ("bad signature", Some(sign(Algorithm::HS256, "another-secret-0123456789abcdef01234", claims()))),
+        ("wrong algorithm", Some(sign(Algorithm::HS384, &secret, claims()))),
+        ("missing header", None),
+        ("basic scheme", Some("Basic YWxpY2U6cGFzc3dvcmQ=".to_string())),
+        ("empty bearer", Some("Bearer ".to_string())),
+        ("not a jwt", Some("Bearer not-a-jwt".to_string())),
+    ];
+    for (case, authorization) in rejected {
+        assert_eq!(status(&app, get(authorization)).await, StatusCode::UNAUTHORIZED, "{}", case);
+    }
+}
+
+#[actix_web::test]
+async fn refresh_tokens_rotate_and_reuse_revokes_the_login() {
+    let app = auth_app().await;
+    post(&app, "/auth/register", json!({"username": "alice", "password": "correct horse"})).await;
+    let (_, first) = post(&app, "/auth/login", json!({"username": "alice", "password": "correct horse"})).await;
+
+    let (status, second) = post(&app, "/auth/refresh", json!({"refresh_token": first["refresh_token"]})).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(second["refresh_token"], first["refresh_token"]);
+
+    // the first token was used up, presenting it again revokes the rotated one too
+    let (status, _) = post(&app, "/auth/refresh", json!({"refresh_token": first["refresh_token"]})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = post(&app, "/auth/refresh", json!({"refresh_token": second["refresh_token"]})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (_, login) = post(&app, "/auth/login", json!({"username": "alice", "password": "correct horse"})).await;
+    let (status, _) = post(&app, "/auth/logout", json!({"refresh_token": login["refresh_token"]})).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, _) = post(&app, "/auth/refresh", json!({"refresh_token": login["refresh_token"]})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = post(&app, "/auth/refresh", json!({"refresh_token": "made up"})).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn repeated_failed_logins_lock_the_account() {
+    let app = auth_app().await;
+    post(&app, "/auth/register", json!({"username": "alice", "password": "correct horse"})).await;
+    let max_failed = config::GLOBAL_CONFIG.load().auth.max_failed_logins.unwrap();
+    for _ in 0..max_failed {
+        let (status, _) = post(&app, "/auth/login", json!({"username": "alice", "password": "wrong horse"})).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    let req = test::TestRequest::post().uri("/auth/login")
+        .set_json(json!({"username": "alice", "password": "correct horse"}))
+        .to_request();
+    let resp = test::call_service(&app, req).await;
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(resp.headers().contains_key("retry-after"));
+}
+
+#[actix_web::test]
+async fn jwt_config_rejects_weak_secrets() {
+    use validator::Validate;
+
+    let jwt = |algorithm: &str, secret: &str| config::Jwt {
+        algorithm: Some(algorithm.to_string()),
+        secret: Some(secret.to_string()),
+        public_key: None,
+        private_key: None,
+        issuer: None,
+        audience: None,
+        leeway: None,
+    };
+    assert!(jwt("HS256", "").validate().is_err());
+    assert!(jwt("HS256", "change-me").validate().is_err());
+    assert!(jwt("HS256", &"x".repeat(config::MIN_JWT_SECRET - 1)).validate().is_err());
+    assert!(jwt("HS256", &"x".repeat(config::MIN_JWT_SECRET)).validate().is_ok());
+    // RS256 signs with the key files, the secret is unused
+    assert!(jwt("RS256", "").validate().is_ok());
+}
+
+/// Loads the built-in config only, with `env` in place of the process environment.
+fn load_defaults(env: &[(&str, &str)]) -> Result<config::Conf, conf_rs::ConfError> {
+    let empty = std::env::temp_dir().join(format!("actix-web-example-empty-{}.toml", std::process::id()));
+    std::fs::write(&empty, "").unwrap();
+    let env = env.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+    config::Conf::load(conf_rs::Loader::new("APP").file(&empty).env(env))
+}
+
+const JWT_SECRET: (&str, &str) = ("APP__JWT__SECRET", "0123456789abcdef0123456789abcdef");
+
+#[actix_web::test]
+async fn built_in_defaults_hold_no_secrets() {
+    let err = load_defaults(&[]).expect_err("no jwt secret");
+    assert!(err.to_string().contains("jwt"), "{}", err);
+
+    let conf = load_defaults(&[JWT_SECRET]).unwrap();
+    assert_eq!(conf.sign.clients, Some(vec![]));
+    assert_eq!(conf.sign.session_secret.as_deref(), Some(""));
+    let err = load_defaults(&[JWT_SECRET, ("APP__SIGN__SESSION_SECRET", "too-short")]).expect_err("short secret");
+    assert!(err.to_string().contains("session_secret"), "{}", err);
+}
+
+#[actix_web::test]
+async fn only_sqlite_databases_are_accepted() {
+    for db_type in ["mysql", "postgres"] {
+        let err = load_defaults(&[JWT_SECRET, ("APP__DB__DB_TYPE", db_type)]).expect_err(db_type);
+        assert!(err.to_string().contains("only sqlite is"), "{}", err);
+    }
+}
+
+#[actix_web::test]
+async fn changed_certificate_paths_need_a_restart() {
+    let old = load_defaults(&[JWT_SECRET]).unwrap();
+    let paths = [("APP__TLS__CERT", "conf/other.pem"), ("APP__TLS__KEY", "conf/other-key.pem")];
+    let moved = load_defaults(&[JWT_SECRET, paths[0], paths[1]]).unwrap();
+    assert_eq!(moved.restart_required(&old), vec!["tls.cert", "tls.key"]);
+    assert!(load_defaults(&[JWT_SECRET]).unwrap().restart_required(&old).is_empty());
+}
+
+#[actix_web::test]
+async fn credentials_need_explicit_origins() {
+    let credentials = ("APP__CORS__PUBLIC__SUPPORTS_CREDENTIALS", "true");
+    let err = load_defaults(&[JWT_SECRET, credentials]).expect_err("\"*\" with credentials");
+    assert!(err.to_string().contains("wildcard origin"), "{}", err);
+
+    for pattern in [".*", "https://.*", "https?://.+"] {
+        let env = [JWT_SECRET, ("APP__CORS__APP__ALLOWED_ORIGIN_PATTERNS__0", pattern)];
+        let err = load_defaults(&env).expect_err(pattern);
+        assert!(err.to_string().contains("matches any origin"), "{}", err);
+        let env = [env[0], env[1], ("APP__CORS__APP__SUPPORTS_CREDENTIALS", "false")];
+        assert!(load_defaults(&env).is_ok(), "{} without credentials", pattern);
+    }
+    let subdomains = ("APP__CORS__APP__ALLOWED_ORIGIN_PATTERNS__0", "https://.*\\.example\\.com");
+    assert!(load_defaults(&[JWT_SECRET, subdomains]).is_ok());
+}
+
+/// [`config::reload`] from `contents` in place of `conf/app.toml`, with the configs before and
+/// after it. The config before is put back afterwards.
+async fn reload_from(
+    contents: &str,
+) -> (Result<Vec<&'static str>, conf_rs::ConfError>, Arc<config::Conf>, Arc<config::Conf>) {
+    init_conf();
+    let _swap = CONFIG_SWAP.lock().await;
+    let before = config::GLOBAL_CONFIG.load_full();
+    let path = std::env::temp_dir().join(format!("actix-web-example-reload-{}.toml", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    std::env::set_var("APP_CONFIG", &path);
+    let restart = config::reload();
+    std::env::remove_var("APP_CONFIG");
+    let after = config::GLOBAL_CONFIG.swap(before.clone());
+    (restart, before, after)
+}
+
+#[actix_web::test]
+async fn reload_keeps_the_config_when_the_file_is_invalid() {
+    let valid = std::fs::read_to_string("conf/app.toml").unwrap();
+    let bad_level = valid.replace("level = \"debug\"", "level = \"loud\"");
+    for contents in ["[server\nname = ", "[db]\ndb_type = \"mysql\"", &bad_level] {
+        let (result, before, after) = reload_from(contents).await;
+        assert!(result.is_err(), "{}", contents);
+        assert!(Arc::ptr_eq(&before, &after), "{}", contents);
+    }
+}
+
+#[actix_web::test]
+async fn reload_reports_changed_services() {
+    let mut changes = config::subscribe();
+    let valid = std::fs::read_to_string("conf/app.toml").unwrap();
+    let (restart, _, reloaded) = reload_from(&valid.replace("port = 8088", "port = 9099")).await;
+    assert_eq!(restart.unwrap(), vec!["server.services"]);
+    let ports: Vec<_> = reloaded.server.services.iter().flatten().map(|s| s.port).collect();
+    assert_eq!(ports, vec![Some(9099); 3]);
+    // subscribers get the new config
+    let published = changes.try_recv().unwrap();
+    assert!(Arc::ptr_eq(&published, &reloaded));
+
+    let (restart, _, _) = reload_from(&valid).await;
+    assert!(restart.unwrap().is_empty());
+}
+
+/// `/app/ping`, `/sys/ping` and `/ping`, each scope behind its CORS policy.
+async fn cors_app() -> impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error> {
+    init_conf();
+    let ping = || web::get().to(HttpResponse::Ok);
+    test::init_service(
+        App::new()
+            .service(web::scope("/app").wrap(middleware::cors(CorsScope::App)).route("/ping", ping()))
+            .service(web::scope("/sys").wrap(middleware::cors(CorsScope::Sys)).route("/ping", ping()))
+            .service(web::scope("").wrap(middleware::cors(CorsScope::Public)).route("/ping", ping())),
+    )
+    .await
+}
+
+/// Status and `Access-Control-Allow-Origin` of a `GET` of `uri` from `origin`.
+async fn cors_get(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+    uri: &str,
+    origin: &str,
+) -> (StatusCode, Option<String>) {
+    let req = test::TestRequest::get().uri(uri).insert_header(("Origin", origin)).to_request();
+    match test::try_call_service(app, req).await {
+        Ok(resp) => {
+            let allowed = resp.headers().get("access-control-allow-origin").map(|v| v.to_str().unwrap().to_string());
+            (resp.status(), allowed)
+        }
+        Err(e) => (e.as_response_error().status_code(), None),
+    }
+}
+
+#[actix_web::test]
+async fn cors_allows_the_origins_of_each_scope() {
+    let app = cors_app().await;
+    let local = "http://localhost:8080";
+    assert_eq!(cors_get(&app, "/app/ping", local).await, (StatusCode::OK, Some(local.to_string())));
+    let sub = "https://api-1.example.com";
+    assert_eq!(cors_get(&app, "/app/ping", sub).await, (StatusCode::OK, Some(sub.to_string())));
+    for denied in ["https://evil.test", "https://example.com.evil.test", "http://localhost:8081"] {
+        assert_eq!(cors_get(&app, "/app/ping", denied).await.1, None, "{}", denied);
+    }
+    // no origins for /sys, any for the public routes
+    assert_eq!(cors_get(&app, "/sys/ping", local).await.1, None);
+    let any = "https://evil.test";
+    assert_eq!(cors_get(&app, "/ping", any).await, (StatusCode::OK, Some(any.to_string())));
+    // requests without an origin are not cors requests
+    assert_eq!(status(&app, test::TestRequest::get().uri("/sys/ping").to_request()).await, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn cors_answers_preflight_requests() {
+    let app = cors_app().await;
+    let preflight = |uri: &str, origin: &str| {
+        test::TestRequest::default()
+            .method(actix_web::http::Method::OPTIONS)
+            .uri(uri)
+            .insert_header(("Origin", origin))
+            .insert_header(("Access-Control-Request-Method", "PATCH"))
+            .insert_header(("Access-Control-Request-Headers", "authorization, if-match"))
+            .to_request()
+    };
+    let resp = test::call_service(&app, preflight("/app/ping", "http://localhost:8080")).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let header = |name: &str| resp.headers().get(name).map(|v| v.to_str().unwrap().to_string());
+    assert_eq!(header("access-control-allow-origin").as_deref(), Some("http://localhost:8080"));
+    assert!(header("access-control-allow-methods").unwrap().contains("PATCH"));
+    assert!(header("access-control-allow-headers").unwrap().contains("if-match"));
+    assert_eq!(header("access-control-allow-credentials").as_deref(), Some("true"));
+    assert_eq!(header("access-control-max-age").as_deref(), Some("3600"));
+
+    // /sys allows no origin and the public routes no PATCH
+    assert_ne!(status(&app, preflight("/sys/ping", "http://localhost:8080")).await, StatusCode::OK);
+    assert_ne!(status(&app, preflight("/ping", "http://localhost:8080")).await, StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn cors_origins_follow_a_reload() {
+    let app = cors_app().await;
+    let _swap = CONFIG_SWAP.lock().await;
+    let old = config::GLOBAL_CONFIG.load_full();
+    let mut conf = (*old).clone();
+    conf.cors.app.allowed_origins = Some(vec!["https://new.test".to_string()]);
+    conf.cors.app.allowed_origin_patterns = None;
+    config::GLOBAL_CONFIG.store(Arc::new(conf));
+    let new = cors_get(&app, "/app/ping", "https://new.test").await;
+    let local = cors_get(&app, "/app/ping", "http://localhost:8080").await;
+    config::GLOBAL_CONFIG.store(old);
+
+    assert_eq!(new, (StatusCode::OK, Some("https://new.test".to_string())));
+    assert_eq!(local.1, None);
+    assert_eq!(cors_get(&app, "/app/ping", "https://new.test").await.1, None);
+}
+
+#[actix_web::test]
+async fn requests_are_counted_by_route_pattern() {
+    init_conf();
+    let app = test::init_service(
+        App::new()
+            .wrap(middleware::AccessLogging::default())
+            .route("/counted/{id}", web::route().to(HttpResponse::Ok))
+            .service(handler::metrics::get_metrics),
+    )
+    .await;
+    let count = |method| metrics::HTTP_REQUESTS.with_label_values(&[method, "/counted/{id}", "200"]).get();
+    let (get, other) = (count("GET"), count("other"));
+    test::call_service(&app, test::TestRequest::get().uri("/counted/7").to_request()).await;
+    let brew = actix_web::http::Method::from_bytes(b"BREW").unwrap();
+    test::call_service(&app, test::TestRequest::default().method(brew).uri("/counted/8").to_request()).await;
+    assert_eq!((count("GET"), count("other")), (get + 1, other + 1));
+
+    // one series per pattern and known method, not per id or method name
+    let rendered = metrics::render();
+    assert!(rendered.contains(r#"method="GET",route="/counted/{id}",status="200""#), "{}", rendered);
+    assert!(!rendered.contains("/counted/7") && !rendered.contains("BREW"), "{}", rendered);
+    // only with the admin token
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    let admin = ("Authorization", format!("Bearer {}", ADMIN_TOKEN));
+    let req = test::TestRequest::get().uri("/metrics").insert_header(admin).to_request();
+    assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+}
+
+#[actix_web::test]
+async fn stop_needs_the_admin_token_and_reports_progress() {
+    init_conf();
+    let shutdown = web::Data::new(Shutdown::default());
+    let app = test::init_service(
+        App::new()
+            .app_data(shutdown.clone())
+            .service(handler::stop::stop)
+            .service(handler::stop::progress),
+    )
+    .await;
+    let stop = |graceful: bool, auth: Option<String>| {
+        let req = test::TestRequest::post().uri(&format!("/stop/{}", graceful));
+        match auth {
+            Some(auth) => req.insert_header(("Authorization", auth)).to_request(),
+            None => req.to_request(),
+        }
+    };
+    let admin = || Some(format!("Bearer {}", ADMIN_TOKEN));
+
+    for auth in [None, Some("Bearer wrong-token".to_string()), Some(format!("Basic {}", ADMIN_TOKEN))] {
+        assert_eq!(status(&app, stop(true, auth.clone())).await, StatusCode::UNAUTHORIZED, "{:?}", auth);
+    }
+    assert_eq!(status(&app, test::TestRequest::get().uri("/stop").to_request()).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(shutdown.progress().phase, Phase::Running);
+
+    let resp = test::call_service(&app, stop(true, admin())).await;
+    assert_eq!(resp.status(), StatusCode::ACCEPTED);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body, json!({"phase": "requested", "graceful": true}));
+
+    // carried out by the coordinator, a second stop changes nothing
+    shutdown.run().await;
+    let body: Value = test::read_body_json(test::call_service(&app, stop(false, admin())).await).await;
+    assert_eq!(body, json!({"phase": "stopped", "graceful": true}));
+    let req = test::TestRequest::get().uri("/stop").insert_header(("Authorization", admin().unwrap()));
+    let body: Value = test::call_and_read_body_json(&app, req.to_request()).await;
+    assert_eq!(body, json!({"phase": "stopped", "graceful": true}));
+}
+
+#[actix_web::test]
+async fn unknown_api_keys_share_the_ip_bucket() {
+    init_conf();
+    let app = test::init_service(
+        App::new().service(
+            web::scope("/auth")
+                .wrap(actix_web_example::middleware::RateLimit)
+                .route("/login", web::post().to(|| async { "ok" })),
+        ),
+    )
+    .await;
+    let capacity = config::GLOBAL_CONFIG.load().rate_limit.group("POST", "/auth/login").unwrap().capacity;
+    let peer = "192.0.2.7:4000".parse().unwrap();
+    for i in 0..capacity {
+        let req = test::TestRequest::post().uri("/auth/login").peer_addr(peer).insert_header(("x-api-key", format!("key-{}", i)));
+        assert_eq!(test::call_service(&app, req.to_request()).await.status(), StatusCode::OK);
+    }
+    let req = test::TestRequest::post().uri("/auth/login").peer_addr(peer).insert_header(("x-api-key", "yet-another-key"));
+    assert_eq!(status(&app, req.to_request()).await, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[actix_web::test]
+async fn admins_set_roles_and_teachers_edit_their_own_courses() {
+    let app = auth_app().await;
+    let (_, user) = post(&app, "/auth/register", json!({"username": "ada", "password": "correct horse"})).await;
+    let role_uri = format!("/app/users/{}/role", user["id"].as_str().unwrap());
+    let root = bearer(&app, "root", ROOT_PASSWORD).await;
+    let set_role = |auth: (&'static str, String), body: Value| {
+        test::TestRequest::put().uri(&role_uri).insert_header(auth).set_json(body).to_request()
+    };
+
+    // only admins change roles, teachers need a known teacher_id
+    let ada = bearer(&app, "ada", "correct horse").await;
+    assert_eq!(status(&app, set_role(ada, json!({"role": "admin"}))).await, StatusCode::FORBIDDEN);
+    for body in [json!({"role": "teacher"}), json!({"role": "student", "teacher_id": 1}), json!({"role": "teacher", "teacher_id": 9})] {
+        let req = set_role(root.clone(), body.clone());
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST, "{}", body);
+    }
+    let body: Value = test::call_and_read_body_json(&app, set_role(root.clone(), json!({"role": "teacher", "teacher_id": 1}))).await;
+    assert_eq!((body["role"].as_str(), body["teacher_id"].as_i64()), (Some("teacher"), Some(1)));
+
+    let mut page = Value::Null;
+    for teacher_id in [1, 2] {
+        let req = test::TestRequest::post().uri("/app/courses").insert_header(root.clone())
+            .set_json(json!({"teacher_id": teacher_id, "name": "course", "price": 10.0}));
+        page = test::call_and_read_body_json(&app, req.to_request()).await;
+    }
+    let id_of = |teacher_id: i64| {
+        let items = page["items"].as_array().unwrap();
+        items.iter().find(|c| c["teacher_id"] == teacher_id).unwrap()["id"].as_str().unwrap().to_string()
+    };
+    let ids = [id_of(1), id_of(2)];
+
+    // a new login carries the teacher claims
+    let ada = bearer(&app, "ada", "correct horse").await;
+    let patch = |id: &str| {
+        test::TestRequest::patch().uri(&format!("/app/courses/{}", id)).insert_header(ada.clone())
+            .insert_header(("If-Match", "*")).set_json(json!({"name": "renamed"})).to_request()
+    };
+    let resp = test::call_service(&app, patch(&ids[0])).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    let course: Value = test::read_body_json(resp).await;
+    assert_eq!(course["name"], "renamed");
+    assert_eq!(test::call_service(&app, patch(&ids[1])).await.status(), StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn sign_accepts_what_the_clients_sign() {
+    init_conf();
+    let app = test::init_service(
+        App::new().service(
+            web::scope("/app")
+                .wrap(actix_web_example::middleware::Sign)
+                .route("/json", web::post().to(|body: String| async move { body })),
+        ),
+    )
+    .await;
+    let signed_by = |client: &str, secret: &str, nonce: &str| {
+        let body = r#"{"code":1}"#;
+        let timestamp = chrono::Local::now().timestamp();
+        let canonical = sign_rs::canonical_request("POST", "/app/json", "b=2&a=1", body.as_bytes(), timestamp, nonce);
+        test::TestRequest::post()
+            .uri("/app/json?b=2&a=1")
+            .insert_header((sign_rs::SIGN_HEADER, sign_rs::sign(secret.as_bytes(), &canonical)))
+            .insert_header((sign_rs::SIGN_CLIENT_HEADER, client.to_string()))
+            .insert_header((sign_rs::SIGN_TIMESTAMP_HEADER, timestamp.to_string()))
+            .insert_header((sign_rs::SIGN_NONCE_HEADER, nonce.to_string()))
+            .set_payload(body)
+            .to_request()
+    };
+    let signed = |secret: &str, nonce: &str| signed_by("reqwest", secret, nonce);
+    let nonce = uuid::Uuid::new_v4().to_string();
+    let resp = test::call_service(&app, signed(SIGN_SECRET, &nonce)).await;
+    assert_eq!(resp.status(), StatusCode::OK);
+    assert_eq!(test::read_body(resp).await, r#"{"code":1}"#);
+    // replayed nonce, wrong secret
+    assert_eq!(status(&app, signed(SIGN_SECRET, &nonce)).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(&app, signed("not-the-secret-at-all", "n2")).await, StatusCode::UNAUTHORIZED);
+    // sign.required is true in conf/app.toml
+    let req = test::TestRequest::post().uri("/app/json").set_payload("{}").to_request();
+    assert_eq!(status(&app, req).await, StatusCode::UNAUTHORIZED);
+
+    // session keys of a login sign until they expire, their id cannot be made up
+    let now = chrono::Local::now().timestamp();
+    let key = session_key(now + 60);
+    assert_eq!(test::call_service(&app, signed_by(&key.client, &key.secret, "n3")).await.status(), StatusCode::OK);
+    let expired = session_key(now - 1);
+    assert_eq!(status(&app, signed_by(&expired.client, &expired.secret, "n4")).await, StatusCode::UNAUTHORIZED);
+    let forged = key.client.replace(&(now + 60).to_string(), &(now + 3600).to_string());
+    assert_eq!(status(&app, signed_by(&forged, &key.secret, "n5")).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(&app, signed_by("session.x", &key.secret, "n6")).await, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn panics_become_500_with_the_request_id() {
+    use actix_web_example::handler::err_handlers::ErrorHandlers;
+
+    async fn boom() -> &'static str {
+        panic!("boom")
+    }
+    let app = test::init_service(
+        App::new()
+            .wrap(actix_web_example::middleware::RequestId)
+            .service(web::scope("/app").wrap(ErrorHandlers::api()).route("/boom", web::get().to(boom))),
+    )
+    .await;
+    let req = test::TestRequest::get().uri("/app/boom").to_request();
+    // the request went down with the panic, the server renders the error
+    let err = test::try_call_service(&app, req).await.expect_err("panic turned into an error");
+    let resp = err.error_response();
+    assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let request_id = resp.headers().get("x-request-id").unwrap().to_str().unwrap().to_string();
+    let body: Value = serde_json::from_slice(&actix_web::body::to_bytes(resp.into_body()).await.unwrap()).unwrap();
+    assert_eq!(body["code"], "ACTIX_000010");
+    assert_eq!(body["request_id"], request_id.as_str());
+}
+
+#[actix_web::test]
+async fn request_ids_are_echoed_or_replaced() {
+    use actix_web_example::utils::request_id::{self, ReqId, MDC_KEY};
+
+    /// The id as the handler sees it: extractor, task-local and log MDC.
+    async fn seen(id: ReqId) -> HttpResponse {
+        let mdc = log_mdc::get(MDC_KEY, |v| v.map(str::to_string));
+        let current = request_id::current().map(|id| id.to_string());
+        HttpResponse::Ok().json(json!([id.as_str(), current, mdc]))
+    }
+    let app = test::init_service(App::new().wrap(middleware::RequestId).route("/seen", web::get().to(seen))).await;
+    let call = |sent: Option<String>| {
+        let req = test::TestRequest::get().uri("/seen");
+        let req = match sent {
+            Some(sent) => req.insert_header(("X-Request-Id", sent)),
+            None => req,
+        };
+        test::call_service(&app, req.to_request())
+    };
+
+    for valid in ["abc-123_x.y:z".to_string(), "a".repeat(64)] {
+        let resp = call(Some(valid.clone())).await;
+        assert_eq!(resp.headers().get("x-request-id").unwrap(), valid.as_str());
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body, json!([valid, valid, valid]));
+    }
+    let invalid = [Some("a".repeat(65)), Some("a/b".to_string()), Some("x y".to_string()), Some(String::new()), None];
+    for sent in invalid {
+        let resp = call(sent.clone()).await;
+        let id = resp.headers().get("x-request-id").unwrap().to_str().unwrap().to_string();
+        assert_ne!(Some(&id), sent.as_ref());
+        assert!(uuid::Uuid::parse_str(&id).is_ok(), "{}", id);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body, json!([id, id, id]), "{:?}", sent);
+    }
+    // only set while the request is handled
+    assert_eq!(request_id::current(), None);
+    assert_eq!(log_mdc::get(MDC_KEY, |v| v.map(str::to_string)), None);
+}
+
+#[actix_web::test]
+async fn unknown_pages_are_html() {
+    use actix_web_example::handler::err_handlers::ErrorHandlers;
+
+    let app = test::init_service(
+        App::new()
+            .wrap(ErrorHandlers::pages())
+            .service(web::scope("/app").wrap(ErrorHandlers::api()).route("/json", web::get().to(|| async { "{}" })))
+            .service(web::scope("").route("/", web::get().to(|| async { "index" }))),
+    )
+    .await;
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/no-such-page").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let content_type = resp.headers().get("content-type").unwrap().to_str().unwrap().to_string();
+    assert!(content_type.starts_with("text/html"), "{}", content_type);
+    let html = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+    assert!(html.contains("404"), "{}", html);
+
+    // the API scopes keep their JSON bodies
+    let resp = test::call_service(&app, test::TestRequest::get().uri("/app/missing").to_request()).await;
+    assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    let body: Value = test::read_body_json(resp).await;
+    assert_eq!(body["code"], "ACTIX_000003");
+}
+
+/// Migrated SQLite database in a fresh temp file, through the same pool setup as the server.
+async fn sqlite_pool(name: &str) -> sqlx::AnyPool {
+    let path = std::env::temp_dir().join(format!("actix-web-example-{}-{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let db = config::Database {
+        db_type: Some("sqlite".to_string()),
+        name: Some(path.to_string_lossy().into_owned()),
+        max_idle: None,
+        max_open: Some(8),
+        connect_timeout: None,
+        idle_timeout: None,
+        max_lifetime: None,
+    };
+    let pool = config::setup_db(&db).unwrap();
+    migrate::up(&pool).await.unwrap();
+    pool
+}
+
+fn sql_teacher(name: &str) -> Teacher {
+    Teacher { id: None, name: name.to_string(), email: None, bio: None, created_at: None, updated_at: None }
+}
+
+fn sql_course(teacher_id: i64) -> model::Course {
+    let mut course = model::Course::new();
+    course.teacher_id = teacher_id;
+    course.name = Some("rust".to_string());
+    course
+}
+
+#[actix_web::test]
+async fn sqlite_enforces_the_teacher_foreign_key() {
+    let pool = sqlite_pool("teachers").await;
+    let teachers = SqlTeacherRepository::new(pool.clone());
+    let courses = SqlCourseRepository::new(pool.clone());
+    let kept = teachers.create(&sql_teacher("ada")).await.unwrap().id.unwrap();
+    let gone = teachers.create(&sql_teacher("bob")).await.unwrap().id.unwrap();
+
+    let err = courses.create(&sql_course(99)).await.expect_err("unknown teacher");
+    assert!(matches!(err, AppError::Conflict(_)), "{:?}", err);
+    let err = sqlx::query("INSERT INTO courses (id, teacher_id, name) VALUES ('raw', 99, 'raw')")
+        .execute(&pool)
+        .await
+        .expect_err("unknown teacher");
+    assert!(err.as_database_error().is_some_and(|e| e.is_foreign_key_violation()), "{:?}", err);
+
+    courses.create(&sql_course(kept)).await.unwrap();
+    courses.create(&sql_course(gone)).await.unwrap();
+    let err = teachers.delete(gone, false).await.expect_err("teacher has courses");
+    assert!(matches!(err, AppError::Conflict(_)), "{:?}", err);
+    // the foreign key blocks it without the repository's count as well
+    let err = sqlx::query("DELETE FROM teachers WHERE id = ?")
+        .bind(gone)
+        .execute(&pool)
+        .await
+        .expect_err("teacher has courses");
+    assert!(err.as_database_error().is_some_and(|e| e.is_foreign_key_violation()), "{:?}", err);
+
+    teachers.delete(gone, true).await.unwrap();
+    let left = courses.list(&Default::default()).await.unwrap();
+    assert_eq!(left.total, 1);
+    assert!(left.items.iter().all(|c| c.teacher_id == kept));
+    assert!(matches!(teachers.delete(gone, true).await, Err(AppError::NotFound(_))));
+}
+
+#[actix_web::test]
+async fn sqlite_pages_through_null_prices_by_cursor() {
+    let pool = sqlite_pool("null-prices").await;
+    let teachers = SqlTeacherRepository::new(pool.clone());
+    let courses = SqlCourseRepository::new(pool.clone());
+    let teacher_id = teachers.create(&sql_teacher("ada")).await.unwrap().id.unwrap();
+    let mut ids = Vec::new();
+    for price in [None, Some(5.0), None, Some(1.0), Some(5.0), None, Some(3.0)] {
+        let id = courses.create(&sql_course(teacher_id)).await.unwrap().id.unwrap();
+        sqlx::query("UPDATE courses SET price = ? WHERE id = ?")
+            .bind(price)
+            .bind(id.clone())
+            .execute(&pool)
+            .await
+            .unwrap();
+        ids.push(id);
+    }
+    ids.sort();
+
+    for sort in ["price", "-price", "-price,name"] {
+        let (mut seen, mut prices, mut cursor) = (Vec::new(), Vec::new(), None);
+        loop {
+            let query =
+                model::CourseQuery { page_size: Some(2), sort: Some(sort.to_string()), cursor, ..Default::default() };
+            let page = courses.list(&query).await.unwrap();
+            assert!(page.items.len() <= 2, "{}", sort);
+            seen.extend(page.items.iter().map(|c| c.id.clone().unwrap()));
+            prices.extend(page.items.iter().map(|c| c.price));
+            cursor = page.next_cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        seen.sort();
+        assert_eq!(seen, ids, "{}", sort);
+        let expected = [None, None, None, Some(1.0), Some(3.0), Some(5.0), Some(5.0)];
+        if sort == "price" {
+            assert_eq!(prices, expected);
+        } else {
+            assert_eq!(prices, expected.into_iter().rev().collect::<Vec<_>>(), "{}", sort);
+        }
+    }
+}
+
+#[actix_web::test]
+async fn sqlite_enrolls_one_of_many_concurrent_students_into_the_last_seat() {
+    let pool = sqlite_pool("enrollments").await;
+    let teachers = SqlTeacherRepository::new(pool.clone());
+    let courses = SqlCourseRepository::new(pool.clone());
+    let enrollments = Arc::new(SqlEnrollmentRepository::new(pool.clone()));
+    let teacher_id = teachers.create(&sql_teacher("ada")).await.unwrap().id.unwrap();
+    let mut course = sql_course(teacher_id);
+    course.capacity = Some(1);
+    let course_id = courses.create(&course).await.unwrap().id.unwrap();
+
+    let handles: Vec<_> = (0..16)
+        .map(|n| {
+            let (enrollments, course_id) = (enrollments.clone(), course_id.clone());
+            tokio::spawn(async move { enrollments.enroll(&course_id, &format!("student-{}", n)).await })
+        })
+        .collect();
+    let mut statuses = Vec::new();
+    for handle in handles {
+        statuses.push(handle.await.unwrap().unwrap().status);
+    }
+    assert_eq!(statuses.iter().filter(|s| **s == EnrollmentStatus::Enrolled).count(), 1);
+    assert_eq!(statuses.iter().filter(|s| **s == EnrollmentStatus::Waitlisted).count(), 15);
+}
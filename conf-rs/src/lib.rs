@@ -0,0 +1,391 @@
+//! Layered toml configuration shared by the services of this repository.
+//!
+//! Layers, later ones win:
+//!
+//! 1. built-in defaults, see [`Loader::defaults`]
+//! 2. the file given by `--config <path>`, `<PREFIX>_CONFIG` or [`Loader::default_file`]
+//! 3. environment overrides `<PREFIX>__SERVER__PORT=8080`, `__` separates the keys and a
+//!    number indexes an array, e.g. `APP__SERVER__SERVICES__0__PORT`
+//! 4. `{{var}}` placeholders in string values, taken from the environment variable `VAR`
+//!    or from [`Loader::var`]
+//!
+//! ```ignore
+//! let conf: Conf = conf_rs::Loader::new("APP")
+//!     .defaults(include_str!("app.toml"))
+//!     .default_file("conf/app.toml")
+//!     .args(std::env::args())
+//!     .var("addr", "127.0.0.1")
+//!     .load()?;
+//! ```
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use toml::value::Table;
+use toml::Value;
+
+#[derive(Debug)]
+pub enum ConfError {
+    /// the config file could not be read
+    Io { path: PathBuf, source: io::Error },
+    /// a layer is not valid toml
+    Parse { layer: String, msg: String },
+    /// an environment override does not fit the config
+    Env { var: String, msg: String },
+    /// a `{{var}}` placeholder has no value
+    Template { path: String, name: String },
+    /// a value has the wrong type or fails validation
+    Field { path: String, msg: String },
+}
+
+impl fmt::Display for ConfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ConfError::Parse { layer, msg } => write!(f, "{}: {}", layer, msg),
+            ConfError::Env { var, msg } => write!(f, "{}: {}", var, msg),
+            ConfError::Template { path, name } => write!(f, "{}: no value for {{{{{}}}}}", path, name),
+            ConfError::Field { path, msg } => write!(f, "{}: {}", path, msg),
+        }
+    }
+}
+
+impl std::error::Error for ConfError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub struct Loader {
+    prefix: String,
+    defaults: Option<String>,
+    default_file: Option<PathBuf>,
+    file: Option<PathBuf>,
+    vars: HashMap<String, String>,
+    env: Option<Vec<(String, String)>>,
+}
+
+impl Loader {
+    /// `prefix` names the environment variables, `APP` reads `APP_CONFIG` and `APP__*`.
+    pub fn new(prefix: &str) -> Loader {
+        Loader {
+            prefix: prefix.to_string(),
+            defaults: None,
+            default_file: None,
+            file: None,
+            vars: HashMap::new(),
+            env: None,
+        }
+    }
+
+    /// Toml used as the lowest layer, the config file becomes optional.
+    pub fn defaults(mut self, toml: &str) -> Loader {
+        self.defaults = Some(toml.to_string());
+        self
+    }
+
+    /// File read when neither `--config` nor `<PREFIX>_CONFIG` is given.
+    pub fn default_file<P: Into<PathBuf>>(mut self, path: P) -> Loader {
+        self.default_file = Some(path.into());
+        self
+    }
+
+    /// File to read, it must exist.
+    pub fn file<P: Into<PathBuf>>(mut self, path: P) -> Loader {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// Picks up `--config <path>` or `--config=<path>`, other arguments are ignored.
+    pub fn args<I: IntoIterator<Item = String>>(mut self, args: I) -> Loader {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--config" {
+                if let Some(path) = args.next() {
+                    self.file = Some(PathBuf::from(path));
+                }
+            } else if let Some(path) = arg.strip_prefix("--config=") {
+                self.file = Some(PathBuf::from(path));
+            }
+        }
+        self
+    }
+
+    /// Default value of a `{{name}}` placeholder.
+    pub fn var(mut self, name: &str, value: &str) -> Loader {
+        self.vars.insert(name.to_string(), value.to_string());
+        self
+    }
+
+    /// Environment to read instead of the process environment.
+    pub fn env<I: IntoIterator<Item = (String, String)>>(mut self, vars: I) -> Loader {
+        self.env = Some(vars.into_iter().collect());
+        self
+    }
+
+    /// Path of the config file that [`Loader::load`] reads, if any.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.file.clone()
+            .or_else(|| self.env_var(&format!("{}_CONFIG", self.prefix)).map(PathBuf::from))
+            .or_else(|| self.default_file.clone())
+    }
+
+    pub fn load<T: DeserializeOwned>(&self) -> Result<T, ConfError> {
+        let value = self.merged()?;
+        serde_path_to_error::deserialize(value).map_err(|e| ConfError::Field {
+            path: e.path().to_string(),
+            msg: e.inner().to_string(),
+        })
+    }
+
+    /// All layers merged into one toml value.
+    pub fn merged(&self) -> Result<Value, ConfError> {
+        let mut root = match self.defaults.as_ref() {
+            Some(toml) => parse("defaults", toml)?,
+            None => Value::Table(Table::new()),
+        };
+
+        // the default file may be missing when there are defaults
+        let explicit = self.file.is_some() || self.env_var(&format!("{}_CONFIG", self.prefix)).is_some();
+        if let Some(path) = self.path() {
+            match fs::read_to_string(&path) {
+                Ok(text) => merge(&mut root, parse(&path.display().to_string(), &text)?),
+                Err(e) if !explicit && self.defaults.is_some() && e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(ConfError::Io { path, source: e }),
+            }
+        }
+
+        let prefix = format!("{}__", self.prefix);
+        let mut overrides: Vec<(String, String)> = self.env_vars()
+            .into_iter()
+            .filter(|(k, _)| k.starts_with(&prefix))
+            .collect();
+        overrides.sort();
+        for (var, raw) in overrides {
+            let keys: Vec<&str> = var[prefix.len()..].split("__").collect();
+            set(&mut root, &keys, &raw).map_err(|msg| ConfError::Env { var: var.clone(), msg })?;
+        }
+
+        self.render(&mut root, &mut String::new())?;
+        Ok(root)
+    }
+
+    fn env_vars(&self) -> Vec<(String, String)> {
+        match self.env.as_ref() {
+            Some(vars) => vars.clone(),
+            None => std::env::vars().collect(),
+        }
+    }
+
+    fn env_var(&self, name: &str) -> Option<String> {
+        match self.env.as_ref() {
+            Some(vars) => vars.iter().find(|(k, _)| k == name).map(|(_, v)| v.clone()),
+            None => std::env::var(name).ok(),
+        }
+    }
+
+    /// Replaces the `{{var}}` placeholders of every string below `value`.
+    fn render(&self, value: &mut Value, path: &mut String) -> Result<(), ConfError> {
+        match value {
+            Value::String(s) => {
+                let mut out = String::new();
+                let mut rest = s.as_str();
+                while let Some(start) = rest.find("{{") {
+                    let end = match rest[start..].find("}}") {
+                        Some(end) => start + end,
+                        None => break,
+                    };
+                    let name = rest[start + 2..end].trim();
+                    let var = self.env_var(&name.to_uppercase())
+                        .or_else(|| self.vars.get(name).cloned())
+                        .ok_or_else(|| ConfError::Template { path: path.clone(), name: name.to_string() })?;
+                    out.push_str(&rest[..start]);
+                    out.push_str(&var);
+                    rest = &rest[end + 2..];
+                }
+                out.push_str(rest);
+                *s = out;
+            }
+            Value::Array(items) => {
+                for (i, item) in items.iter_mut().enumerate() {
+                    let len = path.len();
+                    path.push_str(&format!("[{}]", i));
+                    self.render(item, path)?;
+                    path.truncate(len);
+                }
+            }
+            Value::Table(table) => {
+                for (key, item) in table.iter_mut() {
+                    let len = path.len();
+                    if !path.is_empty() {
+                        path.push('.');
+                    }
+                    path.push_str(key);
+                    self.render(item, path)?;
+                    path.truncate(len);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+fn parse(layer: &str, text: &str) -> Result<Value, ConfError> {
+    toml::from_str(text).map_err(|e| ConfError::Parse { layer: layer.to_string(), msg: e.to_string() })
+}
+
+/// Deep merge, tables are merged key by key, anything else in `over` replaces `base`.
+fn merge(base: &mut Value, over: Value) {
+    match (base, over) {
+        (Value::Table(base), Value::Table(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, over) => *base = over,
+    }
+}
+
+/// env keys are upper case and cannot hold `.` or `-`
+fn normalize(key: &str) -> String {
+    key.to_lowercase().replace(['.', '-'], "_")
+}
+
+/// Sets the value at `keys`, creating missing tables.
+fn set(value: &mut Value, keys: &[&str], raw: &str) -> Result<(), String> {
+    let (key, rest) = match keys.split_first() {
+        Some(split) => split,
+        None => {
+            *value = match value {
+                // keep strings as strings, e.g. a numeric password
+                Value::String(_) => Value::String(raw.to_string()),
+                _ => parse_scalar(raw),
+            };
+            return Ok(());
+        }
+    };
+    match value {
+        Value::Table(table) => {
+            let name = table.keys()
+                .find(|k| normalize(k) == normalize(key))
+                .cloned()
+                .unwrap_or_else(|| key.to_lowercase());
+            let next = table.entry(name).or_insert_with(|| Value::Table(Table::new()));
+            set(next, rest, raw)
+        }
+        Value::Array(items) => {
+            let len = items.len();
+            let next = key.parse::<usize>().ok()
+                .and_then(|i| items.get_mut(i))
+                .ok_or_else(|| format!("{} is not an index below {}", key, len))?;
+            set(next, rest, raw)
+        }
+        _ => Err(format!("{} is not a table", key)),
+    }
+}
+
+/// Reads `raw` as a toml value (`8080`, `true`, `["a", "b"]`), falling back to a string.
+fn parse_scalar(raw: &str) -> Value {
+    toml::from_str::<Table>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize)]
+    struct Conf {
+        server: Server,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Server {
+        name: String,
+        port: u16,
+        services: Vec<Service>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Service {
+        address: String,
+        port: u16,
+    }
+
+    const DEFAULTS: &str = r#"
+        [server]
+        name = "actix-web"
+        port = 8088
+        services = [{ address = "{{addr}}", port = 8088 }]
+    "#;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn env_overrides_and_templates() {
+        let conf: Conf = Loader::new("APP")
+            .defaults(DEFAULTS)
+            .default_file("missing.toml")
+            .var("addr", "127.0.0.1")
+            .env(env(&[("APP__SERVER__PORT", "9000"), ("APP__SERVER__SERVICES__0__PORT", "9443")]))
+            .load()
+            .unwrap();
+        assert_eq!(conf.server.name, "actix-web");
+        assert_eq!(conf.server.port, 9000);
+        assert_eq!(conf.server.services[0].address, "127.0.0.1");
+        assert_eq!(conf.server.services[0].port, 9443);
+
+        let conf: Conf = Loader::new("APP")
+            .defaults(DEFAULTS)
+            .env(env(&[("ADDR", "0.0.0.0")]))
+            .load()
+            .unwrap();
+        assert_eq!(conf.server.services[0].address, "0.0.0.0");
+    }
+
+    #[test]
+    fn errors_name_the_field() {
+        let err = Loader::new("APP")
+            .defaults(DEFAULTS)
+            .var("addr", "127.0.0.1")
+            .env(env(&[("APP__SERVER__SERVICES__0__PORT", "http")]))
+            .load::<Conf>()
+            .unwrap_err();
+        assert!(err.to_string().starts_with("server.services[0].port:"), "{}", err);
+
+        let err = Loader::new("APP").defaults(DEFAULTS).env(Vec::new()).load::<Conf>().unwrap_err();
+        assert_eq!(err.to_string(), "server.services[0].address: no value for {{addr}}");
+
+        let err = Loader::new("APP")
+            .defaults(DEFAULTS)
+            .env(env(&[("APP_CONFIG", "missing.toml")]))
+            .load::<Conf>()
+            .unwrap_err();
+        assert!(matches!(err, ConfError::Io { .. }));
+    }
+
+    #[test]
+    fn config_argument() {
+        let loader = Loader::new("APP").args(vec!["bin".to_string(), "--config=a.toml".to_string()]);
+        assert_eq!(loader.path(), Some(PathBuf::from("a.toml")));
+        let loader = Loader::new("APP").args(vec!["--config".to_string(), "b.toml".to_string()]);
+        assert_eq!(loader.path(), Some(PathBuf::from("b.toml")));
+    }
+}
@@ -0,0 +1,98 @@
+//! Login against `/auth/login` of actix-web-example, every `/app` request carries the access
+//! token as `Authorization: Bearer ...`. The token is refreshed through `/auth/refresh`
+//! shortly before it expires, a failed refresh logs in again.
+use std::env;
+use std::time::{Duration, Instant};
+
+use log::*;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+/// account the client logs in with
+pub const USERNAME_ENV: &str = "APP_USERNAME";
+pub const PASSWORD_ENV: &str = "APP_PASSWORD";
+/// tokens this close to expiring are refreshed first
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+struct TokenPair {
+    access_token: String,
+    expires_in: u64,
+    refresh_token: String,
+}
+
+struct Token {
+    access: String,
+    refresh: String,
+    expires: Instant,
+}
+
+pub struct Session {
+    base_url: String,
+    username: String,
+    password: String,
+    token: Mutex<Option<Token>>,
+}
+
+impl Session {
+    /// Reads the account from `APP_USERNAME` and `APP_PASSWORD`, `None` while one is missing.
+    pub fn from_env(base_url: &str) -> Option<Session> {
+        Some(Session {
+            base_url: base_url.to_string(),
+            username: env::var(USERNAME_ENV).ok().filter(|u| !u.is_empty())?,
+            password: env::var(PASSWORD_ENV).ok().filter(|p| !p.is_empty())?,
+            token: Mutex::new(None),
+        })
+    }
+
+    /// Value of the `Authorization` header, logs in or refreshes when needed.
+    pub async fn bearer(&self, client: &reqwest::Client) -> Result<String, reqwest::Error> {
+        let mut token = self.token.lock().await;
+        let valid = token.as_ref().filter(|t| t.expires > Instant::now() + EXPIRY_MARGIN);
+        if let Some(t) = valid {
+            return Ok(format!("Bearer {}", t.access));
+        }
+        let refreshed = match token.as_ref() {
+            Some(t) => {
+                let body = serde_json::json!({ "refresh_token": t.refresh });
+                match self.post(client, "/auth/refresh", &body).await {
+                    Ok(pair) => Some(pair),
+                    Err(e) => {
+                        warn!("refresh failed, logging in again: {}", e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let pair = match refreshed {
+            Some(pair) => pair,
+            None => {
+                let body = serde_json::json!({ "username": self.username, "password": self.password });
+                self.post(client, "/auth/login", &body).await?
+            }
+        };
+        let access = pair.access_token.clone();
+        *token = Some(Token {
+            access: pair.access_token,
+            refresh: pair.refresh_token,
+            expires: Instant::now() + Duration::from_secs(pair.expires_in),
+        });
+        Ok(format!("Bearer {}", access))
+    }
+
+    /// Drops the token after a 401, the next request logs in again.
+    pub async fn forget(&self) {
+        *self.token.lock().await = None;
+    }
+
+    async fn post(&self, client: &reqwest::Client, path: &str, body: &serde_json::Value) -> Result<TokenPair, reqwest::Error> {
+        client.post(format!("{}{}", self.base_url, path))
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<TokenPair>()
+            .await
+    }
+}
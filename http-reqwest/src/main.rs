@@ -1,4 +1,8 @@
+mod auth;
+mod sign;
+
 use std::collections::HashMap;
+use std::sync::Arc;
 use reqwest::header::HeaderMap;
 use serde_json::value::Value;
 use std::path::{Path};
@@ -8,6 +12,9 @@ use log::*;
 use serde::{Deserialize, Serialize};
 use tokio_cron_scheduler::{JobScheduler, Job};
 
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const BASE_URL: &str = "http://127.0.0.1:8088";
+
 fn init_log() {
     let mut cwd = env::current_dir().unwrap();
     let p = Path::new("conf/log4rs.yaml");
@@ -20,6 +27,14 @@ fn init_log() {
 #[tokio::main]
 async fn main() {
     init_log();
+    // every /app route needs a jwt
+    let session = match auth::Session::from_env(BASE_URL) {
+        Some(session) => Arc::new(session),
+        None => {
+            error!("set {} and {} to the account the client logs in with", auth::USERNAME_ENV, auth::PASSWORD_ENV);
+            return;
+        }
+    };
     let expression: &str = "1/1 * * * * *";
     let mut sched = JobScheduler::new();
     // sched.shutdown_on_ctrl_c();
@@ -29,15 +44,16 @@ async fn main() {
         })
     })).unwrap();
 
-    let four_s_job_async = Job::new_async(expression, |_uuid, _l| {
+    let four_s_job_async = Job::new_async(expression, move |_uuid, _l| {
+        let session = session.clone();
         Box::pin(async move {
-            let res = get_list_req("http://127.0.0.1:8080/app/bytes").await;
+            let res = get_list_req(&session, "/app/bytes").await;
             info!("bytes body: {:?}", res);
-            let res = get_list_req("http://127.0.0.1:8080/app/json").await;
+            let res = get_list_req(&session, "/app/json").await;
             info!("json body: {:?}", res);
-            let res = get_list_req("http://127.0.0.1:8080/app/extract_json").await;
+            let res = get_list_req(&session, "/app/extract_json").await;
             info!("extract_json body: {:?}", res);
-            let res = get_list_req("http://127.0.0.1:8080/app/payload").await;
+            let res = get_list_req(&session, "/app/payload").await;
             info!("payload body: {:?}", res);
             // match res {
             //     Ok(o) => {
@@ -73,26 +89,40 @@ struct ResponseData {
     user_id: i32,
 }
 
-async fn get_list_req(host_path : &str) -> Result<ResponseBody, reqwest::Error> {
+async fn get_list_req(session: &auth::Session, path: &str) -> Result<ResponseBody, reqwest::Error> {
+    let host_path = &format!("{}{}", BASE_URL, path);
     let client = reqwest::Client::builder().no_proxy().build().expect("should be able to build reqwest client");
     let mut headers = HeaderMap::new();
+    headers.insert("Authorization", session.bearer(&client).await?.parse().unwrap());
     headers.insert("Content-Type", "application/json".parse().unwrap());
     headers.insert("test_header", "a9999".parse().unwrap());
     headers.insert("test_header1", "a9999".parse().unwrap());
-    headers.insert("sign", "123456".parse().unwrap());
+    // correlation id, the server logs it and echoes it back
+    let request_id = uuid::Uuid::new_v4().to_string();
+    headers.insert(REQUEST_ID_HEADER, request_id.parse().unwrap());
     let new_post = ResponseBody {
         code: Some(1),
         data:None,
         msg: "req msg".to_string(),
     };
-    let body = client.post(host_path)
+    let payload = serde_json::to_vec(&new_post).unwrap();
+    let url = reqwest::Url::parse(host_path).unwrap();
+    match sign::Signer::from_env() {
+        Some(signer) => headers.extend(signer.headers("POST", &url, &payload)),
+        None => warn!("{} is not set, sending {} unsigned", sign::SIGN_SECRET_ENV, host_path),
+    }
+    let resp = client.post(url)
         .headers(headers)
         .timeout(time::Duration::from_secs(10))
-        .json(&new_post)
+        .body(payload)
         .send()
-        .await?
-        .json::<ResponseBody>()
         .await?;
+    if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+        session.forget().await;
+    }
+    let echoed = resp.headers().get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok());
+    info!("{} request_id={} echoed={:?}", host_path, request_id, echoed);
+    let body = resp.json::<ResponseBody>().await?;
     Ok(body)
 }
 
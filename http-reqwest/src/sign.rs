@@ -0,0 +1,73 @@
+//! Request signing for the `sign` check of actix-web-example, the signed string comes from
+//! `sign-rs` so it always matches the server.
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::Url;
+use sign_rs::{SIGN_CLIENT_HEADER, SIGN_HEADER, SIGN_NONCE_HEADER, SIGN_TIMESTAMP_HEADER};
+
+/// client id, `reqwest` when unset
+pub const SIGN_CLIENT_ENV: &str = "SIGN_CLIENT";
+/// secret shared with the server's `sign.clients`
+pub const SIGN_SECRET_ENV: &str = "SIGN_SECRET";
+
+pub struct Signer {
+    client_id: String,
+    secret: Vec<u8>,
+}
+
+impl Signer {
+    pub fn new(client_id: &str, secret: &str) -> Signer {
+        Signer {
+            client_id: client_id.to_string(),
+            secret: secret.as_bytes().to_vec(),
+        }
+    }
+
+    /// Reads the client from `SIGN_CLIENT` and `SIGN_SECRET`, `None` without a secret.
+    pub fn from_env() -> Option<Signer> {
+        let secret = env::var(SIGN_SECRET_ENV).ok().filter(|s| !s.is_empty())?;
+        let client_id = env::var(SIGN_CLIENT_ENV).unwrap_or_else(|_| "reqwest".to_string());
+        Some(Signer::new(&client_id, &secret))
+    }
+
+    /// Returns the headers to send along with `method url` and `body`.
+    pub fn headers(&self, method: &str, url: &Url, body: &[u8]) -> HeaderMap {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default();
+        let nonce = uuid::Uuid::new_v4().to_string();
+        let canonical = sign_rs::canonical_request(method, url.path(), url.query().unwrap_or(""), body, timestamp, &nonce);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(SIGN_HEADER, HeaderValue::from_str(&sign_rs::sign(&self.secret, &canonical)).unwrap());
+        headers.insert(SIGN_CLIENT_HEADER, HeaderValue::from_str(&self.client_id).unwrap());
+        headers.insert(SIGN_TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+        headers.insert(SIGN_NONCE_HEADER, HeaderValue::from_str(&nonce).unwrap());
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headers_carry_a_verifiable_signature() {
+        let url = Url::parse("http://127.0.0.1:8080/app/json?b=2&a=1").unwrap();
+        let headers = Signer::new("reqwest", "reqwest-client-secret").headers("POST", &url, b"{}");
+        let get = |name: &str| headers.get(name).unwrap().to_str().unwrap().to_string();
+        let canonical = sign_rs::canonical_request(
+            "POST",
+            "/app/json",
+            "a=1&b=2",
+            b"{}",
+            get(SIGN_TIMESTAMP_HEADER).parse().unwrap(),
+            &get(SIGN_NONCE_HEADER),
+        );
+        assert_eq!(get(SIGN_HEADER), sign_rs::sign(b"reqwest-client-secret", &canonical));
+        assert_eq!(get(SIGN_CLIENT_HEADER), "reqwest");
+    }
+}
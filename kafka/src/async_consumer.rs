@@ -29,7 +29,13 @@ pub async fn run_consumer() {
     let (version_n, version_s) = rdkafka::util::get_rdkafka_version();
     info!("rd_kafka_version: 0x{:08x}, {}", version_n, version_s);
 
-    let kafka_config = config::init_kafka();
+    let kafka_config = match config::init_kafka() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("kafka config error: {}", e);
+            return;
+        }
+    };
     let topics_config = kafka_config.topics.unwrap();
     let topics: Vec<&str> = topics_config.iter().map(|s| s.as_str()).collect();
     let consumer: StreamConsumer = util::client_config(Some(kafka_config.config))
@@ -94,12 +100,10 @@ async fn record_borrowed_message_receipt(msg: &BorrowedMessage<'_>) {
 
     // info!("======={}", msg.h)
 
-    if let Some(_h) = msg.headers() {
-        info!("========get header ok=========")
-        // for i in 0..h.count() {
-        //     let header = h.get(i).unwrap();
-        //     info!("header {:#?}: {:?}", header.0, header.1);
-        // }
+    if let Some(h) = msg.headers() {
+        if let Some(request_id) = util::header(h, util::REQUEST_ID_HEADER) {
+            info!("request_id={} offset={}", request_id, msg.offset());
+        }
     }
     // consumer.commit_message(&msg, CommitMode::Async).unwrap();
 }
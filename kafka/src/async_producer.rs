@@ -6,12 +6,20 @@ use rdkafka::producer::{FutureProducer, FutureRecord};
 use std::time::Duration;
 use tokio::time::Instant;
 
-pub async fn run_producer() {
-    info!("producer run");
+/// Sends a message every 100ms until the task is dropped, each with `request_id` of the caller
+/// in the [`util::REQUEST_ID_HEADER`] header.
+pub async fn run_producer(request_id: String) {
+    info!("producer run request_id={}", request_id);
     let (version_n, version_s) = rdkafka::util::get_rdkafka_version();
     info!("rd_kafka_version: 0x{:08x}, {}", version_n, version_s);
 
-    let mut kafka_config = config::init_kafka();
+    let mut kafka_config = match config::init_kafka() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("kafka config error: {}", e);
+            return;
+        }
+    };
     let producer: FutureProducer = util::client_config(Some(kafka_config.config))
         .create()
         .expect("producer creation error");
@@ -27,7 +35,6 @@ pub async fn run_producer() {
         let timestamp = start.elapsed().as_secs_f64();
         // info!("time:{:?}", start.elapsed());
         let msg = &format!("MSG {:?}", start.elapsed());
-        info!("producer {}", msg);
         let pre = match util::compressed(msg.to_bytes()) {
             Ok(o) => o,
             Err(e) => {
@@ -36,7 +43,9 @@ pub async fn run_producer() {
             }
         };
 
+        info!("producer {} request_id={}", msg, request_id);
         let headers = OwnedHeaders::new()
+            .add(util::REQUEST_ID_HEADER, request_id.as_str())
             .add("header_key", "header_value")
             .add("header_key01", "header_value1")
             .add("header_key02", "header_value2")
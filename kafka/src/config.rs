@@ -1,8 +1,8 @@
+pub use conf_rs::ConfError;
 use log::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::env;
-use std::io::Read;
 use std::path::Path;
 
 #[derive(Debug, Deserialize)]
@@ -56,18 +56,13 @@ pub fn init_config() {
     info!("{:#?}", decoded);
 }
 
-pub fn init_kafka() -> Kafka {
-    let mut cwd = env::current_dir().unwrap();
-    cwd.push(Path::new("conf"));
-    cwd.push(Path::new("config.toml"));
-    info!("init log path:{}", cwd.to_str().unwrap());
-    let conf_path = cwd.to_str().unwrap();
-
-    let mut file = std::fs::File::open(conf_path).unwrap();
-    let mut contents = String::new();
-    file.read_to_string(&mut contents).unwrap();
-    info!("read file: {:?}", contents);
-    let decoded: Kafka = toml::from_str(contents.as_str()).unwrap();
+/// Reads `conf/config.toml`, `--config`/`KAFKA_CONFIG` and `KAFKA__*` overrides,
+/// e.g. `KAFKA__CONFIG__BOOTSTRAP_SERVERS` for `bootstrap.servers`.
+pub fn init_kafka() -> Result<Kafka, ConfError> {
+    let decoded: Kafka = conf_rs::Loader::new("KAFKA")
+        .default_file(Path::new("conf").join("config.toml"))
+        .args(env::args())
+        .load()?;
     info!("decoded: {:#?}", decoded);
     // Kafka {
     //     group_id: None,
@@ -79,5 +74,5 @@ pub fn init_kafka() -> Kafka {
     //     KerberosPrincipal: None,
     //     Protocol: None,
     // }
-    decoded
+    Ok(decoded)
 }
@@ -5,6 +5,7 @@ use kafka_v1::async_producer;
 use kafka_v1::config;
 use kafka_v1::log::*;
 use kafka_v1::signal;
+use kafka_v1::util;
 use log::*;
 // use tokio::signal::unix::SignalKind;
 // use tokio::sync::mpsc;
@@ -14,7 +15,8 @@ use log::*;
 async fn main() {
     init();
     tokio::task::spawn(async_consumer::async_consumer());
-    tokio::task::spawn(async_producer::run_producer());
+    // not caused by a request, the producer run gets an id of its own
+    tokio::task::spawn(async_producer::run_producer(util::request_id()));
 
     // tokio::time::sleep(Duration::from_secs(60 * 10)).await;
     // let (_shutdown_tx, mut shutdown_rx) = mpsc::unbounded_channel();
@@ -32,6 +34,8 @@ async fn main() {
 fn init() {
     log_init();
     // config::init_config();
-    let k = config::init_kafka();
-    info!("kafka config: {:?}", k)
+    match config::init_kafka() {
+        Ok(k) => info!("kafka config: {:?}", k),
+        Err(e) => error!("kafka config error: {}", e),
+    }
 }
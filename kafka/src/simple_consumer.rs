@@ -59,7 +59,13 @@ async fn consume_and_print() {
     info!("rd_kafka_version: 0x{:08x}, {}", version_n, version_s);
 
     let context = CustomContext {};
-    let kafka_config = config::init_kafka();
+    let kafka_config = match config::init_kafka() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("kafka config error: {}", e);
+            return;
+        }
+    };
     let topics_config = kafka_config.topics.unwrap();
     let topics: Vec<&str> = topics_config.iter().map(|s| s.as_str()).collect();
     let consumer: StreamConsumer<CustomContext> = util::client_config(Some(kafka_config.config))
@@ -1,18 +1,26 @@
 use crate::config;
 use crate::util;
-use log::info;
+use log::{error, info};
 use rdkafka::message::OwnedHeaders;
 use rdkafka::producer::{FutureProducer, FutureRecord};
 use std::time::Duration;
 
-pub async fn simple_producer() {
+/// Sends 10000 messages, all with `request_id` of the caller in the
+/// [`util::REQUEST_ID_HEADER`] header.
+pub async fn simple_producer(request_id: &str) {
     let (version_n, version_s) = rdkafka::util::get_rdkafka_version();
     info!("rd_kafka_version: 0x{:08x}, {}", version_n, version_s);
-    produce().await;
+    produce(request_id).await;
 }
 
-async fn produce() {
-    let kafka_config = config::init_kafka();
+async fn produce(request_id: &str) {
+    let kafka_config = match config::init_kafka() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("kafka config error: {}", e);
+            return;
+        }
+    };
     let output_topic = kafka_config.topic.unwrap();
     let topic = output_topic.as_str();
     let producer: &FutureProducer = &util::client_config(Some(kafka_config.config))
@@ -21,17 +29,20 @@ async fn produce() {
 
     let futures = (0..10000)
         .map(|i| async move {
+            let headers = OwnedHeaders::new()
+                .add(util::REQUEST_ID_HEADER, request_id)
+                .add("header_key", "header_value");
             let delivery_status = producer
                 .send(
                     FutureRecord::to(topic)
                         .payload(&format!("Message {}", i))
                         .key(&format!("Key {}", i))
-                        .headers(OwnedHeaders::new().add("header_key", "header_value")),
+                        .headers(headers),
                     Duration::from_secs(0),
                 )
                 .await;
 
-            info!("Delivery status for message {} received", i);
+            info!("Delivery status for message {} request_id={} received", i, request_id);
             delivery_status
         })
         .collect::<Vec<_>>();
@@ -1,10 +1,15 @@
 use core::result;
 use log::*;
+use rdkafka::message::Headers;
 use rdkafka::ClientConfig;
 use snap::read;
 use snap::write;
 use std::collections::HashMap;
 use std::io::{Read, Write};
+use uuid::Uuid;
+
+/// Correlation id header, same name as the HTTP header of actix-web-example.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
 
 pub fn client_config(config_overrides: Option<HashMap<String, Option<String>>>) -> ClientConfig {
     let mut config = ClientConfig::new();
@@ -92,3 +97,17 @@ pub fn escape(bytes: &[u8]) -> String {
         .map(|b| b as char)
         .collect()
 }
+
+/// Fresh correlation id for messages that are not caused by a request, callers with a
+/// request pass its id on instead.
+pub fn request_id() -> String {
+    Uuid::new_v4().to_string()
+}
+
+/// Value of the first header named `name`.
+pub fn header<H: Headers + ?Sized>(headers: &H, name: &str) -> Option<String> {
+    (0..headers.count())
+        .filter_map(|i| headers.get(i))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| to_string(value))
+}
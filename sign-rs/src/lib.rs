@@ -0,0 +1,112 @@
+//! Request signatures checked by the `sign` middleware of actix-web-example.
+//!
+//! The server and its clients build the signed string with [`canonical_request`] from this
+//! crate, so they cannot drift apart. The signature is the hex HMAC-SHA256 of
+//!
+//! ```plain
+//! METHOD\nPATH\nSORTED_QUERY\nHEX(SHA256(BODY))\nTIMESTAMP\nNONCE
+//! ```
+//!
+//! sent in the [`SIGN_HEADER`] header along with [`SIGN_CLIENT_HEADER`],
+//! [`SIGN_TIMESTAMP_HEADER`] and [`SIGN_NONCE_HEADER`].
+//!
+//! ```ignore
+//! let canonical = sign_rs::canonical_request("POST", "/app/courses", "", body, timestamp, &nonce);
+//! let signature = sign_rs::sign(secret.as_bytes(), &canonical);
+//! assert!(sign_rs::verify(secret.as_bytes(), &canonical, &signature));
+//! ```
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+/// request signature, hex encoded HMAC-SHA256 of the canonical request
+pub const SIGN_HEADER: &str = "sign";
+/// id of the client whose secret signed the request
+pub const SIGN_CLIENT_HEADER: &str = "x-sign-client";
+/// unix timestamp in seconds
+pub const SIGN_TIMESTAMP_HEADER: &str = "x-sign-timestamp";
+/// random string, never reused by a client inside the replay window
+pub const SIGN_NONCE_HEADER: &str = "x-sign-nonce";
+
+/// Builds the string covered by the signature.
+///
+/// Query pairs are sorted by key then value and kept exactly as they were sent.
+pub fn canonical_request(method: &str, path: &str, query: &str, body: &[u8], timestamp: i64, nonce: &str) -> String {
+    let mut pairs: Vec<(&str, &str)> = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| p.split_once('=').unwrap_or((p, "")))
+        .collect();
+    pairs.sort();
+    let query = pairs
+        .iter()
+        .map(|(k, v)| format!("{}={}", k, v))
+        .collect::<Vec<String>>()
+        .join("&");
+    format!("{}\n{}\n{}\n{}\n{}\n{}", method.to_uppercase(), path, query, hex(&Sha256::digest(body)), timestamp, nonce)
+}
+
+/// Signs a canonical request, returns the lowercase hex signature.
+pub fn sign(secret: &[u8], canonical: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts any key length");
+    mac.update(canonical.as_bytes());
+    hex(&mac.finalize().into_bytes())
+}
+
+/// Checks a hex signature, in either case, against a canonical request in constant time.
+pub fn verify(secret: &[u8], canonical: &str, signature: &str) -> bool {
+    let tag = match unhex(signature) {
+        Some(tag) => tag,
+        None => return false,
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("hmac accepts any key length");
+    mac.update(canonical.as_bytes());
+    mac.verify_slice(&tag).is_ok()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => Some(((*high as char).to_digit(16)? * 16 + (*low as char).to_digit(16)?) as u8),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonical_request_sorts_query() {
+        let c = canonical_request("post", "/app/courses", "b=2&a=1&a=0", b"", 1688000000, "n1");
+        assert_eq!(
+            c,
+            "POST\n/app/courses\na=0&a=1&b=2\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855\n1688000000\nn1"
+        );
+    }
+
+    #[test]
+    fn sign_matches_the_rfc_4231_vector() {
+        // test case 2 of RFC 4231
+        let signature = sign(b"Jefe", "what do ya want for nothing?");
+        assert_eq!(signature, "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+    }
+
+    #[test]
+    fn verify_checks_secret_and_encoding() {
+        let c = canonical_request("GET", "/app/courses", "", b"", 1688000000, "n1");
+        let signature = sign(b"secret", &c);
+        assert!(verify(b"secret", &c, &signature));
+        assert!(verify(b"secret", &c, &signature.to_uppercase()));
+        assert!(!verify(b"other", &c, &signature));
+        assert!(!verify(b"secret", &c, &signature[2..]));
+        for bad in ["", "zz", "+1", "abc"] {
+            assert!(!verify(b"secret", &c, bad), "{}", bad);
+        }
+    }
+}
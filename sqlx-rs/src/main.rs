@@ -10,8 +10,6 @@ use futures::TryStream;
 use futures::TryStreamExt;
 use sqlx::types::chrono;
 use sqlx::{Column, Connection, Executor, FromRow, Pool, Row, Statement, TypeInfo};
-use std::fs::File;
-use std::io::Read;
 use std::path::Path;
 
 #[derive(Debug, Clone, sqlx::FromRow)]
@@ -125,22 +123,11 @@ fn extension_explicit(file_name: &str) -> Option<&str> {
 #[async_std::main]
 // or #[tokio::main]
 async fn main() -> Result<(), sqlx::Error> {
-    let mut cwd = env::current_dir().unwrap();
-    let confPath = Path::new("conf").join(Path::new("conf.toml"));
-    cwd = cwd.join(confPath);
-    let confPath = cwd.to_str().unwrap();
-
-    // let file_path = "sample.toml";
-    let mut file = match File::open(confPath) {
-        Ok(f) => f,
-        Err(e) => panic!("no such file {} exception:{}", confPath, e),
-    };
-    let mut str_val = String::new();
-    match file.read_to_string(&mut str_val) {
-        Ok(s) => s,
-        Err(e) => panic!("Error Reading file: {}", e),
-    };
-    let config: config::Config = toml::from_str(&str_val).unwrap();
+    let config: config::Config = conf_rs::Loader::new("SQLX")
+        .default_file(Path::new("conf").join("conf.toml"))
+        .args(env::args())
+        .load()
+        .map_err(|e| sqlx::Error::Configuration(Box::new(e)))?;
     println!("======config: {:?}", config);
 
     let dbStr = format!(
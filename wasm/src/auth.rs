@@ -0,0 +1,97 @@
+//! Login against `/auth/login` of actix-web-example, every `/app` request carries the access
+//! token as `Authorization: Bearer ...` and is signed.
+//!
+//! The bundle holds no signing secret, the login hands out a key that expires with the
+//! access token. Both are kept in `sessionStorage` to survive the reloads after adding or
+//! deleting a course.
+use js_sys::{Date, Math};
+use serde::Deserialize;
+use sign_rs::{SIGN_CLIENT_HEADER, SIGN_HEADER, SIGN_NONCE_HEADER, SIGN_TIMESTAMP_HEADER};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Request, RequestInit, RequestMode, Response, Storage};
+
+const TOKEN_KEY: &str = "access_token";
+const SIGN_CLIENT_KEY: &str = "sign_client";
+const SIGN_SECRET_KEY: &str = "sign_secret";
+
+/// Body of a successful login, the refresh token is not used.
+#[derive(Debug, Deserialize)]
+struct TokenPair {
+    access_token: String,
+    sign_client: String,
+    sign_secret: String,
+}
+
+/// Logs in and keeps the access token for [`authorize`].
+#[wasm_bindgen]
+pub async fn login(username: String, password: String) -> Result<(), JsValue> {
+    let mut opts = RequestInit::new();
+    opts.method("POST");
+    opts.mode(RequestMode::Cors);
+    let body = serde_json::json!({ "username": username, "password": password }).to_string();
+    opts.body(Some(&JsValue::from_str(&body)));
+    let url = format!("http://{}{}", "127.0.0.1:8088", "/auth/login");
+
+    let request = Request::new_with_str_and_init(&url, &opts)?;
+    request.headers().set("Content-Type", "application/json")?;
+    request.headers().set("Accept", "application/json")?;
+    let window = web_sys::window().ok_or("no window exists")?;
+    let resp: Response = JsFuture::from(window.fetch_with_request(&request)).await?.dyn_into()?;
+    if !resp.ok() {
+        return Err(JsValue::from_str(&format!("login failed: {}", resp.status())));
+    }
+    let tokens: TokenPair = serde_wasm_bindgen::from_value(JsFuture::from(resp.json()?).await?)?;
+    let storage = storage()?;
+    storage.set_item(SIGN_CLIENT_KEY, &tokens.sign_client)?;
+    storage.set_item(SIGN_SECRET_KEY, &tokens.sign_secret)?;
+    storage.set_item(TOKEN_KEY, &tokens.access_token)
+}
+
+/// Whether a token from an earlier [`login`] is kept.
+pub fn logged_in() -> bool {
+    token().is_some()
+}
+
+/// Adds the `Authorization` header and signs `method`, `path` (with its query) and `body`
+/// as they are sent, fails before the first [`login`].
+pub fn authorize(request: &Request, method: &str, path: &str, body: &[u8]) -> Result<(), JsValue> {
+    let token = token().ok_or("not logged in")?;
+    let client = item(SIGN_CLIENT_KEY).ok_or("not logged in")?;
+    let secret = item(SIGN_SECRET_KEY).ok_or("not logged in")?;
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let timestamp = (Date::now() / 1000.0) as i64;
+    // unique inside the replay window, it needs not be secret
+    let nonce = format!("{:x}-{:x}", Date::now() as u64, (Math::random() * 9007199254740992.0) as u64);
+    let canonical = sign_rs::canonical_request(method, path, query, body, timestamp, &nonce);
+
+    let headers = request.headers();
+    headers.set("Authorization", &format!("Bearer {}", token))?;
+    headers.set(SIGN_HEADER, &sign_rs::sign(secret.as_bytes(), &canonical))?;
+    headers.set(SIGN_CLIENT_HEADER, &client)?;
+    headers.set(SIGN_TIMESTAMP_HEADER, &timestamp.to_string())?;
+    headers.set(SIGN_NONCE_HEADER, &nonce)
+}
+
+/// Forgets the token and its signing key, for responses with 401 once they expired.
+pub fn forget() {
+    if let Ok(storage) = storage() {
+        for key in [TOKEN_KEY, SIGN_CLIENT_KEY, SIGN_SECRET_KEY] {
+            let _ = storage.remove_item(key);
+        }
+    }
+}
+
+fn token() -> Option<String> {
+    item(TOKEN_KEY)
+}
+
+fn item(key: &str) -> Option<String> {
+    storage().ok()?.get_item(key).ok()?
+}
+
+fn storage() -> Result<Storage, JsValue> {
+    let window = web_sys::window().ok_or("no window exists")?;
+    window.session_storage()?.ok_or_else(|| JsValue::from_str("no session storage"))
+}
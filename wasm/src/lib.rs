@@ -22,6 +22,7 @@ pub fn greet(_name: &str) {
     alert("Hello, wasm-client!");
 }
 
+pub mod auth;
 pub mod errors;
 pub mod models;
 
@@ -39,7 +40,13 @@ pub async fn main() -> Result<(), JsValue> {
         .get_element_by_id("left-tbody")
         .expect("left div not exists");
 
-    let courses: Vec<Course> = get_courses_by_teacher().await.unwrap();
+    if !auth::logged_in() {
+        let username = window.prompt_with_message("username")?.unwrap_or_default();
+        let password = window.prompt_with_message("password")?.unwrap_or_default();
+        auth::login(username, password).await?;
+    }
+    // add_course creates the courses of teacher 1
+    let courses: Vec<Course> = get_courses_by_teacher(1).await.unwrap();
     for c in courses.iter() {
         let tr = document.create_element("tr")?;
         tr.set_attribute("id", format!("tr-{}", c.id).as_str())?;
@@ -1,3 +1,4 @@
+use super::super::auth;
 use super::super::errors::MyError;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
@@ -24,27 +25,42 @@ pub struct Course {
     pub level: Option<String>,
 }
 
-pub async fn get_courses_by_teacher() -> Result<Vec<Course>, MyError> {
+/// One page of `GET /app/courses` and `GET /app/teachers/{id}/courses`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CoursePage {
+    pub total: i64,
+    pub next_cursor: Option<String>,
+    pub items: Vec<Course>,
+}
+
+/// First page of the courses of `teacher_id`, filtered by the server.
+pub async fn get_courses_by_teacher(teacher_id: i32) -> Result<Vec<Course>, MyError> {
     // 访问webservice 读取课程
     let mut opts = RequestInit::new();
     opts.method("GET");
     opts.mode(RequestMode::Cors); // 跨域
 
-    let url = format!("http://{}/app/courses", "127.0.0.1:8088");
+    let path = format!("/app/teachers/{}/courses", teacher_id);
+    let url = format!("http://{}{}", "127.0.0.1:8088", path);
 
     let request = Request::new_with_str_and_init(&url, &opts)?;
     request.headers().set("Accept", "application/json")?;
-    request.headers().set("sign", "123")?;
+    auth::authorize(&request, "GET", &path, b"")?;
 
     let window = web_sys::window().ok_or("no window exists".to_string())?;
     let resp_value = JsFuture::from(window.fetch_with_request(&request)).await?;
     assert!(resp_value.is_instance_of::<Response>());
     let resp: Response = resp_value.dyn_into().unwrap();
+    if resp.status() == 401 {
+        // expired, the next page load logs in again
+        auth::forget();
+        return Err(MyError::SomeError("not logged in".to_string()));
+    }
     let json = JsFuture::from(resp.json()?).await?;
     // let courses: Vec<Course> = json.into_serde().unwrap();
-    let courses: Vec<Course> = serde_wasm_bindgen::from_value(json).unwrap();
+    let page: CoursePage = serde_wasm_bindgen::from_value(json).unwrap();
 
-    Ok(courses)
+    Ok(page.items)
 }
 
 pub async fn delete_course(course_id: String) -> () {
@@ -52,12 +68,13 @@ pub async fn delete_course(course_id: String) -> () {
     opts.method("DELETE");
     opts.mode(RequestMode::Cors);
 
-    let url = format!("http://{}/app/courses/{}", "127.0.0.1:8088", course_id);
+    let path = format!("/app/courses/{}", course_id);
+    let url = format!("http://{}{}", "127.0.0.1:8088", path);
 
     let request = Request::new_with_str_and_init(&url, &opts).unwrap();
     request.headers().set("Accept", "application/json").unwrap();
     request.headers().set("Content-Type", "application/json").unwrap();
-    request.headers().set("sign", "123").unwrap();
+    auth::authorize(&request, "DELETE", &path, b"").unwrap();
     let window = web_sys::window().unwrap();
     let resp_value = JsFuture::from(window.fetch_with_request(&request))
         .await
@@ -87,12 +104,13 @@ pub async fn add_course(name: String, description: String) -> Result<Promise, Js
         name, description
     );
     opts.body(Some(&JsValue::from_str(str_json.as_str())));
-    let url = format!("http://{}/app/courses", "127.0.0.1:8088");
+    let path = "/app/courses";
+    let url = format!("http://{}{}", "127.0.0.1:8088", path);
 
     let request = Request::new_with_str_and_init(&url, &opts)?;
     request.headers().set("Content-Type", "application/json")?;
     request.headers().set("Accept", "application/json")?;
-    request.headers().set("sign", "123")?;
+    auth::authorize(&request, "POST", path, str_json.as_bytes())?;
 
     let window = web_sys::window().ok_or("no window exists".to_string())?;
     let resp_value = JsFuture::from(window.fetch_with_request(&request))